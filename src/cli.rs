@@ -1,9 +1,38 @@
+#[cfg(windows)]
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand, command};
+use clap::{Args, Parser, Subcommand};
+use regex::Regex;
 use tracing_appender::rolling::Rotation;
 
-use crate::pkg::service::SERVICE_DESCRIPTION_PREFIX;
+use crate::pkg::control::{ControlAction, ControlMapping, ControlTable};
+#[cfg(windows)]
+use crate::pkg::duration::format_duration;
+use crate::pkg::duration::parse_duration;
+use crate::pkg::exit_action::{ExitAction, ExitActionTable, ExitMapping};
+use crate::pkg::group::{GroupMode, ProcessSetting, ProcessSpec, build_group, read_procfile};
+use crate::pkg::hooks::Hooks;
+#[cfg(windows)]
+use crate::pkg::limits::format_size;
+use crate::pkg::limits::{PriorityClass, ResourceLimits, parse_size};
+use crate::pkg::liveness::{Probe, ProbeConfig};
+use crate::pkg::output_trigger::OutputTrigger;
+use crate::pkg::readiness::ReadinessCheck;
+use crate::pkg::recovery::FailureAction;
+#[cfg(windows)]
+use crate::pkg::recovery::Recovery;
+use crate::pkg::restart::{Backoff, RestartPolicy};
+use crate::pkg::runner::RunOptions;
+use crate::pkg::schedule::CronSchedule;
+use crate::pkg::shell::Shell;
+use crate::pkg::stop::StopMethod;
+use crate::pkg::thresholds::Thresholds;
+
+pub const SERVICE_DESCRIPTION_PREFIX: &str = "wsw";
 
 #[derive(Debug, Clone)]
 pub enum LogRotation {
@@ -13,13 +42,13 @@ pub enum LogRotation {
     Never,
 }
 
-impl ToString for LogRotation {
-    fn to_string(&self) -> String {
+impl fmt::Display for LogRotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            LogRotation::Minutely => "minutely".to_string(),
-            LogRotation::Hourly => "hourly".to_string(),
-            LogRotation::Daily => "daily".to_string(),
-            LogRotation::Never => "never".to_string(),
+            LogRotation::Minutely => write!(f, "minutely"),
+            LogRotation::Hourly => write!(f, "hourly"),
+            LogRotation::Daily => write!(f, "daily"),
+            LogRotation::Never => write!(f, "never"),
         }
     }
 }
@@ -38,6 +67,44 @@ impl FromStr for LogRotation {
     }
 }
 
+/// When Windows starts the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartType {
+    /// At boot
+    Auto,
+    /// Shortly after boot, once the other auto start services are running
+    Delayed,
+    /// Only when asked to
+    Manual,
+    /// Never
+    Disabled,
+}
+
+impl fmt::Display for StartType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartType::Auto => write!(f, "auto"),
+            StartType::Delayed => write!(f, "delayed"),
+            StartType::Manual => write!(f, "manual"),
+            StartType::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+impl FromStr for StartType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(StartType::Auto),
+            "delayed" => Ok(StartType::Delayed),
+            "manual" => Ok(StartType::Manual),
+            "disabled" => Ok(StartType::Disabled),
+            _ => Err(format!("Invalid start type: {}", s)),
+        }
+    }
+}
+
 impl From<LogRotation> for Rotation {
     fn from(lr: LogRotation) -> Self {
         match lr {
@@ -49,6 +116,643 @@ impl From<LogRotation> for Rotation {
     }
 }
 
+/// Options that control how the wrapped process is supervised.
+/// They are shared between the "install" and "run" subcommands: "install"
+/// forwards them to the service command line using `to_launch_arguments`.
+#[derive(Args, Debug, Clone)]
+pub struct SupervisorArgs {
+    /// When the wrapped process should be restarted after it exits
+    /// * always
+    /// * on-failure: only if it exits with a non zero exit code
+    /// * never
+    #[arg(long, default_value_t = RestartPolicy::Always)]
+    pub restart: RestartPolicy,
+
+    /// Delay before the first restart (e.g. 500ms, 1s, 2m)
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    pub restart_delay: Duration,
+
+    /// Upper bound for the restart delay
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    pub restart_max_delay: Duration,
+
+    /// Factor applied to the restart delay on every consecutive restart
+    #[arg(long, default_value_t = 2.0)]
+    pub restart_multiplier: f64,
+
+    /// Random spread applied to the restart delay, as a fraction of it (0.1 = ±10%)
+    #[arg(long, default_value_t = 0.1)]
+    pub restart_jitter: f64,
+
+    /// Minimum uptime after which the wrapped process is considered healthy
+    /// and the restart delay goes back to its initial value
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub restart_reset_after: Duration,
+
+    /// Stop the service if the wrapped process is restarted more than this
+    /// many times within "restart-window". 0 means no limit
+    #[arg(long, default_value_t = 0)]
+    pub max_restarts: u32,
+
+    /// Time window used to count restarts for "max-restarts"
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    pub restart_window: Duration,
+
+    /// Wait this long after the service starts before launching the wrapped process
+    #[arg(long, default_value = "0s", value_parser = parse_duration)]
+    pub start_delay: Duration,
+
+    /// Random extra delay, up to this long, added to "start-delay".
+    /// Spreads the start of services installed with the same options
+    #[arg(long, default_value = "0s", value_parser = parse_duration)]
+    pub start_jitter: Duration,
+
+    /// How the wrapped process is asked to exit when the service stops
+    /// * ctrl-break: send CTRL_BREAK to the process group
+    /// * ctrl-c: send CTRL_C to the process console
+    /// * stdin: write "stop-line" to the process stdin
+    /// * kill: kill the process tree straight away
+    #[arg(long, default_value_t = StopMethod::CtrlBreak)]
+    pub stop_method: StopMethod,
+
+    /// How the command line is launched
+    /// * cmd: through cmd.exe /C, which expands %VAR% and handles &, | and >
+    /// * none: the executable is started directly with the parsed arguments
+    /// * powershell, pwsh: through PowerShell -Command
+    /// * sh: through sh -c
+    #[arg(long, default_value_t = Shell::default())]
+    pub shell: Shell,
+
+    /// Line written to the wrapped process stdin when "stop-method" is "stdin"
+    #[arg(long, required_if_eq("stop_method", "stdin"))]
+    pub stop_line: Option<String>,
+
+    /// How long to wait for the wrapped process to exit before
+    /// killing its whole process tree
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub stop_timeout: Duration,
+
+    /// How long Windows waits for the service to stop when the system shuts down.
+    /// During a shutdown the wrapped process is killed before this expires,
+    /// even if "stop-timeout" is longer
+    #[arg(long, default_value = "3m", value_parser = parse_duration)]
+    pub preshutdown_timeout: Duration,
+
+    /// Report the service as running only once the wrapped process is ready.
+    /// Until then the service stays in the "start pending" state
+    /// * tcp:host:port: the port accepts connections
+    /// * http://host:port/path: the url answers with a 2xx status
+    /// * output:regex: a line of the captured output matches the regex
+    #[arg(long)]
+    pub ready: Option<ReadinessCheck>,
+
+    /// Let the wrapped process report its state with systemd style notifications
+    /// ("READY=1", "WATCHDOG=1", "STATUS=...", "STOPPING=1"), written as messages
+    /// to the local named pipe found in its NOTIFY_SOCKET environment variable.
+    /// The service is reported as running once "READY=1" is received
+    #[arg(long, default_value_t = false, conflicts_with = "ready")]
+    pub notify: bool,
+
+    /// Restart the wrapped process when it sends no "WATCHDOG=1" for this long.
+    /// The timeout is given to it in the WATCHDOG_USEC environment variable
+    #[arg(long, value_parser = parse_duration, requires = "notify")]
+    pub watchdog_timeout: Option<Duration>,
+
+    /// How long to wait for the wrapped process to become ready
+    /// before failing the service start
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    pub ready_timeout: Duration,
+
+    /// Periodically probe the wrapped process and restart it when it stops responding
+    /// * tcp:host:port: the port accepts connections
+    /// * http://host:port/path: the url answers with "liveness-status" (any 2xx by default)
+    /// * exec:command: the command exits with a zero exit code
+    #[arg(long)]
+    pub liveness: Option<Probe>,
+
+    /// HTTP status expected from an http liveness probe
+    #[arg(long)]
+    pub liveness_status: Option<u16>,
+
+    /// Time between two liveness probes
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub liveness_interval: Duration,
+
+    /// How long a single liveness probe may take before it counts as a failure
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub liveness_timeout: Duration,
+
+    /// Consecutive liveness probe failures after which the wrapped process is restarted
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub liveness_threshold: u32,
+
+    /// Memory the whole process tree can commit, e.g. 512M or 2G.
+    /// Allocations beyond it fail. Windows only
+    #[arg(long, value_parser = parse_size)]
+    pub memory_limit: Option<u64>,
+
+    /// Memory any single process of the tree can commit, e.g. 512M or 2G
+    #[arg(long, value_parser = parse_size)]
+    pub process_memory_limit: Option<u64>,
+
+    /// Share of the total CPU time the process tree can use, in percent. Windows only
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=100))]
+    pub cpu_limit: Option<u32>,
+
+    /// Number of processes that can run at the same time in the process tree.
+    /// On Unix the limit applies to all the processes of the user
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_processes: Option<u32>,
+
+    /// Restart the wrapped process when its process tree uses more memory than this,
+    /// e.g. 800M, for "memory-threshold-duration"
+    #[arg(long, value_parser = parse_size)]
+    pub memory_threshold: Option<u64>,
+
+    /// How long the memory usage must stay above "memory-threshold"
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub memory_threshold_duration: Duration,
+
+    /// Restart the wrapped process when its process tree uses more CPU than this,
+    /// in percent of one core, for "cpu-threshold-duration"
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub cpu_threshold: Option<u32>,
+
+    /// How long the CPU usage must stay above "cpu-threshold"
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    pub cpu_threshold_duration: Duration,
+
+    /// Restart the wrapped process when it prints nothing for this long
+    #[arg(long, value_parser = parse_duration)]
+    pub silence_timeout: Option<Duration>,
+
+    /// Restart the wrapped process when a line of its output matches the regex,
+    /// e.g. "OutOfMemoryError". Can be repeated
+    #[arg(long = "restart-on-output", value_name = "REGEX")]
+    pub restart_on_output: Vec<Regex>,
+
+    /// Matching lines are ignored for this long after a match,
+    /// so that a burst of them only causes one restart
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    pub restart_on_output_cooldown: Duration,
+
+    /// Scheduling priority of the process tree
+    /// * idle
+    /// * below-normal
+    /// * normal
+    /// * above-normal
+    /// * high
+    #[arg(long)]
+    pub priority: Option<PriorityClass>,
+
+    /// Command run before the wrapped process is (re)started
+    #[arg(long)]
+    pub pre_start: Option<String>,
+
+    /// Command run after the wrapped process has been started
+    #[arg(long)]
+    pub post_start: Option<String>,
+
+    /// Command run before the wrapped process is asked to stop
+    #[arg(long)]
+    pub pre_stop: Option<String>,
+
+    /// Command run after the wrapped process has exited
+    #[arg(long)]
+    pub post_stop: Option<String>,
+
+    /// Command run when the wrapped process exits with a failure.
+    /// The exit code and the restart count are passed in the
+    /// WSW_EXIT_CODE and WSW_RESTART_COUNT environment variables
+    #[arg(long)]
+    pub on_failure: Option<String>,
+
+    /// How long a hook command may run before it is killed and considered failed
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub hook_timeout: Duration,
+
+    /// Stop the service if the pre-start or the post-start hook fails
+    #[arg(long)]
+    pub hook_failure_aborts_start: bool,
+
+    /// Gracefully restart the wrapped process on a cron style schedule,
+    /// in local time (e.g. "0 3 * * *" for every day at 3 AM)
+    #[arg(long)]
+    pub restart_schedule: Option<CronSchedule>,
+
+    /// Gracefully restart the wrapped process once it has been running
+    /// for this long (e.g. 12h)
+    #[arg(long, value_parser = parse_duration)]
+    pub max_runtime: Option<Duration>,
+
+    /// Gracefully restart the wrapped process when a watched file changes.
+    /// Accepts files, directories (watched recursively) and globs like "conf/**/*.toml".
+    /// Relative paths are resolved against the working directory.
+    /// Can be repeated
+    #[arg(long)]
+    pub watch: Vec<String>,
+
+    /// How long the watched files must stay unchanged before
+    /// the wrapped process is restarted
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub watch_debounce: Duration,
+
+    /// Bind a user defined service control code (133-255) to an action, as CODE=ACTION.
+    /// Actions: restart-child, reopen-logs, dump-status, ctrl-c, ctrl-break, stdin:LINE.
+    /// Can be repeated
+    #[arg(long = "control", value_name = "CODE=ACTION")]
+    pub controls: Vec<ControlMapping>,
+
+    /// What to do when the wrapped process exits with a given code, as CODE=ACTION.
+    /// Actions: restart, stop-service, ignore, restart-after:DELAY.
+    /// Overrides the restart policy. Can be repeated
+    #[arg(long = "on-exit", value_name = "CODE=ACTION")]
+    pub exit_actions: Vec<ExitMapping>,
+
+    /// Action for the exit codes without an "on-exit" mapping.
+    /// When not set, the restart policy decides
+    #[arg(long, value_name = "ACTION")]
+    pub on_exit_default: Option<ExitAction>,
+
+    /// Supervise a named command as part of a process group, as NAME=CMD.
+    /// Processes are started in the given order and stopped in the reverse one.
+    /// Can be repeated
+    #[arg(long = "process", value_name = "NAME=CMD")]
+    pub processes: Vec<ProcessSpec>,
+
+    /// Supervise the processes of a Procfile ("name: command" lines) as a process group.
+    /// They run in the Procfile directory and start before the "process" ones
+    #[arg(long)]
+    pub procfile: Option<String>,
+
+    /// Working directory of a process of the group, as NAME=DIR. Can be repeated
+    #[arg(long = "process-dir", value_name = "NAME=DIR")]
+    pub process_dirs: Vec<ProcessSetting<String>>,
+
+    /// Restart policy of a process of the group, as NAME=POLICY.
+    /// If not specified, "restart" is used. Can be repeated
+    #[arg(long = "process-restart", value_name = "NAME=POLICY")]
+    pub process_restarts: Vec<ProcessSetting<RestartPolicy>>,
+
+    /// How the processes of the group are restarted:
+    /// * independent: every process is restarted on its own
+    /// * restart-all: when a process exits, the whole group is restarted
+    #[arg(long, default_value_t = GroupMode::Independent)]
+    pub group_mode: GroupMode,
+}
+
+impl SupervisorArgs {
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            initial: self.restart_delay,
+            max: self.restart_max_delay,
+            multiplier: self.restart_multiplier,
+            jitter: self.restart_jitter,
+        }
+    }
+
+    pub fn run_options(&self, working_dir: Option<String>, disable_logs: bool) -> RunOptions {
+        RunOptions {
+            working_dir,
+            disable_logs,
+            pipe_stdin: self.stop_method == StopMethod::Stdin || self.control_table().needs_stdin(),
+            new_process_group: self.stop_method == StopMethod::CtrlBreak,
+            output_observer: None,
+            env: Vec::new(),
+            log_tag: None,
+            limits: self.resource_limits(),
+            shell: self.shell,
+        }
+    }
+
+    pub fn output_trigger(&self) -> Option<OutputTrigger> {
+        if self.restart_on_output.is_empty() {
+            return None;
+        }
+        Some(OutputTrigger::new(
+            self.restart_on_output.clone(),
+            self.restart_on_output_cooldown,
+        ))
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            memory: self
+                .memory_threshold
+                .map(|limit| (limit, self.memory_threshold_duration)),
+            cpu: self
+                .cpu_threshold
+                .map(|limit| (limit, self.cpu_threshold_duration)),
+            silence: self.silence_timeout,
+        }
+    }
+
+    pub fn resource_limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            job_memory: self.memory_limit,
+            process_memory: self.process_memory_limit,
+            cpu_rate: self.cpu_limit,
+            active_processes: self.max_processes,
+            priority: self.priority,
+        }
+    }
+
+    pub fn control_table(&self) -> ControlTable {
+        ControlTable::new(&self.controls)
+    }
+
+    /// Returns the processes of the group, empty when a single command is supervised
+    pub fn process_group(&self) -> Result<Vec<ProcessSpec>, String> {
+        let mut specs = match &self.procfile {
+            Some(path) => read_procfile(Path::new(path))?,
+            None => Vec::new(),
+        };
+        specs.extend(self.processes.iter().cloned());
+        if !specs.is_empty()
+            && let Some(option) = self.single_process_option()
+        {
+            return Err(format!(
+                "--{} is not supported with a process group",
+                option
+            ));
+        }
+        build_group(specs, &self.process_dirs, &self.process_restarts)
+    }
+
+    /// Returns the first option set that only applies to a single wrapped process
+    fn single_process_option(&self) -> Option<&'static str> {
+        [
+            ("ready", self.ready.is_some()),
+            ("notify", self.notify),
+            ("liveness", self.liveness.is_some()),
+            ("pre-start", self.pre_start.is_some()),
+            ("post-start", self.post_start.is_some()),
+            ("pre-stop", self.pre_stop.is_some()),
+            ("post-stop", self.post_stop.is_some()),
+            ("on-failure", self.on_failure.is_some()),
+            ("restart-schedule", self.restart_schedule.is_some()),
+            ("max-runtime", self.max_runtime.is_some()),
+            ("watch", !self.watch.is_empty()),
+            ("on-exit", !self.exit_actions.is_empty()),
+            ("on-exit-default", self.on_exit_default.is_some()),
+            ("memory-threshold", self.memory_threshold.is_some()),
+            ("cpu-threshold", self.cpu_threshold.is_some()),
+            ("silence-timeout", self.silence_timeout.is_some()),
+            ("restart-on-output", !self.restart_on_output.is_empty()),
+        ]
+        .into_iter()
+        .find(|(_, set)| *set)
+        .map(|(option, _)| option)
+    }
+
+    pub fn exit_action_table(&self) -> ExitActionTable {
+        ExitActionTable::new(&self.exit_actions, self.on_exit_default)
+    }
+
+    pub fn hooks(&self) -> Hooks {
+        Hooks {
+            pre_start: self.pre_start.clone(),
+            post_start: self.post_start.clone(),
+            pre_stop: self.pre_stop.clone(),
+            post_stop: self.post_stop.clone(),
+            on_failure: self.on_failure.clone(),
+            timeout: self.hook_timeout,
+        }
+    }
+
+    pub fn liveness_config(&self) -> Option<ProbeConfig> {
+        self.liveness.clone().map(|probe| ProbeConfig {
+            probe,
+            expected_status: self.liveness_status,
+            interval: self.liveness_interval,
+            timeout: self.liveness_timeout,
+            threshold: self.liveness_threshold,
+            shell: self.shell,
+        })
+    }
+
+    /// Converts the options back to command line arguments
+    #[cfg(windows)]
+    pub fn to_launch_arguments(&self) -> Vec<OsString> {
+        let mut args = vec![
+            OsString::from("--restart"),
+            OsString::from(self.restart.to_string()),
+            OsString::from("--restart-delay"),
+            OsString::from(format_duration(self.restart_delay)),
+            OsString::from("--restart-max-delay"),
+            OsString::from(format_duration(self.restart_max_delay)),
+            OsString::from("--restart-multiplier"),
+            OsString::from(self.restart_multiplier.to_string()),
+            OsString::from("--restart-jitter"),
+            OsString::from(self.restart_jitter.to_string()),
+            OsString::from("--restart-reset-after"),
+            OsString::from(format_duration(self.restart_reset_after)),
+            OsString::from("--max-restarts"),
+            OsString::from(self.max_restarts.to_string()),
+            OsString::from("--restart-window"),
+            OsString::from(format_duration(self.restart_window)),
+            OsString::from("--start-delay"),
+            OsString::from(format_duration(self.start_delay)),
+            OsString::from("--start-jitter"),
+            OsString::from(format_duration(self.start_jitter)),
+            OsString::from("--stop-method"),
+            OsString::from(self.stop_method.to_string()),
+            OsString::from("--shell"),
+            OsString::from(self.shell.to_string()),
+            OsString::from("--stop-timeout"),
+            OsString::from(format_duration(self.stop_timeout)),
+            OsString::from("--preshutdown-timeout"),
+            OsString::from(format_duration(self.preshutdown_timeout)),
+            OsString::from("--ready-timeout"),
+            OsString::from(format_duration(self.ready_timeout)),
+            OsString::from("--liveness-interval"),
+            OsString::from(format_duration(self.liveness_interval)),
+            OsString::from("--liveness-timeout"),
+            OsString::from(format_duration(self.liveness_timeout)),
+            OsString::from("--liveness-threshold"),
+            OsString::from(self.liveness_threshold.to_string()),
+            OsString::from("--memory-threshold-duration"),
+            OsString::from(format_duration(self.memory_threshold_duration)),
+            OsString::from("--cpu-threshold-duration"),
+            OsString::from(format_duration(self.cpu_threshold_duration)),
+            OsString::from("--restart-on-output-cooldown"),
+            OsString::from(format_duration(self.restart_on_output_cooldown)),
+            OsString::from("--hook-timeout"),
+            OsString::from(format_duration(self.hook_timeout)),
+            OsString::from("--watch-debounce"),
+            OsString::from(format_duration(self.watch_debounce)),
+            OsString::from("--group-mode"),
+            OsString::from(self.group_mode.to_string()),
+        ];
+        if let Some(line) = &self.stop_line {
+            args.push(OsString::from("--stop-line"));
+            args.push(OsString::from(line));
+        }
+        if let Some(check) = &self.ready {
+            args.push(OsString::from("--ready"));
+            args.push(OsString::from(check.to_string()));
+        }
+        if self.notify {
+            args.push(OsString::from("--notify"));
+        }
+        if let Some(timeout) = self.watchdog_timeout {
+            args.push(OsString::from("--watchdog-timeout"));
+            args.push(OsString::from(format_duration(timeout)));
+        }
+        for (arg, size) in [
+            ("--memory-limit", self.memory_limit),
+            ("--process-memory-limit", self.process_memory_limit),
+        ] {
+            if let Some(size) = size {
+                args.push(OsString::from(arg));
+                args.push(OsString::from(format_size(size)));
+            }
+        }
+        if let Some(size) = self.memory_threshold {
+            args.push(OsString::from("--memory-threshold"));
+            args.push(OsString::from(format_size(size)));
+        }
+        if let Some(timeout) = self.silence_timeout {
+            args.push(OsString::from("--silence-timeout"));
+            args.push(OsString::from(format_duration(timeout)));
+        }
+        for rule in &self.restart_on_output {
+            args.push(OsString::from("--restart-on-output"));
+            args.push(OsString::from(rule.as_str()));
+        }
+        for (arg, value) in [
+            ("--cpu-limit", self.cpu_limit),
+            ("--max-processes", self.max_processes),
+            ("--cpu-threshold", self.cpu_threshold),
+        ] {
+            if let Some(value) = value {
+                args.push(OsString::from(arg));
+                args.push(OsString::from(value.to_string()));
+            }
+        }
+        if let Some(priority) = self.priority {
+            args.push(OsString::from("--priority"));
+            args.push(OsString::from(priority.to_string()));
+        }
+        if let Some(probe) = &self.liveness {
+            args.push(OsString::from("--liveness"));
+            args.push(OsString::from(probe.to_string()));
+        }
+        if let Some(code) = self.liveness_status {
+            args.push(OsString::from("--liveness-status"));
+            args.push(OsString::from(code.to_string()));
+        }
+        for (arg, hook) in [
+            ("--pre-start", &self.pre_start),
+            ("--post-start", &self.post_start),
+            ("--pre-stop", &self.pre_stop),
+            ("--post-stop", &self.post_stop),
+            ("--on-failure", &self.on_failure),
+        ] {
+            if let Some(cmd) = hook {
+                args.push(OsString::from(arg));
+                args.push(OsString::from(cmd));
+            }
+        }
+        if self.hook_failure_aborts_start {
+            args.push(OsString::from("--hook-failure-aborts-start"));
+        }
+        if let Some(schedule) = &self.restart_schedule {
+            args.push(OsString::from("--restart-schedule"));
+            args.push(OsString::from(schedule.to_string()));
+        }
+        if let Some(max_runtime) = self.max_runtime {
+            args.push(OsString::from("--max-runtime"));
+            args.push(OsString::from(format_duration(max_runtime)));
+        }
+        for path in &self.watch {
+            args.push(OsString::from("--watch"));
+            args.push(OsString::from(path));
+        }
+        for mapping in &self.controls {
+            args.push(OsString::from("--control"));
+            args.push(OsString::from(mapping.to_string()));
+        }
+        for mapping in &self.exit_actions {
+            args.push(OsString::from("--on-exit"));
+            args.push(OsString::from(mapping.to_string()));
+        }
+        if let Some(action) = self.on_exit_default {
+            args.push(OsString::from("--on-exit-default"));
+            args.push(OsString::from(action.to_string()));
+        }
+        for spec in &self.processes {
+            args.push(OsString::from("--process"));
+            args.push(OsString::from(spec.to_string()));
+        }
+        if let Some(path) = &self.procfile {
+            args.push(OsString::from("--procfile"));
+            args.push(OsString::from(path));
+        }
+        for setting in &self.process_dirs {
+            args.push(OsString::from("--process-dir"));
+            args.push(OsString::from(setting.to_string()));
+        }
+        for setting in &self.process_restarts {
+            args.push(OsString::from("--process-restart"));
+            args.push(OsString::from(setting.to_string()));
+        }
+        args
+    }
+}
+
+/// SCM recovery options, taken by Windows when the service itself fails.
+/// They are written to the SCM by "install" and not forwarded to the service
+#[derive(Args, Debug, Clone)]
+pub struct RecoveryArgs {
+    /// What Windows does when the service fails, as ACTION[:DELAY]
+    /// * restart: start the service again
+    /// * run: run the failure command
+    /// * reboot: reboot the computer
+    /// * none: do nothing
+    ///
+    /// The first one applies to the first failure, the second one to the
+    /// second failure and the last one to all the following failures. Can be repeated
+    #[arg(long = "failure-action", value_name = "ACTION[:DELAY]")]
+    pub failure_actions: Vec<FailureAction>,
+
+    /// Reset the failure count after this long without failure.
+    /// If not specified, the count is never reset
+    #[arg(long, value_parser = parse_duration, requires = "failure_actions")]
+    pub failure_reset_period: Option<Duration>,
+
+    /// Command line run by the "run" failure action
+    #[arg(long, requires = "failure_actions")]
+    pub failure_command: Option<String>,
+
+    /// Message broadcast to the users before the "reboot" failure action
+    #[arg(long, requires = "failure_actions")]
+    pub failure_reboot_message: Option<String>,
+
+    /// Also take the failure actions when the service stops with an error
+    /// exit code, for instance after a crash loop, and not only when it crashes
+    #[arg(long, default_value_t = false, requires = "failure_actions")]
+    pub failure_actions_on_non_crash: bool,
+}
+
+impl RecoveryArgs {
+    /// Returns the recovery configuration, None when no failure action is given
+    #[cfg(windows)]
+    pub fn recovery(&self) -> Result<Option<Recovery>, String> {
+        if self.failure_actions.is_empty() {
+            return Ok(None);
+        }
+        Recovery::new(
+            self.failure_actions.clone(),
+            self.failure_reset_period,
+            self.failure_command.clone(),
+            self.failure_reboot_message.clone(),
+            self.failure_actions_on_non_crash,
+        )
+        .map(Some)
+    }
+}
+
 #[derive(Parser)]
 #[command(
     name = "WSW",
@@ -60,6 +764,74 @@ pub struct Cli {
     pub command: Option<Commands>,
 }
 
+/// Options of the "install" subcommand
+#[derive(Args, Debug, Clone)]
+pub struct InstallArgs {
+    /// Path and args for the executable to run as a service.
+    /// Not needed when a process group is given with "process" or "procfile"
+    #[arg(
+        long,
+        short,
+        required_unless_present_any = ["processes", "procfile"],
+        conflicts_with_all = ["processes", "procfile"]
+    )]
+    pub cmd: Option<String>,
+    /// Service working directory
+    /// If not specified, the target directory of the executable (cmd arg) will be used
+    #[arg(long)]
+    pub working_dir: Option<String>,
+    /// Name of the service to install
+    #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
+    pub name: String,
+    /// If set to true, wrapped application logs will not be captured.
+    /// This means that following call to the "logs" subcommand will not
+    /// display any output regarding the wrapped app. This is useful in scenarios
+    /// where logs full managed from the wrapped application already.
+    #[arg(long, short, default_value_t = false)]
+    pub disable_logs: bool,
+
+    /// Set the log rotation policy
+    /// * daily
+    /// * hourly
+    /// * minutely
+    /// * never
+    #[arg(long, short, default_value_t = LogRotation::Daily)]
+    pub log_rotation: LogRotation,
+
+    /// How many log files to keep
+    /// This is only used if the log rotation policy is set to something other than "never"
+    #[arg(long, short, default_value_t = 30)]
+    pub max_log_files: usize,
+
+    /// Run the service using specified account_name.
+    /// If the user is local put it in the format .\username
+    #[arg(long, requires = "account_password")]
+    pub account_name: Option<String>,
+
+    /// Run the service using specified account_password
+    #[arg(long, requires = "account_name")]
+    pub account_password: Option<String>,
+
+    /// Service that must be running before this one is started.
+    /// Can be repeated
+    #[arg(long = "depends-on", value_name = "SERVICE")]
+    pub depends_on: Vec<String>,
+
+    /// When Windows starts the service
+    /// * auto: at boot
+    /// * delayed: shortly after boot
+    /// * manual: only when asked to
+    /// * disabled: never. The service is not started after the install either
+    #[arg(long, default_value_t = StartType::Auto)]
+    pub start_type: StartType,
+
+    #[command(flatten)]
+    pub recovery: RecoveryArgs,
+
+    #[command(flatten)]
+    pub supervisor: SupervisorArgs,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Show the logs of the Windows service managed from 'wsw'
@@ -92,6 +864,9 @@ pub enum Commands {
         /// Name of the service to start
         #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
         name: String,
+        /// Stop the running services that depend on this one first
+        #[arg(long, default_value_t = false)]
+        cascade: bool,
     },
     /// Print a service status
     #[command()]
@@ -100,6 +875,43 @@ pub enum Commands {
         #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
         name: String,
     },
+    /// Pause a service, freezing the wrapped process tree
+    #[command()]
+    Pause {
+        /// Name of the service to pause
+        #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
+        name: String,
+    },
+    /// Resume a paused service
+    #[command()]
+    Resume {
+        /// Name of the service to resume
+        #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
+        name: String,
+    },
+    /// Send a user defined control code to a service
+    #[command()]
+    Control {
+        /// Name of the service to control
+        #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
+        name: String,
+        /// Built-in action to trigger: restart-child, reopen-logs, dump-status, ctrl-c or ctrl-break
+        #[arg(long, required_unless_present = "code", conflicts_with = "code")]
+        action: Option<ControlAction>,
+        /// Control code to send, as bound with "--control" at install time
+        #[arg(long, value_parser = clap::value_parser!(u32).range(128..=255))]
+        code: Option<u32>,
+    },
+    /// Change when Windows starts a service
+    #[command()]
+    SetStartType {
+        /// Name of the service to change
+        #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
+        name: String,
+        /// auto, delayed, manual or disabled
+        #[arg(long)]
+        start_type: StartType,
+    },
     /// Restart a service
     #[command()]
     Restart {
@@ -109,60 +921,30 @@ pub enum Commands {
     },
     /// Install and start the Windows service
     #[command(visible_alias = "i")]
-    Install {
-        /// Path and args for the executable to run as a service
-        #[arg(long, short)]
-        cmd: String,
-        /// Service working directory
-        /// If not specified, the target directory of the executable (cmd arg) will be used
-        #[arg(long)]
-        working_dir: Option<String>,
-        /// Name of the service to install
-        #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
-        name: String,
-        /// If set to true, wrapped application logs will not be captured.
-        /// This means that following call to the "logs" subcommand will not
-        /// display any output regarding the wrapped app. This is useful in scenarios
-        /// where logs full managed from the wrapped application already.
-        #[arg(long, short, default_value_t = false)]
-        disable_logs: bool,
-
-        /// Set the log rotation policy
-        /// * daily
-        /// * hourly
-        /// * minutely
-        /// * never
-        #[arg(long, short, default_value_t = LogRotation::Daily)]
-        log_rotation: LogRotation,
-
-        /// How many log files to keep
-        /// This is only used if the log rotation policy is set to something other than "never"
-        #[arg(long, short, default_value_t = 30)]
-        max_log_files: usize,
-
-        /// Run the service using specified account_name.
-        /// If the user is local put it in the format .\username
-        #[arg(long, requires = "account_password")]
-        account_name: Option<String>,
-
-        /// Run the service using specified account_password
-        #[arg(long, requires = "account_name")]
-        account_password: Option<String>
-    },
+    Install(InstallArgs),
     /// Stop and uninstall the Windows service
     #[command(visible_alias = "u")]
     Uninstall {
         /// Name of the service to uninstall
         #[arg(long, short, default_value_t = String::from(SERVICE_DESCRIPTION_PREFIX))]
         name: String,
+        /// Uninstall the service even if other services depend on it.
+        /// The running ones are stopped first
+        #[arg(long, default_value_t = false)]
+        force: bool,
     },
     /// Run in service mode (called by the system or for debugging)
     /// This command is not intended to be called directly from the command line
     #[command(hide = true)]
     Run {
         /// Path and args for the executable to run
-        #[arg(long, short)]
-        cmd: String,
+        #[arg(
+            long,
+            short,
+            required_unless_present_any = ["processes", "procfile"],
+            conflicts_with_all = ["processes", "procfile"]
+        )]
+        cmd: Option<String>,
         /// Service working directory
         /// If not specified, the target directory of the executable (cmd arg) will be used
         #[arg(long)]
@@ -188,5 +970,8 @@ pub enum Commands {
         /// This is only used if the log rotation policy is set to something other than "never"
         #[arg(long, short, default_value_t = 30)]
         max_log_files: usize,
+
+        #[command(flatten)]
+        supervisor: SupervisorArgs,
     },
 }
@@ -0,0 +1,31 @@
+use windows_service::service::UserEventCode;
+
+use crate::commands::pause::handle_pause_error;
+use crate::pkg::control::{ControlAction, builtin_code};
+use crate::pkg::service::send_control_code;
+
+pub fn handle(name: &str, action: Option<ControlAction>, code: Option<u32>) {
+    let code = match (code, &action) {
+        (Some(code), _) => code,
+        (None, Some(action)) => match builtin_code(action) {
+            Some(code) => code,
+            None => {
+                eprintln!(
+                    "Action '{}' has no built-in control code: bind it with --control at install time and use --code.",
+                    action
+                );
+                return;
+            }
+        },
+        (None, None) => unreachable!("clap requires either --action or --code"),
+    };
+    let Ok(event) = UserEventCode::from_raw(code) else {
+        eprintln!("Invalid control code: {}", code);
+        return;
+    };
+
+    match send_control_code(name, event) {
+        Ok(_) => println!("Control code {} sent to service '{}'.", code, name),
+        Err(e) => handle_pause_error(e, name, "control"),
+    }
+}
@@ -1,36 +1,70 @@
-use crate::{cli::LogRotation, pkg::service::install_service};
-use windows_service::Error;
-use windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED;
-
-pub fn handle(
-    cmd: &str,
-    working_dir: Option<String>,
-    name: &str,
-    disable_logs: bool,
-    log_rotation: LogRotation,
-    max_log_files: usize,
-    account_name: Option<String>,
-    account_password: Option<String>
-) {
-    match install_service(
-        &name,
-        working_dir,
-        &cmd,
-        disable_logs,
-        log_rotation,
-        max_log_files,
-        account_name,
-        account_password
-    ) {
-        Ok(_) => println!("Service '{}' installed successfully.", name),
-        Err(Error::Winapi(e)) => match e.raw_os_error() {
-            Some(code) if code as u32 == ERROR_ACCESS_DENIED => {
-                eprintln!("Access denied — run as Administrator or add the privilege.");
-            }
-            _ => {
-                eprintln!("Failed to install the service '{}': {:?}", name, e);
-            }
-        },
-        Err(e) => eprintln!("Failed to install service '{}': {}", name, e),
-    }
-}
+use crate::{
+    cli::InstallArgs,
+    pkg::service::{dependency_graph, get_service_status, install_service, set_recovery},
+};
+use windows_service::Error;
+use windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED;
+
+/// Validates the dependencies before anything is written to the SCM
+fn check_dependencies(name: &str, depends_on: &[String]) -> Result<(), String> {
+    for dependency in depends_on {
+        if get_service_status(dependency).is_err() {
+            return Err(format!("service '{}' is not installed", dependency));
+        }
+    }
+    let mut graph =
+        dependency_graph().map_err(|e| format!("failed to read the installed services: {}", e))?;
+    graph.add(name, depends_on);
+    graph.start_order().map(|_| ())
+}
+
+pub fn handle(mut args: InstallArgs) {
+    let name = args.name.as_str();
+    // the service does not run from the current directory
+    if let Some(path) = &args.supervisor.procfile
+        && let Ok(path) = std::path::absolute(path)
+    {
+        args.supervisor.procfile = Some(path.to_string_lossy().to_string());
+    }
+    if let Err(e) = args.supervisor.process_group() {
+        eprintln!("Invalid process group: {}", e);
+        return;
+    }
+    if !args.depends_on.is_empty()
+        && let Err(e) = check_dependencies(name, &args.depends_on)
+    {
+        eprintln!("Invalid dependencies: {}", e);
+        return;
+    }
+    let recovery = match args.recovery.recovery() {
+        Ok(recovery) => recovery,
+        Err(e) => {
+            eprintln!("Invalid failure actions: {}", e);
+            return;
+        }
+    };
+
+    match install_service(&args) {
+        Ok(_) => {
+            println!("Service '{}' installed successfully.", name);
+            // the service is already installed, so a failure here is only reported
+            if let Some(recovery) = recovery
+                && let Err(e) = set_recovery(name, &recovery)
+            {
+                eprintln!(
+                    "Failed to configure the failure actions of the service '{}': {}",
+                    name, e
+                );
+            }
+        }
+        Err(Error::Winapi(e)) => match e.raw_os_error() {
+            Some(code) if code as u32 == ERROR_ACCESS_DENIED => {
+                eprintln!("Access denied — run as Administrator or add the privilege.");
+            }
+            _ => {
+                eprintln!("Failed to install the service '{}': {:?}", name, e);
+            }
+        },
+        Err(e) => eprintln!("Failed to install service '{}': {}", name, e),
+    }
+}
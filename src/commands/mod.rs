@@ -1,8 +1,12 @@
+pub mod control;
 pub mod install;
 pub mod list;
 pub mod logs;
+pub mod pause;
 pub mod restart;
+pub mod resume;
 pub mod run;
+pub mod set_start_type;
 pub mod start;
 pub mod status;
 pub mod stop;
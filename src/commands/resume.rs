@@ -0,0 +1,20 @@
+use windows_service::service::ServiceState;
+
+use crate::commands::pause::handle_pause_error;
+use crate::pkg::service::{resume_service, wait_for_service_status};
+
+pub fn handle(name: &str) {
+    match resume_service(name) {
+        Ok(_) => {
+            match wait_for_service_status(
+                name,
+                ServiceState::Running,
+                std::time::Duration::from_secs(10),
+            ) {
+                Ok(_) => println!("Service '{}' is now running.", name),
+                Err(e) => eprintln!("Failed to wait for service '{}': {}", name, e),
+            }
+        }
+        Err(e) => handle_pause_error(e, name, "resume"),
+    }
+}
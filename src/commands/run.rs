@@ -1,31 +1,42 @@
-use windows::Win32::Foundation::CloseHandle;
 use windows_service::{define_windows_service, service_dispatcher};
 
 use crate::{
     cli::LogRotation,
-    pkg::{logs::setup_logging, runner::run_command, service::service_main},
+    pkg::{
+        logs::{flush_logs, keep_log_guard, setup_logging},
+        runner::{RunOptions, run_command},
+        service::service_main,
+        shell::Shell,
+    },
 };
 
 pub fn handle(
-    cmd: &str,
+    cmd: Option<&str>,
     working_dir: Option<String>,
     name: &str,
     disable_logs: bool,
     log_rotation: LogRotation,
     max_log_files: usize,
+    shell: Shell,
 ) {
     define_windows_service!(ffi_service_main, service_main);
-    let _guard = setup_logging(&name, log_rotation, max_log_files);
+    keep_log_guard(setup_logging(&name, log_rotation, max_log_files));
     if let Err(_e) = service_dispatcher::start(name, ffi_service_main) {
-        match run_command(&cmd, working_dir, disable_logs) {
+        let Some(cmd) = cmd else {
+            tracing::error!("Process groups can only run as a service");
+            flush_logs();
+            return;
+        };
+        let options = RunOptions {
+            working_dir,
+            disable_logs,
+            shell,
+            ..Default::default()
+        };
+        match run_command(&cmd, options) {
             Ok(mut child) => {
-                if let Err(e) = child.1.wait() {
-                tracing::error!("Failed to wait for child process: {}", e);
-                }
-                unsafe {
-                    if let Err(e) = CloseHandle(std::mem::transmute(child.0)) {
-                        tracing::error!("Failed to close handle: {:?}", e);
-                    }
+                if let Err(e) = child.wait() {
+                    tracing::error!("Failed to wait for child process: {}", e);
                 }
             }
             Err(e) => {
@@ -33,4 +44,5 @@ pub fn handle(
             }
         }
     }
+    flush_logs();
 }
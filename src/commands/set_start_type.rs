@@ -0,0 +1,23 @@
+use windows_service::Error;
+use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SERVICE_DOES_NOT_EXIST};
+
+use crate::cli::StartType;
+use crate::pkg::service::set_start_type;
+
+pub fn handle(name: &str, start_type: StartType) {
+    match set_start_type(name, start_type) {
+        Ok(_) => println!("Service '{}' start type set to {}.", name, start_type),
+        Err(Error::Winapi(e)) => match e.raw_os_error() {
+            Some(code) if code as u32 == ERROR_SERVICE_DOES_NOT_EXIST => {
+                eprintln!("Service '{name}' is not installed.");
+            }
+            Some(code) if code as u32 == ERROR_ACCESS_DENIED => {
+                eprintln!("Access denied — run as Administrator or add the privilege.");
+            }
+            _ => {
+                eprintln!("Failed to set the start type of '{}': {:?}", name, e);
+            }
+        },
+        Err(e) => eprintln!("Failed to set the start type of '{}': {}", name, e),
+    }
+}
@@ -1,7 +1,10 @@
 use windows_service::Error;
-use windows_service::service::ServiceState;
+use windows_service::service::{ServiceExitCode, ServiceState};
 
-use crate::pkg::service::{get_service_command_line, get_service_status};
+use crate::pkg::duration::format_duration;
+use crate::pkg::service::{get_recovery, get_service_command_line, get_service_status};
+use crate::pkg::state::read_state;
+use crate::pkg::status::describe_exit_code;
 use prettytable::{Table, row};
 use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SERVICE_DOES_NOT_EXIST};
 
@@ -33,11 +36,50 @@ pub fn handle(name: &str) {
             }
 
             if status.current_state == ServiceState::Stopped {
-                table.add_row(row!["Exit Code", format!("{:?}", status.exit_code)]);
+                let exit_code = match status.exit_code {
+                    ServiceExitCode::Win32(code) => code.to_string(),
+                    ServiceExitCode::ServiceSpecific(code) => match describe_exit_code(code) {
+                        Some(reason) => format!("{} (0x{:X}, {})", code, code, reason),
+                        None => format!("{} (0x{:X}, service specific)", code, code),
+                    },
+                };
+                table.add_row(row!["Exit Code", exit_code]);
             } else {
                 table.add_row(row!["Exit Code", "N/A"]);
             }
 
+            if let Ok(recovery) = get_recovery(name)
+                && !recovery.actions.is_empty()
+            {
+                let actions: Vec<String> = recovery.actions.iter().map(|a| a.to_string()).collect();
+                table.add_row(row!["Failure Actions", actions.join(", ")]);
+                let reset_period = match recovery.reset_period {
+                    Some(period) => format_duration(period),
+                    None => String::from("never"),
+                };
+                table.add_row(row!["Failure Reset Period", reset_period]);
+                if let Some(command) = &recovery.command {
+                    table.add_row(row!["Failure Command", command]);
+                }
+                if let Some(message) = &recovery.reboot_message {
+                    table.add_row(row!["Failure Reboot Message", message]);
+                }
+                table.add_row(row![
+                    "Failure Actions On Non-Crash",
+                    if recovery.on_non_crash_failures {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                ]);
+            }
+
+            if let Ok(entries) = read_state(name) {
+                for (key, value) in entries {
+                    table.add_row(row![key, value]);
+                }
+            }
+
             table.printstd();
         }
         Err(Error::Winapi(e)) => match e.raw_os_error() {
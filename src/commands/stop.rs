@@ -1,9 +1,12 @@
 use windows_service::service::ServiceState;
 
-use crate::pkg::service::{stop_service, wait_for_service_status};
+use crate::pkg::service::{
+    dependency_graph, get_service_status, stop_service, wait_for_service_status,
+};
 use windows_service::Error;
 use windows_sys::Win32::Foundation::{
-    ERROR_ACCESS_DENIED, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_NOT_ACTIVE,
+    ERROR_ACCESS_DENIED, ERROR_DEPENDENT_SERVICES_RUNNING, ERROR_SERVICE_DOES_NOT_EXIST,
+    ERROR_SERVICE_NOT_ACTIVE,
 };
 
 pub fn handle_stop_error(e: Error, name: &str) {
@@ -18,6 +21,11 @@ pub fn handle_stop_error(e: Error, name: &str) {
             Some(code) if code as u32 == ERROR_SERVICE_NOT_ACTIVE => {
                 eprintln!("Service '{name}' is alredy stopped.");
             }
+            Some(code) if code as u32 == ERROR_DEPENDENT_SERVICES_RUNNING => {
+                eprintln!(
+                    "Services depending on '{name}' are running: stop them first or use 'wsw stop --cascade'."
+                );
+            }
             _ => {
                 eprintln!("Failed to stop service '{}': {:?}", name, e);
             }
@@ -28,18 +36,66 @@ pub fn handle_stop_error(e: Error, name: &str) {
     }
 }
 
-pub fn handle(name: &str) {
-    match stop_service(&name) {
+/// Returns the running services that depend on the given one, in stop order
+pub fn running_dependents(name: &str) -> Vec<String> {
+    let dependents = dependency_graph()
+        .map_err(|e| e.to_string())
+        .and_then(|graph| graph.dependents(name));
+    match dependents {
+        Ok(dependents) => dependents
+            .into_iter()
+            .filter(|dependent| {
+                get_service_status(dependent)
+                    .is_ok_and(|status| status.current_state != ServiceState::Stopped)
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to find the services depending on '{}': {}", name, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Stops a service and waits for it. Returns false if it could not be stopped
+pub fn stop_and_wait(name: &str) -> bool {
+    match stop_service(name) {
         Ok(_) => {
             match wait_for_service_status(
-                &name,
+                name,
                 ServiceState::Stopped,
                 std::time::Duration::from_secs(10),
             ) {
-                Ok(_) => println!("Service '{}' is now stopped.", name),
-                Err(e) => eprintln!("Failed to wait for service '{}': {}", name, e),
+                Ok(_) => {
+                    println!("Service '{}' is now stopped.", name);
+                    true
+                }
+                Err(e) => {
+                    eprintln!("Failed to wait for service '{}': {}", name, e);
+                    false
+                }
             }
         }
-        Err(e) => handle_stop_error(e, name),
+        Err(e) => {
+            handle_stop_error(e, name);
+            false
+        }
+    }
+}
+
+pub fn handle(name: &str, cascade: bool) {
+    let dependents = running_dependents(name);
+    if !dependents.is_empty() && !cascade {
+        eprintln!(
+            "Services depending on '{}' are running: {}. Stop them first or use --cascade.",
+            name,
+            dependents.join(", ")
+        );
+        return;
+    }
+    for dependent in &dependents {
+        if !stop_and_wait(dependent) {
+            return;
+        }
     }
+    stop_and_wait(name);
 }
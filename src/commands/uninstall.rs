@@ -1,18 +1,49 @@
-use crate::pkg::service::uninstall_service;
-use windows_service::Error;
-use windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED;
-
-pub fn handle(name: &str) {
-    match uninstall_service(&name) {
-        Ok(_) => println!("Service '{}' uninstalled successfully.", name),
-        Err(Error::Winapi(e)) => match e.raw_os_error() {
-            Some(code) if code as u32 == ERROR_ACCESS_DENIED => {
-                eprintln!("Access denied — run as Administrator or add the privilege.");
-            }
-            _ => {
-                eprintln!("Failed to uninstall the service '{}': {:?}", name, e);
-            }
-        },
-        Err(e) => tracing::error!("Failed to uninstall service '{}': {}", name, e),
-    }
-}
+use crate::pkg::service::{dependency_graph, uninstall_service};
+use windows_service::Error;
+use windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED;
+
+use super::stop::{running_dependents, stop_and_wait};
+
+pub fn handle(name: &str, force: bool) {
+    let dependents = dependency_graph()
+        .map_err(|e| e.to_string())
+        .and_then(|graph| graph.dependents(name));
+    match dependents {
+        Ok(dependents) if !dependents.is_empty() && !force => {
+            eprintln!(
+                "Services {} depend on '{}': uninstall them first or use --force.",
+                dependents.join(", "),
+                name
+            );
+            return;
+        }
+        Ok(dependents) if !dependents.is_empty() => {
+            // they could not be stopped once their dependency is gone
+            for dependent in running_dependents(name) {
+                stop_and_wait(&dependent);
+            }
+        }
+        Ok(_) => {}
+        Err(e) if !force => {
+            eprintln!(
+                "Failed to find the services depending on '{}': {}. Use --force to uninstall it anyway.",
+                name, e
+            );
+            return;
+        }
+        Err(_) => {}
+    }
+
+    match uninstall_service(&name) {
+        Ok(_) => println!("Service '{}' uninstalled successfully.", name),
+        Err(Error::Winapi(e)) => match e.raw_os_error() {
+            Some(code) if code as u32 == ERROR_ACCESS_DENIED => {
+                eprintln!("Access denied — run as Administrator or add the privilege.");
+            }
+            _ => {
+                eprintln!("Failed to uninstall the service '{}': {:?}", name, e);
+            }
+        },
+        Err(e) => tracing::error!("Failed to uninstall service '{}': {}", name, e),
+    }
+}
@@ -1,12 +1,26 @@
+// wsw is a Windows only tool. On other platforms only the supervision logic
+// is built, so that it can be unit tested.
+
+#[cfg(windows)]
 use clap::CommandFactory;
+#[cfg(windows)]
 use clap::Parser;
 
 mod cli;
+#[cfg(windows)]
 mod commands;
 mod pkg;
 
+#[cfg(windows)]
 use cli::*;
 
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("wsw only runs on Windows");
+    std::process::exit(1);
+}
+
+#[cfg(windows)]
 fn main() {
     let cli = Cli::parse();
     // If parsing fails, clap will print the error and exit
@@ -14,30 +28,20 @@ fn main() {
         Some(Commands::Logs { name, follow, full }) => commands::logs::handle(&name, follow, full),
         Some(Commands::List) => commands::list::handle(),
         Some(Commands::Start { name }) => commands::start::handle(&name),
-        Some(Commands::Stop { name }) => commands::stop::handle(&name),
+        Some(Commands::Stop { name, cascade }) => commands::stop::handle(&name, cascade),
         Some(Commands::Status { name }) => commands::status::handle(&name),
+        Some(Commands::Pause { name }) => commands::pause::handle(&name),
+        Some(Commands::Resume { name }) => commands::resume::handle(&name),
+        Some(Commands::Control { name, action, code }) => {
+            commands::control::handle(&name, action, code)
+        }
+        Some(Commands::SetStartType { name, start_type }) => {
+            commands::set_start_type::handle(&name, start_type)
+        }
         Some(Commands::Restart { name }) => commands::restart::handle(&name),
-        Some(Commands::Install {
-            cmd,
-            working_dir,
-            name,
-            disable_logs,
-            log_rotation,
-            max_log_files,
-            account_name,
-            account_password
-        }) => commands::install::handle(
-            &cmd,
-            working_dir,
-            &name,
-            disable_logs,
-            log_rotation,
-            max_log_files,
-            account_name,
-            account_password
-        ),
+        Some(Commands::Install(args)) => commands::install::handle(args),
 
-        Some(Commands::Uninstall { name }) => commands::uninstall::handle(&name),
+        Some(Commands::Uninstall { name, force }) => commands::uninstall::handle(&name, force),
         Some(Commands::Run {
             cmd,
             working_dir,
@@ -45,13 +49,15 @@ fn main() {
             disable_logs,
             log_rotation,
             max_log_files,
+            supervisor,
         }) => commands::run::handle(
-            &cmd,
+            cmd.as_deref(),
             working_dir,
             &name,
             disable_logs,
             log_rotation,
             max_log_files,
+            supervisor.shell,
         ),
         None => {
             Cli::command().print_help().unwrap();
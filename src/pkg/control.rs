@@ -0,0 +1,221 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::pkg::runner::ConsoleEvent;
+
+/// Range of the control codes that services can define for themselves
+pub const USER_CONTROL_CODES: std::ops::RangeInclusive<u32> = 128..=255;
+
+/// Supervisor action triggered by a user defined service control code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    /// Gracefully restart the wrapped process, without stopping the service
+    RestartChild,
+    /// Reopen the log files, e.g. after they have been moved away
+    ReopenLogs,
+    /// Write the supervisor status to the log
+    DumpStatus,
+    /// Deliver a console event (a signal on Unix) to the wrapped process
+    ConsoleEvent(ConsoleEvent),
+    /// Write a line to the wrapped process stdin
+    StdinLine(String),
+}
+
+impl fmt::Display for ControlAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlAction::RestartChild => write!(f, "restart-child"),
+            ControlAction::ReopenLogs => write!(f, "reopen-logs"),
+            ControlAction::DumpStatus => write!(f, "dump-status"),
+            ControlAction::ConsoleEvent(ConsoleEvent::CtrlC) => write!(f, "ctrl-c"),
+            ControlAction::ConsoleEvent(ConsoleEvent::CtrlBreak) => write!(f, "ctrl-break"),
+            ControlAction::StdinLine(line) => write!(f, "stdin:{}", line),
+        }
+    }
+}
+
+impl FromStr for ControlAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(line) = s.strip_prefix("stdin:") {
+            return Ok(ControlAction::StdinLine(line.to_string()));
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "restart-child" => Ok(ControlAction::RestartChild),
+            "reopen-logs" | "rotate-logs" => Ok(ControlAction::ReopenLogs),
+            "dump-status" => Ok(ControlAction::DumpStatus),
+            "ctrl-c" => Ok(ControlAction::ConsoleEvent(ConsoleEvent::CtrlC)),
+            "ctrl-break" => Ok(ControlAction::ConsoleEvent(ConsoleEvent::CtrlBreak)),
+            _ => Err(format!("Invalid control action: {}", s)),
+        }
+    }
+}
+
+/// Custom mapping from a control code to an action, given as "CODE=ACTION"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlMapping {
+    pub code: u32,
+    pub action: ControlAction,
+}
+
+impl fmt::Display for ControlMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.code, self.action)
+    }
+}
+
+impl FromStr for ControlMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (code, action) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid control mapping: {} (expected CODE=ACTION)", s))?;
+        let code: u32 = code
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid control code: {}", code))?;
+        if !USER_CONTROL_CODES.contains(&code) {
+            return Err(format!(
+                "Invalid control code: {} (must be between {} and {})",
+                code,
+                USER_CONTROL_CODES.start(),
+                USER_CONTROL_CODES.end()
+            ));
+        }
+        // "wsw control --action" always sends the built-in codes
+        if let Some((_, builtin)) = BUILTIN_CONTROLS.iter().find(|(c, _)| *c == code) {
+            return Err(format!(
+                "Invalid control code: {} (reserved for {})",
+                code, builtin
+            ));
+        }
+        Ok(ControlMapping {
+            code,
+            action: action.parse()?,
+        })
+    }
+}
+
+/// Actions available out of the box, with their control codes
+const BUILTIN_CONTROLS: [(u32, ControlAction); 5] = [
+    (128, ControlAction::RestartChild),
+    (129, ControlAction::ReopenLogs),
+    (130, ControlAction::DumpStatus),
+    (131, ControlAction::ConsoleEvent(ConsoleEvent::CtrlC)),
+    (132, ControlAction::ConsoleEvent(ConsoleEvent::CtrlBreak)),
+];
+
+/// Maps control codes to actions: the built-in ones plus the custom mappings
+#[derive(Debug, Clone)]
+pub struct ControlTable {
+    entries: Vec<(u32, ControlAction)>,
+}
+
+impl ControlTable {
+    /// Custom mappings never use the codes of the built-in ones
+    pub fn new(custom: &[ControlMapping]) -> Self {
+        let mut entries = BUILTIN_CONTROLS.to_vec();
+        entries.extend(custom.iter().map(|m| (m.code, m.action.clone())));
+        ControlTable { entries }
+    }
+
+    /// Returns the action bound to the control code, if any
+    #[cfg(any(windows, test))]
+    pub fn dispatch(&self, code: u32) -> Option<&ControlAction> {
+        self.entries
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, action)| action)
+    }
+
+    /// True if some code writes to the wrapped process stdin
+    pub fn needs_stdin(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(_, action)| matches!(action, ControlAction::StdinLine(_)))
+    }
+}
+
+/// Returns the control code of a built-in action, used by "wsw control --action"
+#[cfg(any(windows, test))]
+pub fn builtin_code(action: &ControlAction) -> Option<u32> {
+    BUILTIN_CONTROLS
+        .iter()
+        .find(|(_, a)| a == action)
+        .map(|(code, _)| *code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action_round_trip() {
+        for action in [
+            "restart-child",
+            "reopen-logs",
+            "dump-status",
+            "ctrl-c",
+            "ctrl-break",
+            "stdin:reload config",
+        ] {
+            assert_eq!(action.parse::<ControlAction>().unwrap().to_string(), action);
+        }
+        assert!("reboot".parse::<ControlAction>().is_err());
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            "200=stdin:reload".parse(),
+            Ok(ControlMapping {
+                code: 200,
+                action: ControlAction::StdinLine(String::from("reload")),
+            })
+        );
+        assert!("127=dump-status".parse::<ControlMapping>().is_err());
+        assert!("256=dump-status".parse::<ControlMapping>().is_err());
+        // built-in codes can't be rebound
+        assert!("128=dump-status".parse::<ControlMapping>().is_err());
+        assert!("132=stdin:quit".parse::<ControlMapping>().is_err());
+        assert!("133=dump-status".parse::<ControlMapping>().is_ok());
+        assert!("200".parse::<ControlMapping>().is_err());
+        assert!("abc=dump-status".parse::<ControlMapping>().is_err());
+    }
+
+    #[test]
+    fn test_dispatch_builtin_codes() {
+        let table = ControlTable::new(&[]);
+        assert_eq!(table.dispatch(128), Some(&ControlAction::RestartChild));
+        assert_eq!(table.dispatch(130), Some(&ControlAction::DumpStatus));
+        assert_eq!(
+            table.dispatch(132),
+            Some(&ControlAction::ConsoleEvent(ConsoleEvent::CtrlBreak))
+        );
+        assert_eq!(table.dispatch(200), None);
+        assert!(!table.needs_stdin());
+    }
+
+    #[test]
+    fn test_dispatch_custom_codes() {
+        let table = ControlTable::new(&[
+            "200=stdin:reload".parse().unwrap(),
+            "133=dump-status".parse().unwrap(),
+        ]);
+        assert_eq!(
+            table.dispatch(200),
+            Some(&ControlAction::StdinLine(String::from("reload")))
+        );
+        assert_eq!(table.dispatch(133), Some(&ControlAction::DumpStatus));
+        assert_eq!(table.dispatch(128), Some(&ControlAction::RestartChild));
+        assert!(table.needs_stdin());
+    }
+
+    #[test]
+    fn test_builtin_code() {
+        assert_eq!(builtin_code(&ControlAction::ReopenLogs), Some(129));
+        assert_eq!(builtin_code(&ControlAction::StdinLine(String::new())), None);
+    }
+}
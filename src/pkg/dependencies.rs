@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Dependencies between services, used to validate them before they are
+/// written to the SCM and to find the services affected by a stop.
+/// Service names are case insensitive, like in the SCM
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Keyed by lowercase name
+    nodes: BTreeMap<String, Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    dependencies: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+fn key(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the dependencies of a service, replacing the previous ones.
+    /// Dependencies that are not in the graph are added without dependencies
+    pub fn add(&mut self, name: &str, dependencies: &[String]) {
+        for dependency in dependencies {
+            self.node(dependency);
+        }
+        self.node(name).dependencies = dependencies.iter().map(|d| key(d)).collect();
+    }
+
+    fn node(&mut self, name: &str) -> &mut Node {
+        self.nodes.entry(key(name)).or_insert_with(|| Node {
+            name: name.to_string(),
+            dependencies: Vec::new(),
+        })
+    }
+
+    /// Returns the services in an order where every service comes after its
+    /// dependencies, or the first cycle found, e.g. "api -> db -> api"
+    pub fn start_order(&self) -> Result<Vec<String>, String> {
+        let mut marks = HashMap::new();
+        let mut stack = Vec::new();
+        let mut order = Vec::new();
+        for node in self.nodes.keys() {
+            self.visit(node, &mut marks, &mut stack, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        node: &'a str,
+        marks: &mut HashMap<&'a str, Mark>,
+        stack: &mut Vec<&'a str>,
+        order: &mut Vec<String>,
+    ) -> Result<(), String> {
+        match marks.get(node) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                let start = stack.iter().position(|n| *n == node).unwrap_or(0);
+                let cycle: Vec<&str> = stack[start..]
+                    .iter()
+                    .chain([&node])
+                    .map(|n| self.nodes[*n].name.as_str())
+                    .collect();
+                return Err(format!("Dependency cycle: {}", cycle.join(" -> ")));
+            }
+            None => {}
+        }
+
+        marks.insert(node, Mark::Visiting);
+        stack.push(node);
+        for dependency in &self.nodes[node].dependencies {
+            self.visit(dependency, marks, stack, order)?;
+        }
+        stack.pop();
+        marks.insert(node, Mark::Done);
+        order.push(self.nodes[node].name.clone());
+        Ok(())
+    }
+
+    /// Returns the services depending, directly or not, on the given one,
+    /// in the order they should be stopped
+    pub fn dependents(&self, name: &str) -> Result<Vec<String>, String> {
+        let target = key(name);
+        let mut dependents: Vec<String> = Vec::new();
+        // in start order the dependencies of a service are always seen before it
+        for service in self.start_order()? {
+            let node = &self.nodes[&key(&service)];
+            if node
+                .dependencies
+                .iter()
+                .any(|d| *d == target || dependents.iter().any(|s| key(s) == *d))
+            {
+                dependents.push(service);
+            }
+        }
+        dependents.reverse();
+        Ok(dependents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for (name, dependencies) in edges {
+            let dependencies: Vec<String> = dependencies.iter().map(|d| d.to_string()).collect();
+            graph.add(name, &dependencies);
+        }
+        graph
+    }
+
+    fn position(order: &[String], name: &str) -> usize {
+        order.iter().position(|n| n == name).unwrap()
+    }
+
+    #[test]
+    fn test_start_order() {
+        let g = graph(&[
+            ("web", &["api", "cache"]),
+            ("api", &["db"]),
+            ("cache", &[]),
+            ("db", &["Tcpip"]),
+        ]);
+        let order = g.start_order().unwrap();
+        assert_eq!(order.len(), 5);
+        assert!(position(&order, "Tcpip") < position(&order, "db"));
+        assert!(position(&order, "db") < position(&order, "api"));
+        assert!(position(&order, "api") < position(&order, "web"));
+        assert!(position(&order, "cache") < position(&order, "web"));
+    }
+
+    #[test]
+    fn test_cycles_are_detected() {
+        let g = graph(&[("api", &["db"]), ("db", &["queue"]), ("queue", &["api"])]);
+        assert_eq!(
+            g.start_order(),
+            Err(String::from("Dependency cycle: api -> db -> queue -> api"))
+        );
+
+        let g = graph(&[("api", &["api"])]);
+        assert_eq!(
+            g.start_order(),
+            Err(String::from("Dependency cycle: api -> api"))
+        );
+        assert!(g.dependents("api").is_err());
+    }
+
+    #[test]
+    fn test_names_are_case_insensitive() {
+        let g = graph(&[("api", &["DB"]), ("db", &["API"])]);
+        assert!(g.start_order().is_err());
+
+        let g = graph(&[("api", &["DB"]), ("db", &[])]);
+        assert_eq!(g.start_order().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_add_replaces_dependencies() {
+        let mut g = graph(&[("api", &["db"]), ("db", &[])]);
+        g.add("db", &[String::from("api")]);
+        assert!(g.start_order().is_err());
+        g.add("db", &[]);
+        assert!(g.start_order().is_ok());
+    }
+
+    #[test]
+    fn test_dependents_in_stop_order() {
+        let g = graph(&[
+            ("web", &["api"]),
+            ("api", &["db"]),
+            ("worker", &["db"]),
+            ("db", &[]),
+            ("cache", &[]),
+        ]);
+        let dependents = g.dependents("DB").unwrap();
+        assert_eq!(dependents.len(), 3);
+        // web must be stopped before the api it depends on
+        assert!(position(&dependents, "web") < position(&dependents, "api"));
+        assert!(dependents.contains(&String::from("worker")));
+
+        assert_eq!(g.dependents("cache"), Ok(vec![]));
+        assert_eq!(g.dependents("unknown"), Ok(vec![]));
+    }
+}
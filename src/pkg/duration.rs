@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+/// Parses a human friendly duration like `500ms`, `10s`, `5m`, `12h` or `1d`.
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", s))?;
+
+    let seconds = match unit.trim().to_ascii_lowercase().as_str() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 60.0 * 60.0,
+        "d" => value * 60.0 * 60.0 * 24.0,
+        _ => return Err(format!("Invalid duration unit: {}", s)),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Duration out of range: {}", s))
+}
+
+/// Formats a duration so that it can be parsed back by `parse_duration`.
+/// This is used to forward the options to the service command line.
+pub fn format_duration(d: Duration) -> String {
+    if d.subsec_millis() == 0 {
+        format!("{}s", d.as_secs())
+    } else {
+        format!("{}ms", d.as_millis())
+    }
+}
+
+/// Formats a duration for humans, e.g. "2h 05m 09s". Unlike `format_duration`
+/// the result is not meant to be parsed back
+pub fn format_elapsed(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, minutes, seconds) =
+        (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {:02}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{:.2}s", d.as_secs_f64())
+    }
+}
+
+/// Adds a random extra delay of up to `jitter` to `base`.
+/// `sample` is a random value in [0, 1)
+pub fn with_jitter(base: Duration, jitter: Duration, sample: f64) -> Duration {
+    base + jitter.mul_f64(sample.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_parse_duration_bare_number_is_seconds() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("abc").is_err());
+        assert!(parse_duration("10w").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_out_of_range() {
+        assert!(parse_duration("99999999999999999999999d").is_err());
+        assert!(parse_duration(&"9".repeat(400)).is_err());
+    }
+
+    #[test]
+    fn test_format_duration_round_trip() {
+        for d in [
+            Duration::from_secs(0),
+            Duration::from_secs(42),
+            Duration::from_millis(1500),
+        ] {
+            assert_eq!(parse_duration(&format_duration(d)), Ok(d));
+        }
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(350)), "0.35s");
+        assert_eq!(format_elapsed(Duration::from_secs(90)), "1m 30s");
+        assert_eq!(format_elapsed(Duration::from_secs(7509)), "2h 05m 09s");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(3 * 86400 + 3600)),
+            "3d 1h 00m"
+        );
+    }
+
+    #[test]
+    fn test_with_jitter() {
+        let base = Duration::from_secs(10);
+        let jitter = Duration::from_secs(4);
+        assert_eq!(with_jitter(base, jitter, 0.0), base);
+        assert_eq!(with_jitter(base, jitter, 0.5), Duration::from_secs(12));
+        assert!(with_jitter(base, jitter, 0.999) < Duration::from_secs(14));
+        assert_eq!(with_jitter(base, Duration::ZERO, 0.7), base);
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+#[cfg(any(windows, test))]
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::pkg::control::ControlAction;
+
+/// Called by the background threads to wake up the supervisor
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+/// Something the supervisor reacts to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A child process exited, identified by its PID
+    ChildExited(u32),
+    /// The service is asked to stop, `shutdown` when the system is shutting down
+    #[cfg_attr(not(windows), allow(dead_code))] // sent by the Windows control handler
+    Stop {
+        shutdown: bool,
+    },
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Pause,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Resume,
+    /// A user defined control code was received
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Control(ControlAction),
+    /// A line of the child output matched a restart rule
+    Output,
+    /// A background monitor (readiness, liveness, notifications, file watch) has news
+    Wake,
+}
+
+/// Where the supervisor gets its events from.
+/// It is injected so that the supervisor can be driven without a service or processes
+pub trait EventSource {
+    /// Waits for the next event until the deadline, or forever without one.
+    /// Returns None once the deadline is reached
+    fn wait(&mut self, deadline: Option<Instant>) -> Option<Event>;
+}
+
+/// Events sent by the other threads: the service control handler,
+/// the child exit waiters, the output observers and the monitors
+#[cfg(any(windows, test))]
+pub struct EventQueue {
+    tx: Sender<Event>,
+    rx: Receiver<Event>,
+}
+
+#[cfg(any(windows, test))]
+impl EventQueue {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        EventQueue { tx, rx }
+    }
+
+    pub fn sender(&self) -> Sender<Event> {
+        self.tx.clone()
+    }
+}
+
+/// Returns a waker sending the given event
+pub fn waker(events: &Sender<Event>, event: Event) -> Waker {
+    let events = events.clone();
+    Arc::new(move || {
+        let _ = events.send(event.clone());
+    })
+}
+
+#[cfg(any(windows, test))]
+impl EventSource for EventQueue {
+    fn wait(&mut self, deadline: Option<Instant>) -> Option<Event> {
+        // the queue holds a sender itself, so the channel is never disconnected
+        match deadline {
+            None => self.rx.recv().ok(),
+            Some(deadline) => self
+                .rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+        }
+    }
+}
+
+/// Returns the earliest of the given deadlines
+pub fn earliest(deadlines: impl IntoIterator<Item = Option<Instant>>) -> Option<Instant> {
+    deadlines.into_iter().flatten().min()
+}
+
+/// What the service was asked to do so far, updated from the events
+pub struct Controls<E: EventSource> {
+    events: E,
+    pub running: bool,
+    pub pause_requested: bool,
+    pub shutting_down: bool,
+    /// Control actions waiting to be handled
+    pub actions: VecDeque<ControlAction>,
+}
+
+impl<E: EventSource> Controls<E> {
+    #[cfg(any(windows, test))]
+    pub fn new(events: E) -> Self {
+        Controls {
+            events,
+            running: true,
+            pause_requested: false,
+            shutting_down: false,
+            actions: VecDeque::new(),
+        }
+    }
+
+    /// Waits for the next event until the deadline and records the requests it carries.
+    /// Returns false once the deadline is reached
+    pub fn wait(&mut self, deadline: Option<Instant>) -> bool {
+        let Some(event) = self.events.wait(deadline) else {
+            return false;
+        };
+        match event {
+            Event::Stop { shutdown } => {
+                self.running = false;
+                self.shutting_down |= shutdown;
+            }
+            Event::Pause => self.pause_requested = true,
+            Event::Resume => self.pause_requested = false,
+            Event::Control(action) => self.actions.push_back(action),
+            Event::ChildExited(_) | Event::Output | Event::Wake => {}
+        }
+        true
+    }
+
+    /// Sleeps for the given duration, returning early if the service is asked to stop
+    pub fn sleep(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while self.running && self.wait(Some(deadline)) {}
+    }
+
+    /// Waits until the service is asked to stop or a restart-child action is received.
+    /// The other actions are given to `other`
+    pub fn wait_for_restart(&mut self, mut other: impl FnMut(ControlAction)) {
+        while self.running {
+            while let Some(action) = self.actions.pop_front() {
+                if action == ControlAction::RestartChild {
+                    return;
+                }
+                other(action);
+            }
+            self.wait(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays scripted events, then reports every deadline as reached
+    /// without sleeping. A wait without deadline on an empty script is a bug
+    struct FakeEvents {
+        script: VecDeque<Event>,
+        waits: Vec<Option<Instant>>,
+    }
+
+    impl FakeEvents {
+        fn new(script: impl IntoIterator<Item = Event>) -> Self {
+            FakeEvents {
+                script: script.into_iter().collect(),
+                waits: Vec::new(),
+            }
+        }
+    }
+
+    impl EventSource for FakeEvents {
+        fn wait(&mut self, deadline: Option<Instant>) -> Option<Event> {
+            self.waits.push(deadline);
+            let event = self.script.pop_front();
+            assert!(
+                event.is_some() || deadline.is_some(),
+                "waiting forever without events"
+            );
+            event
+        }
+    }
+
+    #[test]
+    fn test_controls() {
+        let mut controls = Controls::new(FakeEvents::new([
+            Event::Pause,
+            Event::Control(ControlAction::DumpStatus),
+            Event::Output,
+            Event::Resume,
+            Event::Stop { shutdown: true },
+        ]));
+        assert!(controls.wait(None));
+        assert!(controls.pause_requested);
+        assert!(controls.wait(None));
+        assert_eq!(
+            controls.actions.pop_front(),
+            Some(ControlAction::DumpStatus)
+        );
+        assert!(controls.wait(None));
+        assert!(controls.wait(None));
+        assert!(!controls.pause_requested);
+        assert!(controls.running);
+        assert!(controls.wait(None));
+        assert!(!controls.running);
+        assert!(controls.shutting_down);
+        assert!(!controls.wait(Some(Instant::now())));
+    }
+
+    #[test]
+    fn test_sleep() {
+        // wake ups that are not a stop request do not end the sleep early
+        let mut controls = Controls::new(FakeEvents::new([Event::Output, Event::Wake]));
+        controls.sleep(Duration::from_secs(3600));
+        assert_eq!(controls.events.waits.len(), 3);
+        assert!(controls.running);
+
+        let mut controls = Controls::new(FakeEvents::new([
+            Event::ChildExited(42),
+            Event::Stop { shutdown: false },
+            Event::Output,
+        ]));
+        controls.sleep(Duration::from_secs(3600));
+        assert!(!controls.running);
+        assert_eq!(controls.events.script, [Event::Output]);
+    }
+
+    #[test]
+    fn test_wait_for_restart() {
+        let mut controls = Controls::new(FakeEvents::new([
+            Event::Control(ControlAction::ReopenLogs),
+            Event::Wake,
+            Event::Control(ControlAction::RestartChild),
+            Event::Control(ControlAction::DumpStatus),
+        ]));
+        let mut others = Vec::new();
+        controls.wait_for_restart(|action| others.push(action));
+        assert_eq!(others, [ControlAction::ReopenLogs]);
+        assert!(controls.running);
+        // no timer is armed while waiting
+        assert!(controls.events.waits.iter().all(Option::is_none));
+
+        controls
+            .events
+            .script
+            .push_back(Event::Stop { shutdown: false });
+        controls.wait_for_restart(|action| others.push(action));
+        assert_eq!(
+            others,
+            [ControlAction::ReopenLogs, ControlAction::DumpStatus]
+        );
+        assert!(!controls.running);
+    }
+
+    #[test]
+    fn test_event_queue() {
+        let mut queue = EventQueue::new();
+        let sender = queue.sender();
+        let wake = waker(&queue.sender(), Event::Wake);
+        std::thread::spawn(move || {
+            sender.send(Event::ChildExited(7)).unwrap();
+            wake();
+        });
+        assert_eq!(queue.wait(None), Some(Event::ChildExited(7)));
+        assert_eq!(queue.wait(None), Some(Event::Wake));
+        let start = Instant::now();
+        assert_eq!(queue.wait(Some(start + Duration::from_millis(50))), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(earliest([None, Some(start), None]), Some(start));
+        assert_eq!(earliest([None, None]), None);
+    }
+}
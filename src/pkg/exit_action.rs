@@ -0,0 +1,197 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::pkg::duration::{format_duration, parse_duration};
+
+/// What the supervisor does when the wrapped process exits on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAction {
+    /// Restart the wrapped process, with the usual backoff
+    Restart,
+    /// Stop the service
+    StopService,
+    /// Leave the wrapped process stopped, but keep the service running
+    Ignore,
+    /// Restart the wrapped process after a fixed delay
+    RestartAfter(Duration),
+}
+
+impl fmt::Display for ExitAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitAction::Restart => write!(f, "restart"),
+            ExitAction::StopService => write!(f, "stop-service"),
+            ExitAction::Ignore => write!(f, "ignore"),
+            ExitAction::RestartAfter(delay) => {
+                write!(f, "restart-after:{}", format_duration(*delay))
+            }
+        }
+    }
+}
+
+impl FromStr for ExitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        // "restart-after:30s" and "restart-after 30s" are both accepted
+        if let Some(delay) = lower.strip_prefix("restart-after")
+            && let Some(delay) = delay
+                .strip_prefix(':')
+                .or_else(|| delay.strip_prefix(char::is_whitespace))
+        {
+            return Ok(ExitAction::RestartAfter(parse_duration(delay)?));
+        }
+        match lower.as_str() {
+            "restart" => Ok(ExitAction::Restart),
+            "stop-service" => Ok(ExitAction::StopService),
+            "ignore" => Ok(ExitAction::Ignore),
+            _ => Err(format!("Invalid exit action: {}", s)),
+        }
+    }
+}
+
+/// Mapping from an exit code to an action, given as "CODE=ACTION"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitMapping {
+    pub code: i32,
+    pub action: ExitAction,
+}
+
+impl fmt::Display for ExitMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.code, self.action)
+    }
+}
+
+impl FromStr for ExitMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (code, action) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid exit mapping: {} (expected CODE=ACTION)", s))?;
+        Ok(ExitMapping {
+            code: parse_exit_code(code.trim())?,
+            action: action.parse()?,
+        })
+    }
+}
+
+/// Parses an exit code. Besides signed values, unsigned and hexadecimal
+/// values are accepted, as Windows exit codes are often written
+/// like 0xC0000005
+fn parse_exit_code(s: &str) -> Result<i32, String> {
+    let invalid = || format!("Invalid exit code: {}", s);
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u32::from_str_radix(hex, 16)
+            .map(|code| code as i32)
+            .map_err(|_| invalid());
+    }
+    s.parse::<i32>()
+        .or_else(|_| s.parse::<u32>().map(|code| code as i32))
+        .map_err(|_| invalid())
+}
+
+/// Decides what to do when the wrapped process exits, from its exit code
+#[derive(Debug, Clone, Default)]
+pub struct ExitActionTable {
+    mappings: Vec<ExitMapping>,
+    default: Option<ExitAction>,
+}
+
+impl ExitActionTable {
+    /// `default` applies to the codes without a mapping. When it is not set,
+    /// the restart policy decides for them
+    pub fn new(mappings: &[ExitMapping], default: Option<ExitAction>) -> Self {
+        ExitActionTable {
+            mappings: mappings.to_vec(),
+            default,
+        }
+    }
+
+    /// Returns the action for the exit code, or None if the restart policy
+    /// decides. A process without an exit code (e.g. killed by a signal)
+    /// gets the default action. The first mapping for a code wins
+    pub fn action(&self, code: Option<i32>) -> Option<ExitAction> {
+        code.and_then(|code| self.mappings.iter().find(|m| m.code == code))
+            .map(|m| m.action)
+            .or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action_round_trip() {
+        for action in ["restart", "stop-service", "ignore", "restart-after:30s"] {
+            assert_eq!(action.parse::<ExitAction>().unwrap().to_string(), action);
+        }
+        assert_eq!(
+            "Restart-After 1m".parse(),
+            Ok(ExitAction::RestartAfter(Duration::from_secs(60)))
+        );
+        assert!("restart-after".parse::<ExitAction>().is_err());
+        assert!("restart-after:soon".parse::<ExitAction>().is_err());
+        assert!("restart-afterwards".parse::<ExitAction>().is_err());
+        assert!("reboot".parse::<ExitAction>().is_err());
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            "3=stop-service".parse(),
+            Ok(ExitMapping {
+                code: 3,
+                action: ExitAction::StopService,
+            })
+        );
+        assert_eq!("-1=restart".parse::<ExitMapping>().map(|m| m.code), Ok(-1));
+        assert_eq!(
+            "0xC0000005=restart".parse::<ExitMapping>().map(|m| m.code),
+            Ok(0xC0000005u32 as i32)
+        );
+        assert_eq!(
+            "3221225477=restart".parse::<ExitMapping>().map(|m| m.code),
+            Ok(0xC0000005u32 as i32)
+        );
+        assert!("3".parse::<ExitMapping>().is_err());
+        assert!("x=restart".parse::<ExitMapping>().is_err());
+        assert!("3=reboot".parse::<ExitMapping>().is_err());
+    }
+
+    #[test]
+    fn test_action_for_mapped_codes() {
+        let table = ExitActionTable::new(
+            &[
+                "0=ignore".parse().unwrap(),
+                "3=stop-service".parse().unwrap(),
+                "3=restart".parse().unwrap(),
+                "4=restart-after:10s".parse().unwrap(),
+            ],
+            Some(ExitAction::Restart),
+        );
+        assert_eq!(table.action(Some(0)), Some(ExitAction::Ignore));
+        assert_eq!(table.action(Some(3)), Some(ExitAction::StopService));
+        assert_eq!(
+            table.action(Some(4)),
+            Some(ExitAction::RestartAfter(Duration::from_secs(10)))
+        );
+        // unmapped codes and missing codes get the default
+        assert_eq!(table.action(Some(1)), Some(ExitAction::Restart));
+        assert_eq!(table.action(None), Some(ExitAction::Restart));
+    }
+
+    #[test]
+    fn test_unmapped_codes_without_default_follow_the_policy() {
+        let table = ExitActionTable::new(&["3=stop-service".parse().unwrap()], None);
+        assert_eq!(table.action(Some(3)), Some(ExitAction::StopService));
+        assert_eq!(table.action(Some(1)), None);
+        assert_eq!(table.action(None), None);
+        assert_eq!(ExitActionTable::default().action(Some(0)), None);
+    }
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::pkg::restart::{RestartDecision, RestartPolicy};
+
+/// A named command supervised as part of a process group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessSpec {
+    /// Name of the process, also used to tag its output in the logs
+    pub name: String,
+    pub cmd: String,
+    /// If not specified, the service working directory is used
+    pub working_dir: Option<String>,
+    /// If not specified, the service restart policy is used
+    pub restart: Option<RestartPolicy>,
+}
+
+impl ProcessSpec {
+    fn new(name: &str, cmd: &str) -> Result<Self, String> {
+        let name = name.trim();
+        let cmd = cmd.trim();
+        validate_name(name)?;
+        if cmd.is_empty() {
+            return Err(format!("Missing command for process: {}", name));
+        }
+        Ok(ProcessSpec {
+            name: name.to_string(),
+            cmd: cmd.to_string(),
+            working_dir: None,
+            restart: None,
+        })
+    }
+}
+
+impl fmt::Display for ProcessSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.cmd)
+    }
+}
+
+/// Parses "NAME=CMD"
+impl FromStr for ProcessSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, cmd) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid process: {} (expected NAME=CMD)", s))?;
+        ProcessSpec::new(name, cmd)
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid process name: {:?} (only letters, digits, '-' and '_' are allowed)",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Per process option, given as "NAME=VALUE"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessSetting<T> {
+    pub name: String,
+    pub value: T,
+}
+
+impl<T: fmt::Display> fmt::Display for ProcessSetting<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl<T> FromStr for ProcessSetting<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid process setting: {} (expected NAME=VALUE)", s))?;
+        let name = name.trim();
+        validate_name(name)?;
+        Ok(ProcessSetting {
+            name: name.to_string(),
+            value: value.trim().parse().map_err(|e| format!("{}", e))?,
+        })
+    }
+}
+
+/// Parses a Procfile: one "name: command" per line.
+/// Blank lines and lines starting with '#' are skipped
+pub fn parse_procfile(content: &str) -> Result<Vec<ProcessSpec>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (name, cmd) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid Procfile line {}: {}", number, line))?;
+            ProcessSpec::new(name, cmd)
+                .map_err(|e| format!("Invalid Procfile line {}: {}", number, e))
+        })
+        .collect()
+}
+
+/// Reads a Procfile. Its processes run by default in the Procfile directory
+pub fn read_procfile(path: &Path) -> Result<Vec<ProcessSpec>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the Procfile {:?}: {}", path, e))?;
+    let dir = path
+        .parent()
+        .filter(|dir| *dir != Path::new(""))
+        .map(|dir| dir.to_string_lossy().to_string());
+    let mut specs = parse_procfile(&content)?;
+    for spec in &mut specs {
+        spec.working_dir = dir.clone();
+    }
+    Ok(specs)
+}
+
+/// Merges the processes of the Procfile with the ones given on the command line,
+/// in this order, and applies the per process settings
+pub fn build_group(
+    mut specs: Vec<ProcessSpec>,
+    working_dirs: &[ProcessSetting<String>],
+    restarts: &[ProcessSetting<RestartPolicy>],
+) -> Result<Vec<ProcessSpec>, String> {
+    for (index, spec) in specs.iter().enumerate() {
+        if specs[..index].iter().any(|other| other.name == spec.name) {
+            return Err(format!("Duplicate process name: {}", spec.name));
+        }
+    }
+    let find = |specs: &[ProcessSpec], name: &str| {
+        specs
+            .iter()
+            .position(|spec| spec.name == name)
+            .ok_or_else(|| format!("Unknown process: {}", name))
+    };
+    for setting in working_dirs {
+        let index = find(&specs, &setting.name)?;
+        specs[index].working_dir = Some(setting.value.clone());
+    }
+    for setting in restarts {
+        let index = find(&specs, &setting.name)?;
+        specs[index].restart = Some(setting.value);
+    }
+    Ok(specs)
+}
+
+/// How the members of a process group are restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupMode {
+    /// Every process is restarted on its own
+    Independent,
+    /// When a process exits, the whole group is restarted
+    RestartAll,
+}
+
+impl fmt::Display for GroupMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupMode::Independent => write!(f, "independent"),
+            GroupMode::RestartAll => write!(f, "restart-all"),
+        }
+    }
+}
+
+impl FromStr for GroupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "independent" => Ok(GroupMode::Independent),
+            "restart-all" => Ok(GroupMode::RestartAll),
+            _ => Err(format!("Invalid group mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupAction {
+    /// Restart the process after the delay, the others keep running
+    Restart(Duration),
+    /// Leave the process stopped, the others keep running
+    LeaveStopped,
+    /// Stop the other processes and restart the whole group after the delay
+    RestartAll(Duration),
+    /// Stop the whole group, and with it the service
+    StopAll,
+}
+
+/// Decides what happens to the group when one of its processes exits.
+/// `decision` comes from the restart policy of the process, `others_alive`
+/// tells if any other process is running or waiting to be restarted
+pub fn on_process_exit(
+    mode: GroupMode,
+    decision: RestartDecision,
+    others_alive: bool,
+) -> GroupAction {
+    match (mode, decision) {
+        (GroupMode::Independent, RestartDecision::Restart(delay)) => GroupAction::Restart(delay),
+        (GroupMode::Independent, RestartDecision::Stop) if others_alive => {
+            GroupAction::LeaveStopped
+        }
+        (GroupMode::RestartAll, RestartDecision::Restart(delay)) => GroupAction::RestartAll(delay),
+        _ => GroupAction::StopAll,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, cmd: &str) -> ProcessSpec {
+        ProcessSpec::new(name, cmd).unwrap()
+    }
+
+    #[test]
+    fn test_parse_process() {
+        assert_eq!(
+            "web=app.exe --port=80".parse(),
+            Ok(spec("web", "app.exe --port=80"))
+        );
+        assert!("web".parse::<ProcessSpec>().is_err());
+        assert!("web=".parse::<ProcessSpec>().is_err());
+        assert!("my app=app.exe".parse::<ProcessSpec>().is_err());
+    }
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(
+            "proxy=never".parse(),
+            Ok(ProcessSetting {
+                name: String::from("proxy"),
+                value: RestartPolicy::Never,
+            })
+        );
+        assert_eq!(
+            "web=C:\\srv\\web".parse::<ProcessSetting<String>>(),
+            Ok(ProcessSetting {
+                name: String::from("web"),
+                value: String::from("C:\\srv\\web"),
+            })
+        );
+        assert!(
+            "proxy=sometimes"
+                .parse::<ProcessSetting<RestartPolicy>>()
+                .is_err()
+        );
+        assert!("never".parse::<ProcessSetting<RestartPolicy>>().is_err());
+    }
+
+    #[test]
+    fn test_parse_procfile() {
+        let content = "# dev setup\n\
+                       web: app.exe --port 80\n\
+                       \n\
+                       shipper:   shipper.exe -c ship.yml  \n";
+        assert_eq!(
+            parse_procfile(content),
+            Ok(vec![
+                spec("web", "app.exe --port 80"),
+                spec("shipper", "shipper.exe -c ship.yml"),
+            ])
+        );
+        assert_eq!(parse_procfile(""), Ok(vec![]));
+        assert!(parse_procfile("web app.exe").is_err());
+        assert!(parse_procfile("web:").is_err());
+        assert_eq!(
+            parse_procfile("web: a.exe\nbad line"),
+            Err(String::from("Invalid Procfile line 2: bad line"))
+        );
+    }
+
+    #[test]
+    fn test_build_group() {
+        let specs = build_group(
+            vec![spec("web", "app.exe"), spec("proxy", "proxy.exe")],
+            &["proxy=C:\\proxy".parse().unwrap()],
+            &["web=on-failure".parse().unwrap()],
+        )
+        .unwrap();
+        // the declaration order is the start order
+        assert_eq!(specs[0].name, "web");
+        assert_eq!(specs[0].restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(specs[0].working_dir, None);
+        assert_eq!(specs[1].working_dir, Some(String::from("C:\\proxy")));
+        assert_eq!(specs[1].restart, None);
+
+        assert!(build_group(vec![spec("web", "a.exe"), spec("web", "b.exe")], &[], &[]).is_err());
+        assert!(
+            build_group(
+                vec![spec("web", "a.exe")],
+                &[],
+                &["api=never".parse().unwrap()]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_independent_mode() {
+        let restart = RestartDecision::Restart(Duration::from_secs(1));
+        assert_eq!(
+            on_process_exit(GroupMode::Independent, restart, true),
+            GroupAction::Restart(Duration::from_secs(1))
+        );
+        assert_eq!(
+            on_process_exit(GroupMode::Independent, RestartDecision::Stop, true),
+            GroupAction::LeaveStopped
+        );
+        // the last process standing takes the service down with it
+        assert_eq!(
+            on_process_exit(GroupMode::Independent, RestartDecision::Stop, false),
+            GroupAction::StopAll
+        );
+    }
+
+    #[test]
+    fn test_restart_all_mode() {
+        let restart = RestartDecision::Restart(Duration::from_secs(2));
+        assert_eq!(
+            on_process_exit(GroupMode::RestartAll, restart, true),
+            GroupAction::RestartAll(Duration::from_secs(2))
+        );
+        assert_eq!(
+            on_process_exit(GroupMode::RestartAll, RestartDecision::Stop, true),
+            GroupAction::StopAll
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::pkg::runner::{RunOptions, run_with_timeout};
+
+/// Points of the service lifecycle where a hook command can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before the child process is (re)started
+    PreStart,
+    /// After the child process has been started
+    PostStart,
+    /// Before the child process is asked to stop
+    PreStop,
+    /// After the child process has exited
+    PostStop,
+    /// After the child process has exited with a failure
+    OnFailure,
+}
+
+impl fmt::Display for HookPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookPoint::PreStart => write!(f, "pre-start"),
+            HookPoint::PostStart => write!(f, "post-start"),
+            HookPoint::PreStop => write!(f, "pre-stop"),
+            HookPoint::PostStop => write!(f, "post-stop"),
+            HookPoint::OnFailure => write!(f, "on-failure"),
+        }
+    }
+}
+
+/// Environment variable holding the lifecycle point the hook runs for
+pub const ENV_HOOK: &str = "WSW_HOOK";
+/// Environment variable holding the exit code of the child (on-failure only)
+pub const ENV_EXIT_CODE: &str = "WSW_EXIT_CODE";
+/// Environment variable holding the number of consecutive restarts (on-failure only)
+pub const ENV_RESTART_COUNT: &str = "WSW_RESTART_COUNT";
+
+/// Commands run at the lifecycle points of the wrapped process
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub pre_start: Option<String>,
+    pub post_start: Option<String>,
+    pub pre_stop: Option<String>,
+    pub post_stop: Option<String>,
+    pub on_failure: Option<String>,
+    /// Hooks still running after the timeout are killed and considered failed
+    pub timeout: Duration,
+}
+
+impl Hooks {
+    pub fn command(&self, point: HookPoint) -> Option<&str> {
+        match point {
+            HookPoint::PreStart => self.pre_start.as_deref(),
+            HookPoint::PostStart => self.post_start.as_deref(),
+            HookPoint::PreStop => self.pre_stop.as_deref(),
+            HookPoint::PostStop => self.post_stop.as_deref(),
+            HookPoint::OnFailure => self.on_failure.as_deref(),
+        }
+    }
+
+    /// Runs the hook configured for `point`, if any, and waits for it to exit.
+    /// `options` are the ones of the child, so that the hook shares its working
+    /// directory, log capture and shell. `env` is added to the hook environment.
+    pub fn run(
+        &self,
+        point: HookPoint,
+        options: &RunOptions,
+        env: &[(&str, String)],
+    ) -> Result<(), String> {
+        let Some(cmdline) = self.command(point) else {
+            return Ok(());
+        };
+        info!("Running {} hook: {}", point, cmdline);
+
+        let mut options = RunOptions {
+            working_dir: options.working_dir.clone(),
+            disable_logs: options.disable_logs,
+            shell: options.shell,
+            ..Default::default()
+        };
+        options.env.push((ENV_HOOK.to_string(), point.to_string()));
+        options
+            .env
+            .extend(env.iter().map(|(k, v)| (k.to_string(), v.clone())));
+
+        let result = match run_with_timeout(cmdline, options, self.timeout) {
+            Ok(Some(status)) if status.success() => Ok(()),
+            Ok(Some(status)) => Err(format!("exited with {}", status)),
+            Ok(None) => Err(format!("timed out after {:?}", self.timeout)),
+            Err(e) => Err(format!("failed to run: {}", e)),
+        };
+        match &result {
+            Ok(_) => info!("The {} hook completed", point),
+            Err(reason) => error!("The {} hook failed: {}", point, reason),
+        }
+        result
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn options() -> RunOptions {
+        RunOptions {
+            working_dir: Some(std::env::temp_dir().to_string_lossy().to_string()),
+            disable_logs: true,
+            ..Default::default()
+        }
+    }
+
+    fn hooks() -> Hooks {
+        Hooks {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_missing_hook_succeeds() {
+        assert_eq!(hooks().run(HookPoint::PreStart, &options(), &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_hook_failure() {
+        let hooks = Hooks {
+            pre_start: Some(String::from("exit 4")),
+            ..hooks()
+        };
+        assert_eq!(
+            hooks.run(HookPoint::PreStart, &options(), &[]),
+            Err(String::from("exited with exit status: 4"))
+        );
+    }
+
+    #[test]
+    fn test_hook_uses_the_child_shell() {
+        let hooks = Hooks {
+            pre_start: Some(String::from(r#"/bin/sh -c "exit $0" 7"#)),
+            ..hooks()
+        };
+        let options = RunOptions {
+            shell: crate::pkg::shell::Shell::None,
+            ..options()
+        };
+        // through sh -c, "$0" would be expanded before it reaches the inner shell
+        assert_eq!(
+            hooks.run(HookPoint::PreStart, &options, &[]),
+            Err(String::from("exited with exit status: 7"))
+        );
+    }
+
+    #[test]
+    fn test_hook_timeout() {
+        let hooks = Hooks {
+            post_stop: Some(String::from("sleep 5")),
+            timeout: Duration::from_millis(200),
+            ..hooks()
+        };
+        assert_eq!(
+            hooks.run(HookPoint::PostStop, &options(), &[]),
+            Err(String::from("timed out after 200ms"))
+        );
+    }
+
+    #[test]
+    fn test_on_failure_hook_environment() {
+        let out = std::env::temp_dir().join(format!("wsw-hook-{}.txt", std::process::id()));
+        let hooks = Hooks {
+            on_failure: Some(format!(
+                "echo $WSW_HOOK $WSW_EXIT_CODE $WSW_RESTART_COUNT > {}",
+                out.display()
+            )),
+            ..hooks()
+        };
+        let env = [
+            (ENV_EXIT_CODE, String::from("3")),
+            (ENV_RESTART_COUNT, String::from("2")),
+        ];
+        assert_eq!(hooks.run(HookPoint::OnFailure, &options(), &env), Ok(()));
+        assert_eq!(fs::read_to_string(&out).unwrap(), "on-failure 3 2\n");
+        let _ = fs::remove_file(out);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Scheduling priority of the child process tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityClass {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+}
+
+impl PriorityClass {
+    /// Nice value with the closest meaning on Unix
+    #[cfg(unix)]
+    pub fn nice(&self) -> i32 {
+        match self {
+            PriorityClass::Idle => 19,
+            PriorityClass::BelowNormal => 10,
+            PriorityClass::Normal => 0,
+            PriorityClass::AboveNormal => -5,
+            PriorityClass::High => -10,
+        }
+    }
+}
+
+impl fmt::Display for PriorityClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriorityClass::Idle => write!(f, "idle"),
+            PriorityClass::BelowNormal => write!(f, "below-normal"),
+            PriorityClass::Normal => write!(f, "normal"),
+            PriorityClass::AboveNormal => write!(f, "above-normal"),
+            PriorityClass::High => write!(f, "high"),
+        }
+    }
+}
+
+impl FromStr for PriorityClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "idle" => Ok(PriorityClass::Idle),
+            "below-normal" => Ok(PriorityClass::BelowNormal),
+            "normal" => Ok(PriorityClass::Normal),
+            "above-normal" => Ok(PriorityClass::AboveNormal),
+            "high" => Ok(PriorityClass::High),
+            _ => Err(format!("Invalid priority class: {}", s)),
+        }
+    }
+}
+
+/// Limits applied to the child process tree.
+/// On Windows they are enforced by the Job Object holding the tree.
+/// On Unix the per process ones are mapped to rlimits and the priority
+/// to the nice value, the others need cgroups and are not supported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Memory committed by the whole process tree, in bytes
+    pub job_memory: Option<u64>,
+    /// Memory committed by any single process of the tree, in bytes
+    pub process_memory: Option<u64>,
+    /// Share of the total CPU time the tree can use, in percent
+    pub cpu_rate: Option<u32>,
+    /// Number of processes that can run at the same time in the tree
+    pub active_processes: Option<u32>,
+    pub priority: Option<PriorityClass>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == ResourceLimits::default()
+    }
+}
+
+/// Limit the child process tree ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(windows), allow(dead_code))] // only the Windows Job Object reports them
+pub enum LimitViolation {
+    JobMemory,
+    ProcessMemory,
+    ActiveProcesses,
+}
+
+impl LimitViolation {
+    /// Memory limits make allocations fail, which usually takes the process down
+    pub fn is_memory(&self) -> bool {
+        matches!(
+            self,
+            LimitViolation::JobMemory | LimitViolation::ProcessMemory
+        )
+    }
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitViolation::JobMemory => write!(f, "memory limit"),
+            LimitViolation::ProcessMemory => write!(f, "process memory limit"),
+            LimitViolation::ActiveProcesses => write!(f, "process count limit"),
+        }
+    }
+}
+
+const UNITS: [(&str, u64); 4] = [("g", 1 << 30), ("m", 1 << 20), ("k", 1 << 10), ("", 1)];
+
+/// Parses a memory size like `512M`, `2G` or `640K`. The units are powers of 1024,
+/// an optional trailing `B` is accepted. A bare number is interpreted as bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let number = lower.strip_suffix('b').unwrap_or(&lower);
+    let split = number
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(number.len());
+    let (value, unit) = number.split_at(split);
+    let value: u64 = value.parse().map_err(|_| format!("Invalid size: {}", s))?;
+    let multiplier = UNITS
+        .iter()
+        .find(|(name, _)| *name == unit.trim())
+        .map(|(_, multiplier)| *multiplier)
+        .ok_or_else(|| format!("Invalid size unit: {}", s))?;
+    match value.checked_mul(multiplier) {
+        Some(0) | None => Err(format!("Invalid size: {}", s)),
+        Some(size) => Ok(size),
+    }
+}
+
+/// Formats a size so that it can be parsed back by `parse_size`
+#[cfg(any(windows, test))]
+pub fn format_size(size: u64) -> String {
+    let (unit, multiplier) = UNITS
+        .iter()
+        .find(|(_, multiplier)| size.is_multiple_of(*multiplier))
+        .unwrap_or(&("", 1));
+    format!("{}{}", size / multiplier, unit.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("2gb"), Ok(2 << 30));
+        assert_eq!(parse_size("640K"), Ok(640 << 10));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("0M").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("12T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_size_round_trip() {
+        for size in ["512M", "2G", "640K", "1000"] {
+            assert_eq!(format_size(parse_size(size).unwrap()), size);
+        }
+        assert_eq!(format_size(1536 << 10), "1536K");
+    }
+
+    #[test]
+    fn test_priority_round_trip() {
+        for class in ["idle", "below-normal", "normal", "above-normal", "high"] {
+            assert_eq!(class.parse::<PriorityClass>().unwrap().to_string(), class);
+        }
+        assert!("realtime".parse::<PriorityClass>().is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use tracing::{error, info, warn};
+
+use crate::pkg::events::Waker;
+use crate::pkg::net::{HttpUrl, http_get_status, tcp_connect};
+use crate::pkg::runner::{RunOptions, run_with_timeout};
+use crate::pkg::shell::Shell;
+use crate::pkg::state::StateFile;
+
+/// Check that tells if the wrapped process is alive and responsive
+#[derive(Debug, Clone)]
+pub enum Probe {
+    /// The port accepts connections (tcp:host:port)
+    Tcp(String),
+    /// The url answers with the expected status (http://host:port/path)
+    Http(HttpUrl),
+    /// The command exits with a zero exit code (exec:command)
+    Exec(String),
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Tcp(addr) => write!(f, "tcp:{}", addr),
+            Probe::Http(url) => write!(f, "http://{}:{}{}", url.host, url.port, url.path),
+            Probe::Exec(cmd) => write!(f, "exec:{}", cmd),
+        }
+    }
+}
+
+impl FromStr for Probe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(Probe::Tcp(addr.to_string()))
+        } else if s.starts_with("http://") {
+            Ok(Probe::Http(HttpUrl::parse(s)?))
+        } else if let Some(cmd) = s.strip_prefix("exec:") {
+            Ok(Probe::Exec(cmd.to_string()))
+        } else {
+            Err(format!(
+                "Invalid probe: {} (expected tcp:host:port, http://host:port/path or exec:command)",
+                s
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    pub probe: Probe,
+    /// Expected HTTP status. Any 2xx status if not set
+    pub expected_status: Option<u16>,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Consecutive failures after which the child is considered unhealthy
+    pub threshold: u32,
+    /// How an exec probe is launched, like the child
+    pub shell: Shell,
+}
+
+impl ProbeConfig {
+    /// Runs the probe once
+    pub fn run(&self, working_dir: Option<String>) -> Result<(), String> {
+        match &self.probe {
+            Probe::Tcp(addr) => tcp_connect(addr, self.timeout)
+                .map(|_| ())
+                .map_err(|e| format!("connection to {} failed: {}", addr, e)),
+            Probe::Http(url) => match http_get_status(url, self.timeout) {
+                Ok(code)
+                    if self
+                        .expected_status
+                        .map_or((200..300).contains(&code), |s| s == code) =>
+                {
+                    Ok(())
+                }
+                Ok(code) => Err(format!("unexpected HTTP status {}", code)),
+                Err(e) => Err(format!("HTTP request failed: {}", e)),
+            },
+            Probe::Exec(cmd) => {
+                let options = RunOptions {
+                    working_dir,
+                    disable_logs: true,
+                    shell: self.shell,
+                    ..Default::default()
+                };
+                match run_with_timeout(cmd, options, self.timeout) {
+                    Ok(Some(status)) if status.success() => Ok(()),
+                    Ok(Some(status)) => Err(format!("command exited with {}", status)),
+                    Ok(None) => Err(format!("command timed out after {:?}", self.timeout)),
+                    Err(e) => Err(format!("failed to run command: {}", e)),
+                }
+            }
+        }
+    }
+}
+
+/// Counts consecutive probe failures
+pub struct ProbeTracker {
+    threshold: u32,
+    failures: u32,
+}
+
+impl ProbeTracker {
+    pub fn new(threshold: u32) -> Self {
+        ProbeTracker {
+            threshold: threshold.max(1),
+            failures: 0,
+        }
+    }
+
+    /// Records a probe result. Returns true once the failure threshold is reached
+    pub fn record(&mut self, result: &Result<(), String>) -> bool {
+        match result {
+            Ok(_) => self.failures = 0,
+            Err(_) => self.failures += 1,
+        }
+        self.failures >= self.threshold
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+/// Periodically probes the child on a background thread.
+/// `wake` is called once the child is found unhealthy
+pub struct LivenessMonitor {
+    unhealthy: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl LivenessMonitor {
+    pub fn start(
+        config: ProbeConfig,
+        working_dir: Option<String>,
+        state: StateFile,
+        wake: Waker,
+    ) -> Self {
+        let unhealthy = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let unhealthy_bg = unhealthy.clone();
+        let stop_bg = stop.clone();
+        thread::spawn(move || {
+            let mut tracker = ProbeTracker::new(config.threshold);
+            state.set("Liveness", format!("starting ({})", config.probe));
+            loop {
+                let deadline = Instant::now() + config.interval;
+                while Instant::now() < deadline {
+                    if stop_bg.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+
+                let result = config.run(working_dir.clone());
+                let was_failing = tracker.failures() > 0;
+                let threshold_reached = tracker.record(&result);
+                if stop_bg.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+                match &result {
+                    Ok(_) => {
+                        if was_failing {
+                            info!("Liveness probe succeeded");
+                        }
+                        state.set("Liveness", format!("healthy ({})", config.probe));
+                    }
+                    Err(reason) => {
+                        warn!(
+                            "Liveness probe failed ({}/{}): {}",
+                            tracker.failures(),
+                            config.threshold,
+                            reason
+                        );
+                        state.set(
+                            "Liveness",
+                            format!(
+                                "failing {}/{} ({})",
+                                tracker.failures(),
+                                config.threshold,
+                                config.probe
+                            ),
+                        );
+                        state.set("Liveness Last Failure", format!("{} {}", now, reason));
+                    }
+                }
+                state.set("Liveness Last Check", now.to_string());
+
+                if threshold_reached {
+                    error!(
+                        "Liveness probe failed {} times in a row: the child process is unhealthy",
+                        tracker.failures()
+                    );
+                    state.set("Liveness", format!("unhealthy ({})", config.probe));
+                    unhealthy_bg.store(true, Ordering::SeqCst);
+                    wake();
+                    return;
+                }
+            }
+        });
+
+        LivenessMonitor { unhealthy, stop }
+    }
+
+    pub fn is_unhealthy(&self) -> bool {
+        self.unhealthy.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for LivenessMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkg::net::tests::serve_http;
+    use std::net::TcpListener;
+
+    fn config(probe: &str) -> ProbeConfig {
+        ProbeConfig {
+            probe: probe.parse().unwrap(),
+            expected_status: None,
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_secs(2),
+            threshold: 3,
+            shell: Shell::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        assert!(matches!("tcp:localhost:80".parse(), Ok(Probe::Tcp(_))));
+        assert!(matches!(
+            "http://localhost/health".parse(),
+            Ok(Probe::Http(_))
+        ));
+        assert!(
+            matches!("exec:check.bat --quick".parse(), Ok(Probe::Exec(cmd)) if cmd == "check.bat --quick")
+        );
+        assert!("ping:localhost".parse::<Probe>().is_err());
+    }
+
+    #[test]
+    fn test_tracker_threshold() {
+        let mut tracker = ProbeTracker::new(3);
+        let failure = Err(String::from("boom"));
+        assert!(!tracker.record(&failure));
+        assert!(!tracker.record(&failure));
+        // a success resets the counter
+        assert!(!tracker.record(&Ok(())));
+        assert!(!tracker.record(&failure));
+        assert!(!tracker.record(&failure));
+        assert!(tracker.record(&failure));
+        assert_eq!(tracker.failures(), 3);
+    }
+
+    #[test]
+    fn test_tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let probe = config(&format!("tcp:{}", listener.local_addr().unwrap()));
+        assert_eq!(probe.run(None), Ok(()));
+        drop(listener);
+        assert!(probe.run(None).is_err());
+    }
+
+    #[test]
+    fn test_http_probe_expected_status() {
+        let mut probe = config(&format!("http://{}/", serve_http(401)));
+        assert_eq!(
+            probe.run(None),
+            Err(String::from("unexpected HTTP status 401"))
+        );
+
+        probe.expected_status = Some(401);
+        assert_eq!(probe.run(None), Ok(()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_probe() {
+        assert!(config("exec:true").run(Some(String::from("/"))).is_ok());
+        assert!(config("exec:exit 3").run(Some(String::from("/"))).is_err());
+
+        let mut slow = config("exec:sleep 5");
+        slow.timeout = Duration::from_millis(200);
+        assert_eq!(
+            slow.run(Some(String::from("/"))),
+            Err(String::from("command timed out after 200ms"))
+        );
+    }
+
+    #[test]
+    fn test_monitor_reports_unhealthy_child() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let probe = config(&format!("tcp:{}", listener.local_addr().unwrap()));
+        drop(listener);
+
+        let path = std::env::temp_dir().join(format!("wsw-liveness-{}.state", std::process::id()));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let monitor = LivenessMonitor::start(
+            probe,
+            None,
+            StateFile::at(path.clone()),
+            Arc::new(move || {
+                let _ = tx.send(());
+            }),
+        );
+        assert!(!monitor.is_unhealthy());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(monitor.is_unhealthy());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,20 +1,50 @@
+#[cfg(windows)]
 use chrono::Local;
 use encoding_rs::{Encoding, WINDOWS_1252};
 use std::io::{self, Write};
-use tracing_subscriber::fmt::format::Writer;
-use tracing_subscriber::fmt::time::FormatTime;
+use std::sync::Arc;
+#[cfg(windows)]
+use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
 
 use crate::pkg::logs::SERVICE_LOG_PREFIX;
 
+#[cfg(windows)]
 pub struct LocalTimer;
 
+#[cfg(windows)]
 impl FormatTime for LocalTimer {
     fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
         write!(w, "{}", Local::now().format("%Y-%m-%d %H:%M:%S"))
     }
 }
 
-pub struct LogWriter;
+/// Callback invoked with every line captured from the child output
+pub type LineObserver = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Combines two optional observers, so that both see every line
+pub fn chain_observers(
+    first: Option<LineObserver>,
+    second: Option<LineObserver>,
+) -> Option<LineObserver> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(Arc::new(move |line: &str| {
+            first(line);
+            second(line);
+        })),
+        (first, second) => first.or(second),
+    }
+}
+
+/// Longest partial line kept while waiting for its end
+const MAX_PENDING_LINE: usize = 64 * 1024;
+
+pub struct LogWriter {
+    observer: Option<LineObserver>,
+    /// Prepended to every line as "[tag] "
+    tag: Option<String>,
+    /// The last line written so far, until its end comes in a later chunk
+    pending: String,
+}
 
 impl Write for LogWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -22,11 +52,19 @@ impl Write for LogWriter {
 
         match decoded {
             Some(text) => {
-                for line in text.lines() {
-                    if !line.is_empty() {
-                        tracing::info!("{}{}", SERVICE_LOG_PREFIX, line);
+                // a read from the pipe can end in the middle of a line
+                self.pending.push_str(&text);
+                if let Some(end) = self.pending.rfind('\n') {
+                    let rest = self.pending.split_off(end + 1);
+                    let complete = std::mem::replace(&mut self.pending, rest);
+                    for line in complete.lines() {
+                        self.emit(line);
                     }
                 }
+                if self.pending.len() > MAX_PENDING_LINE {
+                    let line = std::mem::take(&mut self.pending);
+                    self.emit(&line);
+                }
             }
             None => {
                 tracing::error!("{}<unreadable data: {:?}>", SERVICE_LOG_PREFIX, buf);
@@ -40,7 +78,37 @@ impl Write for LogWriter {
     }
 }
 
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        // the output ended without a final newline
+        let line = std::mem::take(&mut self.pending);
+        self.emit(&line);
+    }
+}
+
 impl LogWriter {
+    pub fn new(observer: Option<LineObserver>, tag: Option<String>) -> Self {
+        LogWriter {
+            observer,
+            tag,
+            pending: String::new(),
+        }
+    }
+
+    fn emit(&self, line: &str) {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            return;
+        }
+        match &self.tag {
+            Some(tag) => tracing::info!("{}[{}] {}", SERVICE_LOG_PREFIX, tag, line),
+            None => tracing::info!("{}{}", SERVICE_LOG_PREFIX, line),
+        }
+        if let Some(observer) = &self.observer {
+            observer(line);
+        }
+    }
+
     fn try_decode(buf: &[u8]) -> Option<String> {
         // 1. Try UTF-8
         if let Ok(s) = std::str::from_utf8(buf) {
@@ -48,7 +116,7 @@ impl LogWriter {
         }
 
         // 2. Try UTF-16LE (only if even length)
-        if buf.len() % 2 == 0 {
+        if buf.len().is_multiple_of(2) {
             let utf16: Vec<u16> = buf
                 .chunks(2)
                 .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
@@ -76,3 +144,31 @@ impl LogWriter {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_lines_split_across_writes() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let seen = lines.clone();
+        let observer: LineObserver = Arc::new(move |line: &str| {
+            seen.lock().unwrap().push(line.to_string());
+        });
+        let mut writer = LogWriter::new(Some(observer), None);
+        writer.write_all(b"Server li").unwrap();
+        assert!(lines.lock().unwrap().is_empty());
+        writer
+            .write_all(b"stening on 8080\r\nfirst\n\nsec")
+            .unwrap();
+        writer.write_all(b"ond\nno newline").unwrap();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["Server listening on 8080", "first", "second"]
+        );
+        drop(writer);
+        assert_eq!(lines.lock().unwrap().last().unwrap(), "no newline");
+    }
+}
@@ -1,21 +1,84 @@
+#[cfg(windows)]
 use tracing_appender::non_blocking::WorkerGuard;
 
 use std::env;
+use std::io::{self, Write};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+#[cfg(windows)]
 use tracing::info;
 
-use tracing_appender::rolling;
-use tracing_subscriber::EnvFilter;
-use tracing_subscriber::fmt;
-use tracing_subscriber::{Registry, layer::SubscriberExt};
+use tracing_appender::rolling::{self, RollingFileAppender};
+#[cfg(windows)]
+use tracing_subscriber::{EnvFilter, Registry, fmt, layer::SubscriberExt};
 
 use crate::cli::LogRotation;
+#[cfg(windows)]
 use crate::pkg::log_writer::LocalTimer;
 
 pub const SERVICE_LOG_PREFIX: &str = "|SVC-LOG| ";
 
+/// Guard of the log file writer. The service thread can't own it, so it is
+/// kept here until `flush_logs` is called
+#[cfg(windows)]
+static LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// The log file currently in use, kept so that it can be reopened
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+struct LogFile {
+    writer: ReopenableWriter,
+    name: String,
+    log_rotation: LogRotation,
+    max_log_files: usize,
+}
+
+/// Writes to a rolling log file that can be swapped at any time
+#[derive(Clone)]
+struct ReopenableWriter(Arc<Mutex<RollingFileAppender>>);
+
+impl Write for ReopenableWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+fn build_file_appender(
+    name: &str,
+    log_rotation: LogRotation,
+    max_log_files: usize,
+) -> io::Result<RollingFileAppender> {
+    rolling::Builder::new()
+        .filename_prefix(get_log_filename_prefix(name))
+        .rotation(log_rotation.into())
+        .max_log_files(max_log_files)
+        .build(get_log_dir())
+        .map_err(io::Error::other)
+}
+
+/// Closes and reopens the log file, e.g. after an external tool moved it away
+pub fn reopen_logs() -> io::Result<()> {
+    let log_file = LOG_FILE.lock().unwrap();
+    let Some(log_file) = log_file.as_ref() else {
+        return Ok(());
+    };
+    let appender = build_file_appender(
+        &log_file.name,
+        log_file.log_rotation.clone(),
+        log_file.max_log_files,
+    )?;
+    let mut current = log_file.writer.0.lock().unwrap();
+    let _ = current.flush();
+    *current = appender;
+    Ok(())
+}
+
 pub fn get_log_dir() -> PathBuf {
-    let log_path = match env::var("PROGRAMDATA") {
+    match env::var("PROGRAMDATA") {
         Ok(path) => {
             let log_path = PathBuf::from(path).join("wsw").join("logs");
             std::fs::create_dir_all(&log_path).unwrap_or_else(|_| {
@@ -38,24 +101,40 @@ pub fn get_log_dir() -> PathBuf {
             };
             log_path
         }
-    };
-    log_path
+    }
 }
 
 pub fn get_log_filename_prefix(name: &str) -> String {
     format!("{}.log", name)
 }
 
+/// Keeps the guard returned by `setup_logging` alive until `flush_logs`
+#[cfg(windows)]
+pub fn keep_log_guard(guard: WorkerGuard) {
+    *LOG_GUARD.lock().unwrap() = Some(guard);
+}
+
+/// Writes the pending log lines to the log file. Lines logged afterwards are lost,
+/// so this must be called right before the process exits: once the service
+/// reports itself stopped Windows may kill it at any time, e.g. during a shutdown
+#[cfg(windows)]
+pub fn flush_logs() {
+    drop(LOG_GUARD.lock().unwrap().take());
+}
+
+#[cfg(windows)]
 pub fn setup_logging(name: &str, log_rotation: LogRotation, max_log_files: usize) -> WorkerGuard {
     let log_path = get_log_dir();
 
-    let file_appender = rolling::Builder::new()
-        .filename_prefix(get_log_filename_prefix(name))
-        .rotation(log_rotation.into())
-        .max_log_files(max_log_files)
-        .build(&log_path)
-        .unwrap();
-    let (non_blocking_file, guard) = tracing_appender::non_blocking(file_appender); // Set up logging here if needed
+    let file_appender = build_file_appender(name, log_rotation.clone(), max_log_files).unwrap();
+    let writer = ReopenableWriter(Arc::new(Mutex::new(file_appender)));
+    *LOG_FILE.lock().unwrap() = Some(LogFile {
+        writer: writer.clone(),
+        name: name.to_string(),
+        log_rotation,
+        max_log_files,
+    });
+    let (non_blocking_file, guard) = tracing_appender::non_blocking(writer); // Set up logging here if needed
 
     // Console layer (stderr by default, can also write to stdout)
     let console_layer = fmt::layer()
@@ -0,0 +1,282 @@
+use std::io;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+use crate::pkg::duration::format_elapsed;
+use crate::pkg::state::StateFile;
+
+/// How often the usage is published for "wsw status"
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resource usage of a process tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Resident memory (the working set on Windows), in bytes
+    pub memory: u64,
+    /// User and kernel CPU time consumed so far
+    pub cpu_time: Duration,
+    /// Number of running processes
+    pub processes: u32,
+    /// Bytes read and written by the processes, all kinds of I/O included
+    pub io_read: u64,
+    pub io_write: u64,
+    /// Open handles (file descriptors on Unix)
+    pub handles: u64,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.memory += other.memory;
+        self.cpu_time += other.cpu_time;
+        self.processes += other.processes;
+        self.io_read += other.io_read;
+        self.io_write += other.io_write;
+        self.handles += other.handles;
+    }
+}
+
+/// Something whose resource usage can be sampled, like a child process tree
+pub trait UsageSource {
+    fn usage(&self) -> io::Result<Usage>;
+}
+
+/// The fields of /proc/<pid>/stat we care about
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+struct ProcStat {
+    pgrp: u32,
+    /// utime + stime, in clock ticks
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn parse_stat(content: &str) -> Option<ProcStat> {
+    // the command name is enclosed in parentheses and may contain spaces,
+    // the fields are counted from the state which follows it
+    let fields: Vec<&str> = content[content.rfind(')')? + 1..]
+        .split_whitespace()
+        .collect();
+    let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+    Some(ProcStat {
+        pgrp: field(2)? as u32,
+        cpu_ticks: field(11)? + field(12)?,
+        rss_pages: field(21)?,
+    })
+}
+
+/// Reads the "rchar" and "wchar" counters of /proc/<pid>/io
+#[cfg(target_os = "linux")]
+fn parse_io(content: &str) -> (u64, u64) {
+    let counter = |name: &str| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0)
+    };
+    (counter("rchar"), counter("wchar"))
+}
+
+/// Sums the usage of the processes of a process group, read from /proc
+#[cfg(target_os = "linux")]
+pub fn process_group_usage(pgid: u32) -> io::Result<Usage> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    let mut usage = Usage::default();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let is_process = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_process {
+            continue;
+        }
+        // the process may exit while it is being looked at
+        let Ok(content) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some(stat) = parse_stat(&content)
+            && stat.pgrp == pgid
+        {
+            usage.memory += stat.rss_pages * page_size;
+            usage.cpu_time += Duration::from_millis(stat.cpu_ticks * 1000 / ticks_per_second);
+            usage.processes += 1;
+            // both need the same rights as ptrace, they are skipped when denied
+            if let Ok(content) = std::fs::read_to_string(entry.path().join("io")) {
+                let (read, write) = parse_io(&content);
+                usage.io_read += read;
+                usage.io_write += write;
+            }
+            if let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) {
+                usage.handles += fds.count() as u64;
+            }
+        }
+    }
+    Ok(usage)
+}
+
+/// Formats a number of bytes for humans, e.g. "12.3 MiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Keys of the state file written by `UsagePublisher`
+const USAGE_KEYS: [&str; 8] = [
+    "Uptime",
+    "Processes",
+    "CPU Time",
+    "Memory",
+    "Peak Memory",
+    "I/O Read",
+    "I/O Written",
+    "Handles",
+];
+
+/// Periodically publishes the usage of the process tree in the state file,
+/// so that "wsw status", which runs in a different process, can show it
+#[derive(Default)]
+pub struct UsagePublisher {
+    peak_memory: u64,
+    published_at: Option<Instant>,
+}
+
+impl UsagePublisher {
+    /// When the usage must be published next, `now` if it never was
+    pub fn due_at(&self, now: Instant) -> Instant {
+        self.published_at.map_or(now, |at| at + PUBLISH_INTERVAL)
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.due_at(now)
+    }
+
+    /// `uptime` is None when it makes no sense, e.g. for a process group
+    pub fn publish(
+        &mut self,
+        state: &StateFile,
+        usage: &Usage,
+        uptime: Option<Duration>,
+        now: Instant,
+    ) {
+        self.peak_memory = self.peak_memory.max(usage.memory);
+        self.published_at = Some(now);
+        if let Some(uptime) = uptime {
+            state.set("Uptime", format_elapsed(uptime));
+        }
+        state.set("Processes", usage.processes.to_string());
+        state.set("CPU Time", format_elapsed(usage.cpu_time));
+        state.set("Memory", format_bytes(usage.memory));
+        state.set("Peak Memory", format_bytes(self.peak_memory));
+        state.set("I/O Read", format_bytes(usage.io_read));
+        state.set("I/O Written", format_bytes(usage.io_write));
+        state.set("Handles", usage.handles.to_string());
+    }
+
+    /// Removes the published usage, once there is nothing running anymore
+    pub fn clear(&mut self, state: &StateFile) {
+        *self = UsagePublisher::default();
+        for key in USAGE_KEYS {
+            state.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(850 << 20), "850.0 MiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_usage_publisher() {
+        let path = std::env::temp_dir().join(format!("wsw-usage-{}.state", std::process::id()));
+        let state = StateFile::at(path.clone());
+        state.set("Liveness", "healthy");
+        let mut publisher = UsagePublisher::default();
+        let now = Instant::now();
+        assert!(publisher.is_due(now));
+
+        let mut usage = Usage {
+            memory: 300 << 20,
+            processes: 2,
+            ..Default::default()
+        };
+        publisher.publish(&state, &usage, Some(Duration::from_secs(90)), now);
+        assert!(!publisher.is_due(now + Duration::from_secs(1)));
+        assert_eq!(publisher.due_at(now), now + PUBLISH_INTERVAL);
+        assert!(publisher.is_due(now + PUBLISH_INTERVAL));
+
+        usage.memory = 200 << 20;
+        publisher.publish(&state, &usage, None, now + PUBLISH_INTERVAL);
+        let entries = state.entries();
+        let value = |key: &str| entries.iter().find(|(k, _)| k == key).unwrap().1.clone();
+        assert_eq!(value("Uptime"), "1m 30s");
+        assert_eq!(value("Processes"), "2");
+        assert_eq!(value("Memory"), "200.0 MiB");
+        assert_eq!(value("Peak Memory"), "300.0 MiB");
+
+        publisher.clear(&state);
+        assert_eq!(
+            state.entries(),
+            vec![(String::from("Liveness"), String::from("healthy"))]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod proc_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let content = "4242 (my (odd) app) S 1 4240 4240 0 -1 4194560 1043 0 0 0 \
+                       250 30 0 0 20 0 3 0 123456 104857600 2048 18446744073709551615";
+        assert_eq!(
+            parse_stat(content),
+            Some(ProcStat {
+                pgrp: 4240,
+                cpu_ticks: 280,
+                rss_pages: 2048,
+            })
+        );
+        assert_eq!(parse_stat("4242 (app) S 1"), None);
+        assert_eq!(parse_stat(""), None);
+    }
+
+    #[test]
+    fn test_parse_io() {
+        let content = "rchar: 323934931\nwchar: 323929600\nsyscr: 632687\nsyscw: 632675\n\
+                       read_bytes: 0\nwrite_bytes: 323932160\n";
+        assert_eq!(parse_io(content), (323934931, 323929600));
+        assert_eq!(parse_io(""), (0, 0));
+    }
+
+    #[test]
+    fn test_process_group_usage() {
+        let own_group = unsafe { libc::getpgrp() } as u32;
+        let usage = process_group_usage(own_group).unwrap();
+        assert!(usage.memory > 0);
+        assert!(usage.processes >= 1);
+        assert!(usage.handles > 0);
+
+        assert_eq!(process_group_usage(u32::MAX).unwrap(), Usage::default());
+    }
+}
@@ -1,4 +1,30 @@
+pub mod control;
+#[cfg(any(windows, test))]
+pub mod dependencies;
+pub mod duration;
+pub mod events;
+pub mod exit_action;
+pub mod group;
+pub mod hooks;
+pub mod limits;
+pub mod liveness;
 pub mod log_writer;
 pub mod logs;
+pub mod metrics;
+pub mod net;
+pub mod notify;
+pub mod output_trigger;
+pub mod readiness;
+pub mod recovery;
+pub mod restart;
 pub mod runner;
+pub mod schedule;
+#[cfg(windows)]
 pub mod service;
+pub mod shell;
+pub mod state;
+pub mod status;
+pub mod stop;
+pub mod supervisor;
+pub mod thresholds;
+pub mod watch;
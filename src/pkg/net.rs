@@ -0,0 +1,165 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Opens a TCP connection to `addr` (host:port)
+pub fn tcp_connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid address: {}", addr),
+    );
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// A plain HTTP url, split in its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Parses urls like `http://localhost:8080/health`. Only plain HTTP is supported
+    pub fn parse(url: &str) -> Result<HttpUrl, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Only http:// urls are supported: {}", url))?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in url: {}", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in url: {}", url));
+        }
+
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Performs a GET request and returns the response status code
+pub fn http_get_status(url: &HttpUrl, timeout: Duration) -> io::Result<u16> {
+    let mut stream = tcp_connect(&format!("{}:{}", url.host, url.port), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Send the request in a single write: servers that answer as soon as the
+    // request line arrives would otherwise reset the connection under our feet
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        url.path, url.host
+    );
+    stream.write_all(request.as_bytes())?;
+
+    // Only the status line is needed: "HTTP/1.1 200 OK"
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    while len < buf.len() {
+        let n = stream.read(&mut buf[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if buf[..len].contains(&b'\n') {
+            break;
+        }
+    }
+
+    let line = String::from_utf8_lossy(&buf[..len]);
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid HTTP response: {:?}", line.lines().next()),
+            )
+        })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts a local HTTP server that answers every request with `status`.
+    /// Returns its address
+    pub fn serve_http(status: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n",
+                    status
+                );
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            HttpUrl::parse("http://localhost:8080/health?full=1"),
+            Ok(HttpUrl {
+                host: String::from("localhost"),
+                port: 8080,
+                path: String::from("/health?full=1"),
+            })
+        );
+        assert_eq!(
+            HttpUrl::parse("http://example.com"),
+            Ok(HttpUrl {
+                host: String::from("example.com"),
+                port: 80,
+                path: String::from("/"),
+            })
+        );
+        assert!(HttpUrl::parse("https://example.com").is_err());
+        assert!(HttpUrl::parse("http://:80/").is_err());
+        assert!(HttpUrl::parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_http_get_status() {
+        let addr = serve_http(204);
+        let url = HttpUrl::parse(&format!("http://{}/ready", addr)).unwrap();
+        assert_eq!(http_get_status(&url, Duration::from_secs(2)).unwrap(), 204);
+    }
+
+    #[test]
+    fn test_tcp_connect_refused() {
+        // bind and drop a listener to get a free port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        assert!(tcp_connect(&addr, Duration::from_secs(1)).is_err());
+    }
+}
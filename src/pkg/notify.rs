@@ -0,0 +1,452 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+#[cfg(windows)]
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, ERROR_MORE_DATA, ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE,
+    },
+    Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_INBOUND, ReadFile},
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_MESSAGE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_MESSAGE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    },
+};
+
+use crate::pkg::events::Waker;
+use crate::pkg::state::StateFile;
+
+/// Environment variable holding the address the child sends its notifications to
+pub const ENV_NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// Environment variable holding the watchdog timeout in microseconds
+pub const ENV_WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+
+/// systemd limits a notification message to the same size
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Notification sent by the child, in the systemd notification message format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// READY=1: the child finished starting
+    Ready,
+    /// STOPPING=1: the child is shutting down
+    Stopping,
+    /// WATCHDOG=1: the child is alive
+    Watchdog,
+    /// WATCHDOG=trigger: the child asks to be treated as hung
+    WatchdogTrigger,
+    /// STATUS=...: free form status text
+    Status(String),
+}
+
+/// Parses a notification datagram: newline separated "KEY=VALUE" assignments.
+/// Unknown assignments are ignored, as systemd does
+pub fn parse_notifications(message: &str) -> Vec<Notification> {
+    message
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim_end_matches('\r').split_once('=')?;
+            match (key, value) {
+                ("READY", "1") => Some(Notification::Ready),
+                ("STOPPING", "1") => Some(Notification::Stopping),
+                ("WATCHDOG", "1") => Some(Notification::Watchdog),
+                ("WATCHDOG", "trigger") => Some(Notification::WatchdogTrigger),
+                ("STATUS", status) => Some(Notification::Status(status.to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// What the current child reported so far
+#[derive(Debug, Default)]
+struct ChildState {
+    ready: bool,
+    stopping: bool,
+    last_watchdog: Option<Instant>,
+    watchdog_triggered: bool,
+}
+
+impl ChildState {
+    fn apply(&mut self, notification: &Notification, now: Instant) {
+        match notification {
+            Notification::Ready => {
+                self.ready = true;
+                // the watchdog starts counting once the child is up
+                self.last_watchdog = Some(now);
+            }
+            Notification::Stopping => self.stopping = true,
+            Notification::Watchdog => self.last_watchdog = Some(now),
+            Notification::WatchdogTrigger => self.watchdog_triggered = true,
+            Notification::Status(_) => {}
+        }
+    }
+
+    /// A stopping child is not expected to keep pinging the watchdog
+    fn watchdog_expired(&self, timeout: Duration, now: Instant) -> bool {
+        !self.stopping
+            && (self.watchdog_triggered
+                || self
+                    .last_watchdog
+                    .is_some_and(|at| now.duration_since(at) >= timeout))
+    }
+
+    /// When the watchdog expires if the child stays silent
+    fn watchdog_deadline(&self, timeout: Duration) -> Option<Instant> {
+        if self.stopping {
+            return None;
+        }
+        self.last_watchdog.map(|at| at + timeout)
+    }
+}
+
+/// Applies the messages of the child as they come in
+struct Receiver {
+    state: StateFile,
+    child: Arc<Mutex<ChildState>>,
+    wake: Waker,
+}
+
+impl Receiver {
+    fn receive(&self, message: &[u8]) {
+        let message = String::from_utf8_lossy(message);
+        let mut wake = false;
+        for notification in parse_notifications(&message) {
+            match &notification {
+                Notification::Ready => info!("Child process notified it is ready"),
+                Notification::Stopping => info!("Child process notified it is stopping"),
+                Notification::WatchdogTrigger => warn!("Child process triggered the watchdog"),
+                Notification::Status(status) => self.state.set("Child Status", status),
+                Notification::Watchdog => {}
+            }
+            self.child
+                .lock()
+                .unwrap()
+                .apply(&notification, Instant::now());
+            wake |= matches!(
+                notification,
+                Notification::Ready | Notification::WatchdogTrigger
+            );
+        }
+        // the supervisor sees the whole message at once
+        if wake {
+            (self.wake)();
+        }
+    }
+}
+
+/// Tells apart the listeners of the same process
+static LISTENER_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Receives the notifications of the child on a background thread. Only the account of
+/// the service, and the administrators, can send them: on Windows the address is a local
+/// named pipe with the default permissions, on Unix a datagram socket in a private directory.
+/// `wake` is called when the child is ready or triggers the watchdog
+pub struct NotifyListener {
+    address: String,
+    child: Arc<Mutex<ChildState>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl NotifyListener {
+    pub fn start(state: StateFile, wake: Waker) -> io::Result<Self> {
+        let child = Arc::new(Mutex::new(ChildState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let receiver = Receiver {
+            state,
+            child: child.clone(),
+            wake,
+        };
+        let name = format!(
+            "wsw-notify-{}-{}",
+            std::process::id(),
+            LISTENER_ID.fetch_add(1, Ordering::SeqCst)
+        );
+        let address = listen(&name, receiver, stop.clone())?;
+        Ok(NotifyListener {
+            address,
+            child,
+            stop,
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Environment variables telling the child where to send its notifications
+    pub fn env(&self, watchdog_timeout: Option<Duration>) -> Vec<(String, String)> {
+        let mut env = vec![(ENV_NOTIFY_SOCKET.to_string(), self.address.clone())];
+        if let Some(timeout) = watchdog_timeout {
+            env.push((
+                ENV_WATCHDOG_USEC.to_string(),
+                timeout.as_micros().to_string(),
+            ));
+        }
+        env
+    }
+
+    /// Must be called before a new child is started, so that it starts
+    /// with a clean slate. Its watchdog counts from now
+    pub fn child_started(&self) {
+        *self.child.lock().unwrap() = ChildState {
+            last_watchdog: Some(Instant::now()),
+            ..Default::default()
+        };
+    }
+
+    /// Restarts the watchdog countdown, e.g. after the child was paused
+    pub fn reset_watchdog(&self) {
+        self.child.lock().unwrap().last_watchdog = Some(Instant::now());
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.child.lock().unwrap().ready
+    }
+
+    pub fn watchdog_deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.child.lock().unwrap().watchdog_deadline(timeout)
+    }
+
+    pub fn watchdog_expired(&self, timeout: Duration) -> bool {
+        self.child
+            .lock()
+            .unwrap()
+            .watchdog_expired(timeout, Instant::now())
+    }
+}
+
+impl Drop for NotifyListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // the pipe server is waiting for a client: be the last one
+        #[cfg(windows)]
+        let _ = std::fs::OpenOptions::new().write(true).open(&self.address);
+    }
+}
+
+/// Listens on a datagram socket, which only the owner of its directory can reach.
+/// Returns the socket path
+#[cfg(unix)]
+fn listen(name: &str, receiver: Receiver, stop: Arc<AtomicBool>) -> io::Result<String> {
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let dir = std::env::temp_dir().join(name);
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let path = dir.join("notify.sock");
+    let socket = UnixDatagram::bind(&path)?;
+    // wake up regularly to notice when the listener is dropped
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+    thread::spawn(move || {
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        while !stop.load(Ordering::SeqCst) {
+            if let Ok(len) = socket.recv(&mut buffer) {
+                receiver.receive(&buffer[..len]);
+            }
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    });
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Listens on a local named pipe in message mode, so that every write is a notification.
+/// Its default permissions only let the owner, LocalSystem and the administrators write.
+/// Returns the pipe path
+#[cfg(windows)]
+fn listen(name: &str, receiver: Receiver, stop: Arc<AtomicBool>) -> io::Result<String> {
+    let path = format!(r"\\.\pipe\{}", name);
+    let wide: Vec<u16> = path.encode_utf16().chain([0]).collect();
+    // the first instance fails if someone else already serves the name
+    let mut pipe = create_pipe(&wide, FILE_FLAG_FIRST_PIPE_INSTANCE)? as usize;
+    let receiver = Arc::new(receiver);
+    thread::spawn(move || {
+        loop {
+            let connected = unsafe { ConnectNamedPipe(pipe as HANDLE, std::ptr::null_mut()) } != 0
+                || io::Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32);
+            if stop.load(Ordering::SeqCst) {
+                unsafe { CloseHandle(pipe as HANDLE) };
+                break;
+            }
+            if connected {
+                // the client is served by its own thread, the next one by a new instance
+                let receiver = receiver.clone();
+                let client = pipe;
+                thread::spawn(move || read_messages(client as HANDLE, &receiver));
+            } else {
+                unsafe { CloseHandle(pipe as HANDLE) };
+            }
+            pipe = match create_pipe(&wide, 0) {
+                Ok(next) => next as usize,
+                Err(e) => {
+                    warn!("Failed to open the notification pipe: {}", e);
+                    break;
+                }
+            };
+        }
+    });
+    Ok(path)
+}
+
+#[cfg(windows)]
+fn create_pipe(path: &[u16], flags: u32) -> io::Result<HANDLE> {
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            PIPE_ACCESS_INBOUND | flags,
+            PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            0,
+            MAX_MESSAGE_SIZE as u32,
+            0,
+            // the default security descriptor
+            std::ptr::null(),
+        )
+    };
+    if pipe == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pipe)
+}
+
+/// Reads the messages of a connected client until it goes away
+#[cfg(windows)]
+fn read_messages(pipe: HANDLE, receiver: &Receiver) {
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    // the rest of a message longer than the buffer is dropped, as systemd does
+    let mut truncated = false;
+    loop {
+        let mut len = 0u32;
+        let ok = unsafe {
+            ReadFile(
+                pipe,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut len,
+                std::ptr::null_mut(),
+            )
+        } != 0;
+        let more = !ok && io::Error::last_os_error().raw_os_error() == Some(ERROR_MORE_DATA as i32);
+        if !ok && !more {
+            break;
+        }
+        if !truncated {
+            receiver.receive(&buffer[..len as usize]);
+        }
+        truncated = more;
+    }
+    unsafe { CloseHandle(pipe) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notifications() {
+        assert_eq!(
+            parse_notifications("READY=1\nSTATUS=processing queue\nMAINPID=42\n"),
+            vec![
+                Notification::Ready,
+                Notification::Status(String::from("processing queue")),
+            ]
+        );
+        assert_eq!(
+            parse_notifications("WATCHDOG=1\r\nWATCHDOG=trigger\r\nSTOPPING=1"),
+            vec![
+                Notification::Watchdog,
+                Notification::WatchdogTrigger,
+                Notification::Stopping,
+            ]
+        );
+        assert_eq!(parse_notifications("READY=0\nready=1\ngarbage"), vec![]);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let timeout = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut child = ChildState {
+            last_watchdog: Some(start),
+            ..Default::default()
+        };
+        assert!(!child.watchdog_expired(timeout, start + Duration::from_secs(5)));
+        assert!(child.watchdog_expired(timeout, start + Duration::from_secs(11)));
+        assert_eq!(
+            child.watchdog_deadline(timeout),
+            Some(start + Duration::from_secs(10))
+        );
+
+        child.apply(&Notification::Watchdog, start + Duration::from_secs(8));
+        assert!(!child.watchdog_expired(timeout, start + Duration::from_secs(11)));
+
+        child.apply(
+            &Notification::WatchdogTrigger,
+            start + Duration::from_secs(9),
+        );
+        assert!(child.watchdog_expired(timeout, start + Duration::from_secs(9)));
+
+        // a stopping child is left alone
+        child.apply(&Notification::Stopping, start + Duration::from_secs(9));
+        assert!(!child.watchdog_expired(timeout, start + Duration::from_secs(60)));
+        assert_eq!(child.watchdog_deadline(timeout), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listener() {
+        let path = std::env::temp_dir().join(format!("wsw-notify-{}.state", std::process::id()));
+        let state = StateFile::at(path.clone());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let listener = NotifyListener::start(
+            state.clone(),
+            Arc::new(move || {
+                let _ = tx.send(());
+            }),
+        )
+        .unwrap();
+        listener.child_started();
+        assert!(!listener.is_ready());
+
+        let env = listener.env(Some(Duration::from_secs(30)));
+        assert_eq!(
+            env[1],
+            (String::from("WATCHDOG_USEC"), String::from("30000000"))
+        );
+
+        // only the owner can reach the socket
+        let dir = std::path::Path::new(listener.address()).parent().unwrap();
+        let mode = std::os::unix::fs::PermissionsExt::mode(&dir.metadata().unwrap().permissions());
+        assert_eq!(mode & 0o777, 0o700);
+
+        let client = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        client
+            .send_to(b"READY=1\nSTATUS=processing queue", &env[0].1)
+            .unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(listener.is_ready());
+        assert_eq!(
+            state.entries(),
+            vec![(
+                String::from("Child Status"),
+                String::from("processing queue")
+            )]
+        );
+
+        // a new child has to notify again
+        listener.child_started();
+        assert!(!listener.is_ready());
+        let _ = std::fs::remove_file(path);
+
+        // the socket goes away with the listener
+        let dir = dir.to_path_buf();
+        drop(listener);
+        thread::sleep(Duration::from_millis(500));
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::pkg::events::Waker;
+use crate::pkg::log_writer::LineObserver;
+
+#[derive(Debug, Default)]
+struct MatchState {
+    /// Matching line waiting for the supervisor to restart the child
+    pending: Option<String>,
+    last_match: Option<Instant>,
+}
+
+/// Watches the captured output for lines telling that the child must be restarted,
+/// e.g. a fatal error the child does not exit on
+pub struct OutputTrigger {
+    rules: Arc<Vec<Regex>>,
+    cooldown: Duration,
+    state: Arc<Mutex<MatchState>>,
+}
+
+impl OutputTrigger {
+    pub fn new(rules: Vec<Regex>, cooldown: Duration) -> Self {
+        OutputTrigger {
+            rules: Arc::new(rules),
+            cooldown,
+            state: Arc::new(Mutex::new(MatchState::default())),
+        }
+    }
+
+    /// Must be called when a new child is started.
+    /// Returns the observer to attach to the child output, which calls `wake` on a match
+    pub fn child_started(&self, wake: Waker) -> LineObserver {
+        // a line printed by the previous child must not restart this one
+        self.state.lock().unwrap().pending = None;
+        let rules = self.rules.clone();
+        let cooldown = self.cooldown;
+        let state = self.state.clone();
+        Arc::new(move |line: &str| {
+            if on_line(
+                &rules,
+                cooldown,
+                &mut state.lock().unwrap(),
+                line,
+                Instant::now(),
+            ) {
+                wake();
+            }
+        })
+    }
+
+    /// Returns the line that matched, if the child must be restarted
+    pub fn take_match(&self) -> Option<String> {
+        self.state.lock().unwrap().pending.take()
+    }
+}
+
+/// Lines matching within the cooldown of the previous match are ignored,
+/// so that a burst of matching lines only causes one restart.
+/// Returns true if the line matched
+fn on_line(
+    rules: &[Regex],
+    cooldown: Duration,
+    state: &mut MatchState,
+    line: &str,
+    now: Instant,
+) -> bool {
+    if state
+        .last_match
+        .is_some_and(|at| now.duration_since(at) < cooldown)
+    {
+        return false;
+    }
+    if rules.iter().any(|rule| rule.is_match(line)) {
+        state.pending = Some(line.to_string());
+        state.last_match = Some(now);
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn rules() -> Vec<Regex> {
+        vec![
+            Regex::new("OutOfMemoryError").unwrap(),
+            Regex::new("connection pool exhausted").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_match_and_cooldown() {
+        let rules = rules();
+        let cooldown = Duration::from_secs(60);
+        let mut state = MatchState::default();
+        let start = Instant::now();
+
+        on_line(&rules, cooldown, &mut state, "GET /health 200", start);
+        assert_eq!(state.pending, None);
+
+        on_line(
+            &rules,
+            cooldown,
+            &mut state,
+            "java.lang.OutOfMemoryError: heap",
+            start,
+        );
+        // the rest of the burst is ignored
+        let later = start + Duration::from_secs(1);
+        on_line(
+            &rules,
+            cooldown,
+            &mut state,
+            "connection pool exhausted",
+            later,
+        );
+        assert_eq!(
+            state.pending.take(),
+            Some(String::from("java.lang.OutOfMemoryError: heap"))
+        );
+        on_line(
+            &rules,
+            cooldown,
+            &mut state,
+            "OutOfMemoryError",
+            start + Duration::from_secs(59),
+        );
+        assert_eq!(state.pending, None);
+
+        let after_cooldown = start + Duration::from_secs(60);
+        on_line(
+            &rules,
+            cooldown,
+            &mut state,
+            "connection pool exhausted",
+            after_cooldown,
+        );
+        assert_eq!(
+            state.pending,
+            Some(String::from("connection pool exhausted"))
+        );
+    }
+
+    #[test]
+    fn test_trigger() {
+        let trigger = OutputTrigger::new(rules(), Duration::ZERO);
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let wakes_bg = wakes.clone();
+        let wake: Waker = Arc::new(move || {
+            wakes_bg.fetch_add(1, Ordering::SeqCst);
+        });
+        let observer = trigger.child_started(wake.clone());
+        observer("all good");
+        assert_eq!(trigger.take_match(), None);
+        assert_eq!(wakes.load(Ordering::SeqCst), 0);
+
+        observer("connection pool exhausted");
+        assert_eq!(wakes.load(Ordering::SeqCst), 1);
+        // a new child starts with a clean slate
+        let _ = trigger.child_started(wake.clone());
+        assert_eq!(trigger.take_match(), None);
+
+        let observer = trigger.child_started(wake);
+        observer("OutOfMemoryError");
+        assert_eq!(trigger.take_match(), Some(String::from("OutOfMemoryError")));
+        assert_eq!(trigger.take_match(), None);
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::pkg::events::Waker;
+use crate::pkg::log_writer::LineObserver;
+use crate::pkg::net::{HttpUrl, http_get_status, tcp_connect};
+
+/// How long a single network check can take
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long between two network checks
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Condition that tells when the wrapped process is ready to serve
+#[derive(Debug, Clone)]
+pub enum ReadinessCheck {
+    /// A TCP port that accepts connections (tcp:host:port)
+    Tcp(String),
+    /// An HTTP url that answers with a 2xx status (http://host:port/path)
+    Http(HttpUrl),
+    /// A regex matched against the captured output lines (output:regex)
+    Output(Regex),
+}
+
+impl fmt::Display for ReadinessCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadinessCheck::Tcp(addr) => write!(f, "tcp:{}", addr),
+            ReadinessCheck::Http(url) => write!(f, "http://{}:{}{}", url.host, url.port, url.path),
+            ReadinessCheck::Output(re) => write!(f, "output:{}", re.as_str()),
+        }
+    }
+}
+
+impl FromStr for ReadinessCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(ReadinessCheck::Tcp(addr.to_string()))
+        } else if s.starts_with("http://") {
+            Ok(ReadinessCheck::Http(HttpUrl::parse(s)?))
+        } else if let Some(re) = s.strip_prefix("output:") {
+            Regex::new(re)
+                .map(ReadinessCheck::Output)
+                .map_err(|e| format!("Invalid regex '{}': {}", re, e))
+        } else {
+            Err(format!(
+                "Invalid readiness check: {} (expected tcp:host:port, http://host:port/path or output:regex)",
+                s
+            ))
+        }
+    }
+}
+
+impl ReadinessCheck {
+    /// Runs a network check once. It can block up to `CHECK_TIMEOUT`
+    fn probe(&self) -> bool {
+        match self {
+            ReadinessCheck::Tcp(addr) => tcp_connect(addr, CHECK_TIMEOUT).is_ok(),
+            ReadinessCheck::Http(url) => matches!(
+                http_get_status(url, CHECK_TIMEOUT),
+                Ok(code) if (200..300).contains(&code)
+            ),
+            ReadinessCheck::Output(_) => false,
+        }
+    }
+}
+
+/// Tracks the readiness of the current child process.
+/// The network checks run on a background thread, so that a slow answer
+/// doesn't hold up the supervisor
+pub struct Readiness {
+    check: ReadinessCheck,
+    /// Each child gets its own flags, so that a late answer for a previous
+    /// child is never taken for the current one
+    ready: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    pub fn new(check: ReadinessCheck) -> Self {
+        Readiness {
+            check,
+            ready: Arc::new(AtomicBool::new(false)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Must be called when a new child is started.
+    /// Returns the observer to attach to the child output, if the check needs it.
+    /// It calls `wake` once the child is ready
+    pub fn child_started(&mut self, wake: Waker) -> Option<LineObserver> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.ready = Arc::new(AtomicBool::new(false));
+        self.stop = Arc::new(AtomicBool::new(false));
+        match &self.check {
+            ReadinessCheck::Output(re) => {
+                let re = re.clone();
+                let matched = self.ready.clone();
+                Some(Arc::new(move |line: &str| {
+                    if re.is_match(line) && !matched.swap(true, Ordering::SeqCst) {
+                        wake();
+                    }
+                }))
+            }
+            check => {
+                let check = check.clone();
+                let ready = self.ready.clone();
+                let stop = self.stop.clone();
+                thread::spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        if check.probe() {
+                            if !stop.load(Ordering::SeqCst) {
+                                ready.store(true, Ordering::SeqCst);
+                                wake();
+                            }
+                            return;
+                        }
+                        let deadline = Instant::now() + CHECK_INTERVAL;
+                        while Instant::now() < deadline && !stop.load(Ordering::SeqCst) {
+                            thread::sleep(Duration::from_millis(100));
+                        }
+                    }
+                });
+                None
+            }
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkg::net::tests::serve_http;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    fn no_wake() -> Waker {
+        Arc::new(|| {})
+    }
+
+    #[test]
+    fn test_parse_readiness_check() {
+        assert!(matches!(
+            "tcp:127.0.0.1:8080".parse(),
+            Ok(ReadinessCheck::Tcp(addr)) if addr == "127.0.0.1:8080"
+        ));
+        assert!(matches!(
+            "http://localhost:8080/health".parse(),
+            Ok(ReadinessCheck::Http(url)) if url.port == 8080
+        ));
+        assert!(matches!(
+            "output:listening on \\d+".parse(),
+            Ok(ReadinessCheck::Output(_))
+        ));
+        assert!("output:(".parse::<ReadinessCheck>().is_err());
+        assert!("udp:127.0.0.1:53".parse::<ReadinessCheck>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for check in [
+            "tcp:127.0.0.1:80",
+            "http://localhost:8080/health",
+            "output:ready$",
+        ] {
+            assert_eq!(check.parse::<ReadinessCheck>().unwrap().to_string(), check);
+        }
+    }
+
+    /// Returns a waker and the channel it signals
+    fn channel_wake() -> (Waker, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        (
+            Arc::new(move || {
+                let _ = tx.send(());
+            }),
+            rx,
+        )
+    }
+
+    #[test]
+    fn test_tcp_readiness() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut readiness = Readiness::new(format!("tcp:{}", addr).parse().unwrap());
+        assert!(!readiness.is_ready());
+
+        let (wake, woken) = channel_wake();
+        assert!(readiness.child_started(wake).is_none());
+        woken.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(readiness.is_ready());
+
+        // a restarted child has to become ready again
+        drop(listener);
+        readiness.child_started(no_wake());
+        assert!(!readiness.is_ready());
+    }
+
+    #[test]
+    fn test_tcp_readiness_retries() {
+        // reserve a port, then free it so that the first checks fail
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut readiness = Readiness::new(format!("tcp:{}", addr).parse().unwrap());
+        let (wake, woken) = channel_wake();
+        readiness.child_started(wake);
+        assert!(woken.recv_timeout(CHECK_INTERVAL).is_err());
+        assert!(!readiness.is_ready());
+
+        let _listener = TcpListener::bind(addr).unwrap();
+        woken.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn test_http_readiness() {
+        let mut ok = Readiness::new(format!("http://{}/", serve_http(200)).parse().unwrap());
+        let (wake, woken) = channel_wake();
+        ok.child_started(wake);
+        woken.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ok.is_ready());
+
+        let mut failing = Readiness::new(format!("http://{}/", serve_http(503)).parse().unwrap());
+        let (wake, woken) = channel_wake();
+        failing.child_started(wake);
+        assert!(woken.recv_timeout(CHECK_INTERVAL * 2).is_err());
+        assert!(!failing.is_ready());
+    }
+
+    #[test]
+    fn test_readiness_ignores_previous_child() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut readiness = Readiness::new(format!("tcp:{}", addr).parse().unwrap());
+        let (first_wake, first_woken) = channel_wake();
+        readiness.child_started(first_wake);
+        let (wake, woken) = channel_wake();
+        readiness.child_started(wake);
+
+        let _listener = TcpListener::bind(addr).unwrap();
+        woken.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(readiness.is_ready());
+        // the check of the previous child was stopped
+        assert!(first_woken.recv_timeout(CHECK_INTERVAL * 2).is_err());
+    }
+
+    #[test]
+    fn test_output_readiness() {
+        let mut readiness = Readiness::new("output:listening on port \\d+".parse().unwrap());
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let wakes_bg = wakes.clone();
+        let observer = readiness
+            .child_started(Arc::new(move || {
+                wakes_bg.fetch_add(1, Ordering::SeqCst);
+            }))
+            .unwrap();
+        assert!(!readiness.is_ready());
+
+        observer("starting up");
+        assert!(!readiness.is_ready());
+        observer("listening on port 8080");
+        assert!(readiness.is_ready());
+        // the supervisor is only woken up once
+        observer("listening on port 8081");
+        assert_eq!(wakes.load(Ordering::SeqCst), 1);
+
+        // a restarted child has to become ready again
+        readiness.child_started(no_wake());
+        assert!(!readiness.is_ready());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_readiness_with_child_process() {
+        use crate::pkg::runner::{RunOptions, run_command};
+        use std::time::Instant;
+
+        let mut readiness = Readiness::new("output:^ready$".parse().unwrap());
+        let mut process = run_command(
+            "echo starting; sleep 0.2; echo ready; sleep 5",
+            RunOptions {
+                working_dir: Some(String::from("/")),
+                output_observer: readiness.child_started(no_wake()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !readiness.is_ready() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(readiness.is_ready());
+        process.terminate().unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::pkg::duration::{format_duration, parse_duration};
+
+/// What the SCM does when the service fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureActionKind {
+    None,
+    /// Start the service again
+    Restart,
+    /// Run the failure command
+    RunCommand,
+    /// Reboot the computer
+    Reboot,
+}
+
+impl fmt::Display for FailureActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureActionKind::None => write!(f, "none"),
+            FailureActionKind::Restart => write!(f, "restart"),
+            FailureActionKind::RunCommand => write!(f, "run"),
+            FailureActionKind::Reboot => write!(f, "reboot"),
+        }
+    }
+}
+
+impl FromStr for FailureActionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(FailureActionKind::None),
+            "restart" => Ok(FailureActionKind::Restart),
+            "run" => Ok(FailureActionKind::RunCommand),
+            "reboot" => Ok(FailureActionKind::Reboot),
+            _ => Err(format!("Invalid failure action: {}", s)),
+        }
+    }
+}
+
+/// SCM failure action, given as "ACTION[:DELAY]" (e.g. "restart:1m").
+/// The delay defaults to 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureAction {
+    pub kind: FailureActionKind,
+    pub delay: Duration,
+}
+
+impl fmt::Display for FailureAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.delay.is_zero() {
+            write!(f, "{}", self.kind)
+        } else {
+            write!(f, "{}:{}", self.kind, format_duration(self.delay))
+        }
+    }
+}
+
+impl FromStr for FailureAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, delay) = match s.split_once(':') {
+            Some((kind, delay)) => (kind, parse_duration(delay)?),
+            None => (s, Duration::ZERO),
+        };
+        // the SCM stores the delay in milliseconds on 32 bits
+        if delay.as_millis() > u32::MAX as u128 {
+            return Err(format!("Failure action delay is too long: {}", s));
+        }
+        Ok(FailureAction {
+            kind: kind.parse()?,
+            delay,
+        })
+    }
+}
+
+/// SCM recovery configuration of a service
+#[cfg(any(windows, test))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// The first action applies to the first failure, the second one to the
+    /// second failure and the last one to all the following failures
+    pub actions: Vec<FailureAction>,
+    /// How long without failure before the failure count goes back to 0.
+    /// None means never
+    pub reset_period: Option<Duration>,
+    /// Command line of the "run" action
+    pub command: Option<String>,
+    /// Message broadcast to the users before the "reboot" action
+    pub reboot_message: Option<String>,
+    /// Also take the actions when the service stops with an error exit code,
+    /// not only when its process dies without reporting it
+    pub on_non_crash_failures: bool,
+}
+
+#[cfg(any(windows, test))]
+impl Recovery {
+    /// Builds the recovery configuration from the install options,
+    /// and checks that every action has what it needs
+    pub fn new(
+        actions: Vec<FailureAction>,
+        reset_period: Option<Duration>,
+        command: Option<String>,
+        reboot_message: Option<String>,
+        on_non_crash_failures: bool,
+    ) -> Result<Self, String> {
+        let uses = |kind| actions.iter().any(|action| action.kind == kind);
+        if uses(FailureActionKind::RunCommand) != command.is_some() {
+            return Err(String::from(
+                "The \"run\" failure action and the failure command go together",
+            ));
+        }
+        if reboot_message.is_some() && !uses(FailureActionKind::Reboot) {
+            return Err(String::from(
+                "The reboot message is only used by the \"reboot\" failure action",
+            ));
+        }
+        // the SCM stores the reset period in seconds on 32 bits
+        if reset_period.is_some_and(|period| period.as_secs() > u32::MAX as u64) {
+            return Err(String::from("The failure reset period is too long"));
+        }
+        Ok(Recovery {
+            actions,
+            reset_period,
+            command,
+            reboot_message,
+            on_non_crash_failures,
+        })
+    }
+
+    pub fn reboots(&self) -> bool {
+        self.actions
+            .iter()
+            .any(|action| action.kind == FailureActionKind::Reboot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(kind: FailureActionKind, secs: u64) -> FailureAction {
+        FailureAction {
+            kind,
+            delay: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn test_parse_failure_action() {
+        assert_eq!(
+            "restart:1m".parse(),
+            Ok(action(FailureActionKind::Restart, 60))
+        );
+        assert_eq!(
+            "RUN:30s".parse(),
+            Ok(action(FailureActionKind::RunCommand, 30))
+        );
+        assert_eq!("reboot".parse(), Ok(action(FailureActionKind::Reboot, 0)));
+        assert_eq!("none".parse(), Ok(action(FailureActionKind::None, 0)));
+        assert!("restart:soon".parse::<FailureAction>().is_err());
+        assert!("stop:1m".parse::<FailureAction>().is_err());
+        assert!("restart:60d".parse::<FailureAction>().is_err());
+    }
+
+    #[test]
+    fn test_failure_action_round_trip() {
+        for s in ["restart:60s", "run:500ms", "reboot", "none"] {
+            assert_eq!(s.parse::<FailureAction>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_recovery() {
+        let actions = vec![
+            action(FailureActionKind::Restart, 60),
+            action(FailureActionKind::RunCommand, 0),
+        ];
+        let recovery = Recovery::new(
+            actions.clone(),
+            Some(Duration::from_secs(86400)),
+            Some(String::from("notify.exe")),
+            None,
+            true,
+        )
+        .unwrap();
+        assert_eq!(recovery.actions, actions);
+        assert!(!recovery.reboots());
+
+        // "run" needs a command, and a command needs "run"
+        assert!(Recovery::new(actions, None, None, None, false).is_err());
+        let restart = vec![action(FailureActionKind::Restart, 0)];
+        assert!(
+            Recovery::new(
+                restart.clone(),
+                None,
+                Some(String::from("notify.exe")),
+                None,
+                false
+            )
+            .is_err()
+        );
+        assert!(
+            Recovery::new(
+                restart.clone(),
+                None,
+                None,
+                Some(String::from("bye")),
+                false
+            )
+            .is_err()
+        );
+        assert!(
+            Recovery::new(
+                restart,
+                Some(Duration::from_secs(1 << 33)),
+                None,
+                None,
+                false
+            )
+            .is_err()
+        );
+    }
+}
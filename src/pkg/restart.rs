@@ -0,0 +1,419 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// When the wrapped process should be restarted after it exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::OnFailure => write!(f, "on-failure"),
+            RestartPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(RestartPolicy::Always),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "never" => Ok(RestartPolicy::Never),
+            _ => Err(format!("Invalid restart policy: {}", s)),
+        }
+    }
+}
+
+/// Source of time for the restart logic.
+/// It is injected so that the backoff math can be tested without sleeping.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Exponential backoff configuration
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Delay before the first restart
+    pub initial: Duration,
+    /// Upper bound for the delay
+    pub max: Duration,
+    /// Factor applied to the delay on every consecutive restart
+    pub multiplier: f64,
+    /// Random spread applied to the delay, as a fraction of it (0.1 = ±10%)
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// Returns the delay for the given consecutive restart attempt (starting from 0).
+    /// `sample` is a random value in [0, 1) used to compute the jitter.
+    /// The delay never exceeds `max`, jitter included
+    pub fn delay(&self, attempt: u32, sample: f64) -> Duration {
+        let exponent = attempt.min(i32::MAX as u32) as i32;
+        let base = self.initial.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        let base = base.min(self.max.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let spread = base * jitter * (sample * 2.0 - 1.0);
+
+        Duration::try_from_secs_f64((base + spread).max(0.0))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartDecision {
+    /// Restart the child after the given delay
+    Restart(Duration),
+    /// Do not restart the child anymore
+    Stop,
+}
+
+/// Keeps track of the child restarts and decides, according to the policy,
+/// if and when the child should be restarted.
+pub struct RestartTracker<C: Clock = SystemClock> {
+    policy: RestartPolicy,
+    backoff: Backoff,
+    /// Minimum uptime after which the child is considered healthy
+    /// and the backoff is reset
+    reset_after: Duration,
+    clock: C,
+    attempt: u32,
+    started_at: Option<Instant>,
+}
+
+impl RestartTracker<SystemClock> {
+    pub fn new(policy: RestartPolicy, backoff: Backoff, reset_after: Duration) -> Self {
+        Self::with_clock(policy, backoff, reset_after, SystemClock)
+    }
+}
+
+impl<C: Clock> RestartTracker<C> {
+    pub fn with_clock(
+        policy: RestartPolicy,
+        backoff: Backoff,
+        reset_after: Duration,
+        clock: C,
+    ) -> Self {
+        RestartTracker {
+            policy,
+            backoff,
+            reset_after,
+            clock,
+            attempt: 0,
+            started_at: None,
+        }
+    }
+
+    /// Must be called every time the child process is (re)started
+    pub fn child_started(&mut self) {
+        self.started_at = Some(self.clock.now());
+    }
+
+    /// Must be called when the child exits. `success` tells if the child
+    /// exited with a zero exit code.
+    pub fn child_exited(&mut self, success: bool) -> RestartDecision {
+        let restart = match self.policy {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Never => false,
+        };
+        if !restart {
+            return RestartDecision::Stop;
+        }
+        RestartDecision::Restart(self.restart())
+    }
+
+    /// Must be called when the child exits and has to be restarted regardless
+    /// of the policy. Returns the backoff delay
+    pub fn restart(&mut self) -> Duration {
+        if let Some(started_at) = self.started_at.take()
+            && self.clock.now().duration_since(started_at) >= self.reset_after
+        {
+            self.attempt = 0;
+        }
+
+        let delay = self.backoff.delay(self.attempt, self.sample());
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    /// How many consecutive restarts happened since the last reset
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    fn sample(&self) -> f64 {
+        random_fraction(self.attempt)
+    }
+}
+
+/// Returns a random value in [0, 1), used to spread restarts and starts.
+/// RandomState is seeded differently on every call, which is
+/// good enough for that without pulling a rng crate
+pub fn random_fraction(seed: u32) -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(seed);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Circuit breaker that trips when the child is restarted too many times
+/// within a time window, so that a crash looping child is not restarted forever.
+pub struct CrashLoopDetector<C: Clock = SystemClock> {
+    /// Maximum number of restarts allowed in the window. 0 disables the limit
+    max_restarts: u32,
+    window: Duration,
+    clock: C,
+    restarts: VecDeque<Instant>,
+}
+
+impl CrashLoopDetector<SystemClock> {
+    pub fn new(max_restarts: u32, window: Duration) -> Self {
+        Self::with_clock(max_restarts, window, SystemClock)
+    }
+}
+
+impl<C: Clock> CrashLoopDetector<C> {
+    pub fn with_clock(max_restarts: u32, window: Duration, clock: C) -> Self {
+        CrashLoopDetector {
+            max_restarts,
+            window,
+            clock,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Records a restart. Returns false if the restart exceeds the limit
+    /// and the child should not be restarted anymore.
+    pub fn record_restart(&mut self) -> bool {
+        if self.max_restarts == 0 {
+            return true;
+        }
+
+        let now = self.clock.now();
+        while let Some(first) = self.restarts.front() {
+            if now.duration_since(*first) > self.window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.restarts.len() >= self.max_restarts as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+
+    /// How many restarts happened within the current window
+    pub fn restarts(&self) -> usize {
+        self.restarts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct FakeClock {
+        now: Rc<Cell<Instant>>,
+    }
+
+    impl FakeClock {
+        fn new() -> Self {
+            FakeClock {
+                now: Rc::new(Cell::new(Instant::now())),
+            }
+        }
+
+        fn advance(&self, d: Duration) {
+            self.now.set(self.now.get() + d);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+
+    fn backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+
+    fn tracker(policy: RestartPolicy, clock: FakeClock) -> RestartTracker<FakeClock> {
+        RestartTracker::with_clock(policy, backoff(), Duration::from_secs(30), clock)
+    }
+
+    #[test]
+    fn test_restart_policy_from_str() {
+        assert_eq!("always".parse(), Ok(RestartPolicy::Always));
+        assert_eq!("On-Failure".parse(), Ok(RestartPolicy::OnFailure));
+        assert_eq!("never".parse(), Ok(RestartPolicy::Never));
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let b = backoff();
+        let delays: Vec<u64> = (0..6).map(|a| b.delay(a, 0.5).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn test_backoff_jitter_bounds() {
+        let b = Backoff {
+            jitter: 0.5,
+            ..backoff()
+        };
+        assert_eq!(b.delay(2, 0.0), Duration::from_secs(2));
+        assert_eq!(b.delay(2, 0.5), Duration::from_secs(4));
+        assert!(b.delay(2, 0.999) < Duration::from_secs(6));
+    }
+
+    #[test]
+    fn test_backoff_huge_values() {
+        let b = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::MAX,
+            multiplier: 2.0,
+            jitter: 1.0,
+        };
+        // the jitter would push the delay past Duration::MAX
+        assert_eq!(b.delay(u32::MAX, 0.999), Duration::MAX);
+        // a negative exponent would bring the delay below the initial one
+        assert!(b.delay(u32::MAX, 0.5) >= Duration::from_secs(1));
+
+        let b = Backoff {
+            jitter: 0.5,
+            ..backoff()
+        };
+        // at the cap, the jitter can only shorten the delay
+        assert_eq!(b.delay(10, 0.999), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_policy_never_stops() {
+        let mut t = tracker(RestartPolicy::Never, FakeClock::new());
+        t.child_started();
+        assert_eq!(t.child_exited(false), RestartDecision::Stop);
+    }
+
+    #[test]
+    fn test_policy_on_failure() {
+        let mut t = tracker(RestartPolicy::OnFailure, FakeClock::new());
+        t.child_started();
+        assert_eq!(
+            t.child_exited(false),
+            RestartDecision::Restart(Duration::from_secs(1))
+        );
+        t.child_started();
+        assert_eq!(t.child_exited(true), RestartDecision::Stop);
+    }
+
+    #[test]
+    fn test_forced_restart_ignores_policy() {
+        let mut t = tracker(RestartPolicy::Never, FakeClock::new());
+        t.child_started();
+        assert_eq!(t.restart(), Duration::from_secs(1));
+        t.child_started();
+        assert_eq!(t.restart(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_backoff_increases_on_fast_crashes() {
+        let clock = FakeClock::new();
+        let mut t = tracker(RestartPolicy::Always, clock.clone());
+        let mut delays = vec![];
+        for _ in 0..4 {
+            t.child_started();
+            clock.advance(Duration::from_secs(1));
+            match t.child_exited(true) {
+                RestartDecision::Restart(d) => delays.push(d.as_secs()),
+                RestartDecision::Stop => panic!("unexpected stop"),
+            }
+        }
+        assert_eq!(delays, vec![1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn test_backoff_resets_after_min_uptime() {
+        let clock = FakeClock::new();
+        let mut t = tracker(RestartPolicy::Always, clock.clone());
+        for _ in 0..3 {
+            t.child_started();
+            t.child_exited(false);
+        }
+        assert_eq!(t.attempt(), 3);
+
+        t.child_started();
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            t.child_exited(false),
+            RestartDecision::Restart(Duration::from_secs(1))
+        );
+        assert_eq!(t.attempt(), 1);
+    }
+
+    #[test]
+    fn test_crash_loop_disabled() {
+        let mut d = CrashLoopDetector::with_clock(0, Duration::from_secs(60), FakeClock::new());
+        for _ in 0..100 {
+            assert!(d.record_restart());
+        }
+    }
+
+    #[test]
+    fn test_crash_loop_trips_within_window() {
+        let clock = FakeClock::new();
+        let mut d = CrashLoopDetector::with_clock(3, Duration::from_secs(60), clock.clone());
+        for _ in 0..3 {
+            assert!(d.record_restart());
+            clock.advance(Duration::from_secs(10));
+        }
+        assert!(!d.record_restart());
+        assert_eq!(d.restarts(), 3);
+    }
+
+    #[test]
+    fn test_crash_loop_forgets_old_restarts() {
+        let clock = FakeClock::new();
+        let mut d = CrashLoopDetector::with_clock(3, Duration::from_secs(60), clock.clone());
+        for _ in 0..3 {
+            assert!(d.record_restart());
+            clock.advance(Duration::from_secs(30));
+        }
+        // the first restart is now out of the window
+        assert!(d.record_restart());
+        assert_eq!(d.restarts(), 3);
+    }
+}
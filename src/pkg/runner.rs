@@ -1,33 +1,482 @@
-use regex::Regex;
-use std::io::{self};
-use std::os::windows::io::AsRawHandle;
+use std::io::{self, Write};
 use std::{
     path::{Path, PathBuf},
-    process::{Child, Command, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
     thread,
+    time::{Duration, Instant},
 };
+#[cfg(any(windows, test))]
 use tracing::info;
 use which::which;
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+#[cfg(windows)]
+use std::os::windows::{io::AsRawHandle, process::CommandExt};
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, HANDLE, INVALID_HANDLE_VALUE};
+#[cfg(windows)]
+use windows_sys::Win32::System::Console::{
+    AttachConsole, CTRL_BREAK_EVENT, CTRL_C_EVENT, FreeConsole, GenerateConsoleCtrlEvent,
+    SetConsoleCtrlHandler,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, TH32CS_SNAPTHREAD, THREADENTRY32, Thread32First, Thread32Next,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::IO::{CreateIoCompletionPort, GetQueuedCompletionStatus};
+#[cfg(windows)]
 use windows_sys::Win32::System::JobObjects::{
-    AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
-    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
-    SetInformationJobObject,
+    AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
+    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_ACTIVE_PROCESS,
+    JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    JOB_OBJECT_LIMIT_PRIORITY_CLASS, JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    JOBOBJECT_ASSOCIATE_COMPLETION_PORT, JOBOBJECT_BASIC_AND_IO_ACCOUNTING_INFORMATION,
+    JOBOBJECT_BASIC_PROCESS_ID_LIST, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectAssociateCompletionPortInformation,
+    JobObjectBasicAndIoAccountingInformation, JobObjectBasicProcessIdList,
+    JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation,
+    QueryInformationJobObject, SetInformationJobObject, TerminateJobObject,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::ProcessStatus::{K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+#[cfg(windows)]
+use windows_sys::Win32::System::SystemServices::{
+    JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT, JOB_OBJECT_MSG_JOB_MEMORY_LIMIT,
+    JOB_OBJECT_MSG_PROCESS_MEMORY_LIMIT,
 };
+#[cfg(windows)]
+use windows_sys::Win32::System::Threading::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, CREATE_NEW_PROCESS_GROUP,
+    GetProcessHandleCount, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, INFINITE,
+    NORMAL_PRIORITY_CLASS, OpenProcess, OpenThread, PROCESS_QUERY_LIMITED_INFORMATION,
+    PROCESS_SYNCHRONIZE, ResumeThread, SuspendThread, THREAD_SUSPEND_RESUME, WaitForSingleObject,
+};
+
+use crate::pkg::events::Waker;
+#[cfg(windows)]
+use crate::pkg::limits::PriorityClass;
+use crate::pkg::limits::{LimitViolation, ResourceLimits};
+use crate::pkg::log_writer::{LineObserver, LogWriter};
+use crate::pkg::metrics::{Usage, UsageSource};
+use crate::pkg::shell::{Shell, split_executable};
+
+/// Console control event that can be delivered to the child process group.
+/// On Unix they are mapped to SIGINT and SIGTERM respectively
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    CtrlC,
+    CtrlBreak,
+}
+
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    /// Service working directory.
+    /// If not specified, the directory of the executable will be used
+    pub working_dir: Option<String>,
+    /// Do not capture the child stdout and stderr
+    pub disable_logs: bool,
+    /// Keep the child stdin open, so that lines can be written to it
+    pub pipe_stdin: bool,
+    /// Start the child in a new process group. This is needed to deliver
+    /// CTRL_BREAK events to the child only. Note that Windows disables CTRL_C
+    /// handling for processes in a new process group
+    #[cfg_attr(not(windows), allow(dead_code))] // always done on Unix
+    pub new_process_group: bool,
+    /// Called with every line of the captured output
+    pub output_observer: Option<LineObserver>,
+    /// Extra environment variables for the child
+    pub env: Vec<(String, String)>,
+    /// Tag prepended to the captured output lines, to tell apart
+    /// the processes of a group
+    pub log_tag: Option<String>,
+    /// Limits applied to the child process tree
+    pub limits: ResourceLimits,
+    /// How the command line is launched
+    pub shell: Shell,
+}
+
+/// A running child process together with the Job Object (the process group on Unix)
+/// that holds its whole process tree.
+/// Dropping it kills whatever is left of the process tree.
+pub struct ChildProcess {
+    pub child: Child,
+    #[cfg(windows)]
+    job: HANDLE,
+    /// Receives the notifications of the Job Object
+    #[cfg(windows)]
+    port: HANDLE,
+}
+
+impl ChildProcess {
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    #[cfg(windows)]
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+
+    /// Calls `wake` on a background thread once the child exits.
+    /// The child is not reaped, `try_wait` still gets its exit status
+    #[cfg(windows)]
+    pub fn on_exit(&self, wake: Waker) -> io::Result<()> {
+        // a handle of our own, which stays valid even if the child is dropped meanwhile
+        let process = unsafe { OpenProcess(PROCESS_SYNCHRONIZE, 0, self.id()) };
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let process = process as usize;
+        thread::spawn(move || {
+            unsafe {
+                WaitForSingleObject(process as HANDLE, INFINITE);
+                CloseHandle(process as HANDLE);
+            }
+            wake();
+        });
+        Ok(())
+    }
+
+    /// Calls `wake` on a background thread once the child exits.
+    /// The child is not reaped, `try_wait` still gets its exit status
+    #[cfg(unix)]
+    pub fn on_exit(&self, wake: Waker) -> io::Result<()> {
+        let pid = self.id() as libc::id_t;
+        thread::spawn(move || {
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            // WNOWAIT leaves the child waitable. Any other error means it was
+            // already reaped, which only happens once it exited
+            while unsafe {
+                libc::waitid(libc::P_PID, pid, &mut info, libc::WEXITED | libc::WNOWAIT)
+            } == -1
+                && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+            {}
+            wake();
+        });
+        Ok(())
+    }
+
+    /// Writes a line to the child stdin. The child must be started with `pipe_stdin`
+    pub fn write_stdin_line(&mut self, line: &str) -> io::Result<()> {
+        match self.child.stdin.as_mut() {
+            Some(stdin) => {
+                writeln!(stdin, "{}", line)?;
+                stdin.flush()
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "child stdin is not available",
+            )),
+        }
+    }
+
+    /// Sends a console control event to the child process group
+    #[cfg(windows)]
+    pub fn send_console_event(&self, event: ConsoleEvent) -> io::Result<()> {
+        let pid = self.child.id();
+        let (ctrl_event, group) = match event {
+            // Process group 0 means all the processes attached to the console
+            ConsoleEvent::CtrlC => (CTRL_C_EVENT, 0),
+            ConsoleEvent::CtrlBreak => (CTRL_BREAK_EVENT, pid),
+        };
+        unsafe {
+            // The service has no console of its own, so it needs to attach
+            // to the child one in order to deliver the event
+            FreeConsole();
+            if AttachConsole(pid) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Ignore the event in the wrapper itself
+            SetConsoleCtrlHandler(None, 1);
+            let result = GenerateConsoleCtrlEvent(ctrl_event, group);
+            FreeConsole();
+            if result == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a console control event to the child process group
+    #[cfg(unix)]
+    pub fn send_console_event(&self, event: ConsoleEvent) -> io::Result<()> {
+        match event {
+            ConsoleEvent::CtrlC => self.signal_group(libc::SIGINT),
+            ConsoleEvent::CtrlBreak => self.signal_group(libc::SIGTERM),
+        }
+    }
 
-use windows_sys::Win32::Foundation::{GetLastError, HANDLE};
+    /// Kills the whole process tree and waits for the child to exit
+    #[cfg(windows)]
+    pub fn terminate(&mut self) -> io::Result<ExitStatus> {
+        if unsafe { TerminateJobObject(self.job, 1) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.child.wait()
+    }
+
+    /// Kills the whole process tree and waits for the child to exit
+    #[cfg(unix)]
+    pub fn terminate(&mut self) -> io::Result<ExitStatus> {
+        self.signal_group(libc::SIGKILL)?;
+        self.child.wait()
+    }
 
-use crate::pkg::log_writer::LogWriter;
+    /// Freezes the whole process tree
+    #[cfg(windows)]
+    pub fn suspend(&self) -> io::Result<()> {
+        self.for_each_thread(|thread| unsafe { SuspendThread(thread) } != u32::MAX)
+    }
+
+    /// Thaws a process tree frozen by `suspend`
+    #[cfg(windows)]
+    pub fn resume(&self) -> io::Result<()> {
+        self.for_each_thread(|thread| unsafe { ResumeThread(thread) } != u32::MAX)
+    }
 
-fn create_job_object() -> Result<HANDLE, std::io::Error> {
+    /// Freezes the whole process tree
+    #[cfg(unix)]
+    pub fn suspend(&self) -> io::Result<()> {
+        self.signal_group(libc::SIGSTOP)
+    }
+
+    /// Thaws a process tree frozen by `suspend`
+    #[cfg(unix)]
+    pub fn resume(&self) -> io::Result<()> {
+        self.signal_group(libc::SIGCONT)
+    }
+
+    /// Limits the process tree ran into since the last call
+    #[cfg(windows)]
+    pub fn limit_violations(&self) -> Vec<LimitViolation> {
+        let mut violations = Vec::new();
+        let mut message = 0u32;
+        let mut key = 0usize;
+        let mut overlapped = std::ptr::null_mut();
+        // a zero timeout returns as soon as the queue is empty
+        while unsafe {
+            GetQueuedCompletionStatus(self.port, &mut message, &mut key, &mut overlapped, 0)
+        } != 0
+        {
+            let violation = match message {
+                JOB_OBJECT_MSG_JOB_MEMORY_LIMIT => LimitViolation::JobMemory,
+                JOB_OBJECT_MSG_PROCESS_MEMORY_LIMIT => LimitViolation::ProcessMemory,
+                JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT => LimitViolation::ActiveProcesses,
+                _ => continue,
+            };
+            violations.push(violation);
+        }
+        violations
+    }
+
+    /// Limits the process tree ran into since the last call.
+    /// The kernel enforces rlimits silently, so there is nothing to report
+    #[cfg(unix)]
+    pub fn limit_violations(&self) -> Vec<LimitViolation> {
+        Vec::new()
+    }
+
+    /// Ids of the processes held by the Job Object
+    #[cfg(windows)]
+    fn process_ids(&self) -> io::Result<Vec<u32>> {
+        const MAX_PROCESSES: usize = 4096;
+        // the list header takes 8 bytes, followed by the ids as ULONG_PTR
+        let mut buffer = vec![0usize; 8 / size_of::<usize>() + MAX_PROCESSES];
+        unsafe {
+            if QueryInformationJobObject(
+                self.job,
+                JobObjectBasicProcessIdList,
+                buffer.as_mut_ptr() as *mut _,
+                (buffer.len() * size_of::<usize>()) as u32,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let list = &*(buffer.as_ptr() as *const JOBOBJECT_BASIC_PROCESS_ID_LIST);
+            let ids = std::slice::from_raw_parts(
+                list.ProcessIdList.as_ptr(),
+                list.NumberOfProcessIdsInList as usize,
+            );
+            Ok(ids.iter().map(|id| *id as u32).collect())
+        }
+    }
+
+    /// Windows can't suspend a process as a whole, so every thread
+    /// of every process in the job is suspended or resumed
+    #[cfg(windows)]
+    fn for_each_thread(&self, action: impl Fn(HANDLE) -> bool) -> io::Result<()> {
+        let process_ids = self.process_ids()?;
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+            if snapshot == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut result = Ok(());
+            let mut entry: THREADENTRY32 = std::mem::zeroed();
+            entry.dwSize = size_of::<THREADENTRY32>() as u32;
+            let mut found = Thread32First(snapshot, &mut entry) != 0;
+            while found {
+                if process_ids.contains(&entry.th32OwnerProcessID) {
+                    let thread = OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID);
+                    // a null handle means the thread exited in the meantime
+                    if !thread.is_null() {
+                        if !action(thread) {
+                            result = Err(io::Error::last_os_error());
+                        }
+                        CloseHandle(thread);
+                    }
+                }
+                found = Thread32Next(snapshot, &mut entry) != 0;
+            }
+            CloseHandle(snapshot);
+            result
+        }
+    }
+
+    #[cfg(unix)]
+    fn signal_group(&self, signal: libc::c_int) -> io::Result<()> {
+        // The child is the leader of its own process group
+        if unsafe { libc::kill(-(self.child.id() as libc::pid_t), signal) } == -1 {
+            let err = io::Error::last_os_error();
+            // The whole group is already gone
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl UsageSource for ChildProcess {
+    fn usage(&self) -> io::Result<Usage> {
+        unsafe {
+            let mut accounting: JOBOBJECT_BASIC_AND_IO_ACCOUNTING_INFORMATION = std::mem::zeroed();
+            if QueryInformationJobObject(
+                self.job,
+                JobObjectBasicAndIoAccountingInformation,
+                &mut accounting as *mut _ as *mut _,
+                size_of::<JOBOBJECT_BASIC_AND_IO_ACCOUNTING_INFORMATION>() as u32,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            // CPU time and I/O include the processes that already exited,
+            // the CPU time is in 100ns units
+            let basic = &accounting.BasicInfo;
+            let mut usage = Usage {
+                cpu_time: Duration::from_nanos(
+                    (basic.TotalUserTime + basic.TotalKernelTime) as u64 * 100,
+                ),
+                processes: basic.ActiveProcesses,
+                io_read: accounting.IoInfo.ReadTransferCount,
+                io_write: accounting.IoInfo.WriteTransferCount,
+                ..Default::default()
+            };
+
+            for pid in self.process_ids()? {
+                let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+                // a null handle means the process exited in the meantime
+                if process.is_null() {
+                    continue;
+                }
+                let mut counters: PROCESS_MEMORY_COUNTERS = std::mem::zeroed();
+                counters.cb = size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+                if K32GetProcessMemoryInfo(process, &mut counters, counters.cb) != 0 {
+                    usage.memory += counters.WorkingSetSize as u64;
+                }
+                let mut handles = 0;
+                if GetProcessHandleCount(process, &mut handles) != 0 {
+                    usage.handles += handles as u64;
+                }
+                CloseHandle(process);
+            }
+            Ok(usage)
+        }
+    }
+}
+
+#[cfg(unix)]
+impl UsageSource for ChildProcess {
+    #[cfg(target_os = "linux")]
+    fn usage(&self) -> io::Result<Usage> {
+        // the child is the leader of the process group holding the tree
+        crate::pkg::metrics::process_group_usage(self.child.id())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn usage(&self) -> io::Result<Usage> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+impl Drop for ChildProcess {
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        // The Job Object is configured with JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        // so closing it terminates the whole process tree
+        unsafe {
+            if CloseHandle(self.job) == 0 {
+                tracing::error!("Failed to close handle: {}", GetLastError());
+            }
+            CloseHandle(self.port);
+        }
+    }
+
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        let _ = self.signal_group(libc::SIGKILL);
+        let _ = self.child.try_wait();
+    }
+}
+
+#[cfg(windows)]
+fn priority_class(priority: PriorityClass) -> u32 {
+    match priority {
+        PriorityClass::Idle => IDLE_PRIORITY_CLASS,
+        PriorityClass::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+        PriorityClass::Normal => NORMAL_PRIORITY_CLASS,
+        PriorityClass::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+        PriorityClass::High => HIGH_PRIORITY_CLASS,
+    }
+}
+
+#[cfg(windows)]
+fn create_job_object(limits: &ResourceLimits) -> Result<HANDLE, std::io::Error> {
     unsafe {
         let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
         if handle.is_null() {
-            panic!("CreateJobObjectW failed, error={}", GetLastError());
+            return Err(io::Error::last_os_error());
         }
 
         // Set the Job Object to kill all processes on close
         let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
         info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        if let Some(bytes) = limits.job_memory {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            info.JobMemoryLimit = bytes as usize;
+        }
+        if let Some(bytes) = limits.process_memory {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+            info.ProcessMemoryLimit = bytes as usize;
+        }
+        if let Some(count) = limits.active_processes {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+            info.BasicLimitInformation.ActiveProcessLimit = count;
+        }
+        if let Some(priority) = limits.priority {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+            info.BasicLimitInformation.PriorityClass = priority_class(priority);
+        }
 
         let set_result = SetInformationJobObject(
             handle,
@@ -36,20 +485,69 @@ fn create_job_object() -> Result<HANDLE, std::io::Error> {
             std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
         );
         if set_result == 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Failed to set information on Job Object: {}",
-                    GetLastError()
-                ),
-            ));
+            // read before closing the handle, which may overwrite it
+            let error = GetLastError();
+            CloseHandle(handle);
+            return Err(io::Error::other(format!(
+                "Failed to set information on Job Object: {}",
+                error
+            )));
+        }
+
+        if let Some(percent) = limits.cpu_rate {
+            let mut cpu: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = std::mem::zeroed();
+            cpu.ControlFlags =
+                JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+            // the rate is expressed in 1/100 of percent
+            cpu.Anonymous.CpuRate = percent * 100;
+            if SetInformationJobObject(
+                handle,
+                JobObjectCpuRateControlInformation,
+                &cpu as *const _ as *const _,
+                std::mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+            ) == 0
+            {
+                let error = GetLastError();
+                CloseHandle(handle);
+                return Err(io::Error::other(format!(
+                    "Failed to set the CPU rate on Job Object: {}",
+                    error
+                )));
+            }
         }
 
         Ok(handle)
     }
 }
 
-fn find_working_dir(cmdline: &str, working_dir: Option<String>) -> PathBuf {
+/// Creates the completion port receiving the limit notifications of the Job Object
+#[cfg(windows)]
+fn create_job_port(job: HANDLE) -> io::Result<HANDLE> {
+    unsafe {
+        let port = CreateIoCompletionPort(INVALID_HANDLE_VALUE, std::ptr::null_mut(), 0, 1);
+        if port.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let info = JOBOBJECT_ASSOCIATE_COMPLETION_PORT {
+            CompletionKey: job,
+            CompletionPort: port,
+        };
+        if SetInformationJobObject(
+            job,
+            JobObjectAssociateCompletionPortInformation,
+            &info as *const _ as *const _,
+            std::mem::size_of::<JOBOBJECT_ASSOCIATE_COMPLETION_PORT>() as u32,
+        ) == 0
+        {
+            let err = io::Error::last_os_error();
+            CloseHandle(port);
+            return Err(err);
+        }
+        Ok(port)
+    }
+}
+
+pub fn find_working_dir(cmdline: &str, working_dir: Option<String>) -> PathBuf {
     let mut cmd_working_dir: PathBuf = Path::new(".").to_path_buf();
 
     // Check if the working directory is provided and not empty
@@ -67,49 +565,129 @@ fn find_working_dir(cmdline: &str, working_dir: Option<String>) -> PathBuf {
             cmd_working_dir = Path::new(parent).to_path_buf();
         }
 
-        if cmd_working_dir == Path::new("") {
-            match which(exe) {
-                Ok(path) => {
-                    if let Some(parent) = path.parent() {
-                        cmd_working_dir = Path::new(parent).to_path_buf();
-                    }
-                }
-                Err(_) => {}
-            }
+        if cmd_working_dir == Path::new("")
+            && let Ok(path) = which(exe)
+            && let Some(parent) = path.parent()
+        {
+            cmd_working_dir = Path::new(parent).to_path_buf();
         }
     }
 
     cmd_working_dir
 }
 
-pub fn run_command(
-    cmdline: &str,
-    working_dir: Option<String>,
-    disable_logs: bool,
-) -> Result<(HANDLE, Child), std::io::Error> {
+#[cfg(any(windows, test))]
+pub fn run_command(cmdline: &str, options: RunOptions) -> Result<ChildProcess, std::io::Error> {
     // detect the more appropriate working directory for the command line
-    let cmd_working_dir = find_working_dir(cmdline, working_dir);
+    let cmd_working_dir = find_working_dir(cmdline, options.working_dir.clone());
     info!("Command: {:?}", cmdline);
     info!("Working directory: {:?}", cmd_working_dir);
 
-    // Create a Job Object
-    // The Job Object is used to manage the process and its children
-    // and to ensure that all processes are terminated when the Job Object is closed
-    // or when the process exits. Windows does not supports child processes
-    // that are not part of the Job Object. It's not like Linux where you can fork a child process
-    // and it will be a child of the parent process. In Windows, the child process is not a child of the parent process
-    // unless the parent process is a Job Object. So we need to create a Job Object and assign the process to it.
-    let job = create_job_object()?;
-
-    // Use the job handle to create a new process to ensure
-    // properly parsed command line arguments
-    let command = Command::new("cmd.exe")
-        .arg("/C")
-        .arg(cmdline)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .current_dir(cmd_working_dir)
+    let child = spawn(cmdline, &cmd_working_dir, &options)?;
+    assign_to_job(child, &options.limits)
+}
+
+/// Runs a short lived command to completion. If it does not exit within
+/// `timeout` its whole process tree is killed and None is returned
+pub fn run_with_timeout(
+    cmdline: &str,
+    options: RunOptions,
+    timeout: Duration,
+) -> io::Result<Option<ExitStatus>> {
+    let cmd_working_dir = find_working_dir(cmdline, options.working_dir.clone());
+    let child = spawn(cmdline, &cmd_working_dir, &options)?;
+    let mut process = assign_to_job(child, &options.limits)?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = process.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            process.terminate()?;
+            return Ok(None);
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(50)));
+    }
+}
+
+#[cfg(windows)]
+fn create_command(cmdline: &str, working_dir: &Path, options: &RunOptions) -> io::Result<Command> {
+    let mut command = options.shell.command(cmdline, working_dir)?;
+    if options.new_process_group {
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    Ok(command)
+}
+
+#[cfg(unix)]
+fn create_command(cmdline: &str, working_dir: &Path, options: &RunOptions) -> io::Result<Command> {
+    let mut command = options.shell.command(cmdline, working_dir)?;
+    // Run the child in its own process group, so that signals
+    // reach the whole process tree
+    command.process_group(0);
+    let limits = options.limits;
+    if limits.job_memory.is_some() || limits.cpu_rate.is_some() {
+        tracing::warn!("The memory and CPU limits of the process tree are not supported on Unix");
+    }
+    if !limits.is_empty() {
+        // the limits are inherited by the whole process tree
+        unsafe {
+            command.pre_exec(move || apply_limits(&limits));
+        }
+    }
+    Ok(command)
+}
+
+/// Runs in the child between fork and exec, so it sticks to plain system calls
+#[cfg(unix)]
+fn apply_limits(limits: &ResourceLimits) -> io::Result<()> {
+    let check = |result: libc::c_int| {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    let rlimit = |value: u64| libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    if let Some(bytes) = limits.process_memory {
+        check(unsafe { libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes)) })?;
+    }
+    if let Some(count) = limits.active_processes {
+        // note that the kernel counts all the processes of the user
+        check(unsafe { libc::setrlimit(libc::RLIMIT_NPROC, &rlimit(count as u64)) })?;
+    }
+    if let Some(priority) = limits.priority {
+        check(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, priority.nice()) })?;
+    }
+    Ok(())
+}
+
+fn spawn(cmdline: &str, working_dir: &Path, options: &RunOptions) -> io::Result<Child> {
+    let disable_logs = options.disable_logs;
+    // Nobody reads the output when logs are disabled, so don't pipe it
+    // or the child would block once the pipe buffer is full
+    let output = || {
+        if disable_logs {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        }
+    };
+    create_command(cmdline, working_dir, options)?
+        .stdin(if options.pipe_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(output())
+        .stderr(output())
+        .envs(options.env.iter().map(|(k, v)| (k, v)))
+        .current_dir(working_dir)
         .spawn()
         .map(|mut child| {
             if disable_logs {
@@ -117,12 +695,14 @@ pub fn run_command(
             }
             if let Some(mut stdout) = child.stdout.take() {
                 if let Some(mut stderr) = child.stderr.take() {
-                    let mut stdout_logger = LogWriter;
+                    let mut stdout_logger =
+                        LogWriter::new(options.output_observer.clone(), options.log_tag.clone());
                     thread::spawn(move || {
                         let _ = std::io::copy(&mut stdout, &mut stdout_logger);
                     });
 
-                    let mut stderr_logger = LogWriter;
+                    let mut stderr_logger =
+                        LogWriter::new(options.output_observer.clone(), options.log_tag.clone());
                     thread::spawn(move || {
                         let _ = std::io::copy(&mut stderr, &mut stderr_logger);
                     });
@@ -133,50 +713,168 @@ pub fn run_command(
                 tracing::error!("can't get stdout");
             }
             child
-        });
+        })
+}
 
-    if let Ok(child) = &command {
-        let process_handle = child.as_raw_handle();
-        let assign_result = unsafe { AssignProcessToJobObject(job, process_handle) };
-        if assign_result == 0 {
-            unsafe {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed to assign process to Job Object: {}", GetLastError()),
-                ));
-            }
+#[cfg(windows)]
+fn assign_to_job(child: Child, limits: &ResourceLimits) -> Result<ChildProcess, std::io::Error> {
+    // Create a Job Object
+    // The Job Object is used to manage the process and its children
+    // and to ensure that all processes are terminated when the Job Object is closed
+    // or when the process exits. Windows does not supports child processes
+    // that are not part of the Job Object. It's not like Linux where you can fork a child process
+    // and it will be a child of the parent process. In Windows, the child process is not a child of the parent process
+    // unless the parent process is a Job Object. So we need to create a Job Object and assign the process to it.
+    let job = create_job_object(limits)?;
+    let port = match create_job_port(job) {
+        Ok(port) => port,
+        Err(e) => {
+            unsafe { CloseHandle(job) };
+            let mut child = child;
+            let _ = child.kill();
+            return Err(e);
         }
-    }
+    };
+    let mut process = ChildProcess { child, job, port };
 
-    unsafe {
-        if let Ok(child) = command {
-            return Ok((std::mem::transmute(job), child));
-        } else {
-            return Err(command.unwrap_err());
-        }
+    let process_handle = process.child.as_raw_handle();
+    let assign_result = unsafe { AssignProcessToJobObject(job, process_handle) };
+    if assign_result == 0 {
+        let err = unsafe {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to assign process to Job Object: {}", GetLastError()),
+            )
+        };
+        let _ = process.child.kill();
+        return Err(err);
     }
+
+    Ok(process)
 }
 
-fn extract_executable(command: &str) -> Option<String> {
-    // Regex to capture quoted or unquoted executable paths at the beginning.
-    // For unquoted paths, we look for patterns ending with .exe (common executable extension)
-    // followed by a space and arguments, or end of string.
-    let re = Regex::new(r#"^(?:"([^"]+)"|([^"]*\.exe))(?:\s|$)"#).unwrap();
+#[cfg(unix)]
+fn assign_to_job(child: Child, _limits: &ResourceLimits) -> Result<ChildProcess, std::io::Error> {
+    // On Unix the process group created at spawn time plays the role of the Job Object
+    Ok(ChildProcess { child })
+}
 
-    re.captures(command).map(|caps| {
-        // Choose the matching capture group: either quoted (1) or unquoted (2)
-        caps.get(1)
-            .or_else(|| caps.get(2))
-            .unwrap()
-            .as_str()
-            .to_string()
-    })
+fn extract_executable(command: &str) -> Option<String> {
+    split_executable(command).map(|(exe, _)| exe)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(target_os = "linux")]
+    fn process_state(pid: u32) -> char {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        // the state follows the command name, which is enclosed in parentheses
+        stat[stat.rfind(')').unwrap() + 2..].chars().next().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_suspend_and_resume() {
+        let options = RunOptions {
+            working_dir: Some(String::from("/")),
+            disable_logs: true,
+            ..Default::default()
+        };
+        let mut process = run_command("exec sleep 5", options).unwrap();
+        let pid = process.id();
+
+        process.suspend().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(process_state(pid), 'T');
+
+        process.resume().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_ne!(process_state(pid), 'T');
+
+        process.terminate().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_on_exit() {
+        let options = RunOptions {
+            working_dir: Some(String::from("/")),
+            disable_logs: true,
+            ..Default::default()
+        };
+        let mut process = run_command("exit 3", options).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        process
+            .on_exit(std::sync::Arc::new(move || tx.send(()).unwrap()))
+            .unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        // the exit status is still there for the supervisor
+        assert_eq!(process.try_wait().unwrap().unwrap().code(), Some(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_usage() {
+        let options = RunOptions {
+            working_dir: Some(String::from("/")),
+            disable_logs: true,
+            ..Default::default()
+        };
+        let mut process = run_command("while :; do :; done", options).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let usage = process.usage().unwrap();
+        assert!(usage.memory > 0);
+        assert!(usage.cpu_time > Duration::ZERO);
+        assert!(usage.processes >= 1);
+        process.terminate().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resource_limits() {
+        let options = RunOptions {
+            working_dir: Some(String::from("/")),
+            disable_logs: true,
+            limits: ResourceLimits {
+                process_memory: Some(100 << 20),
+                priority: Some(crate::pkg::limits::PriorityClass::BelowNormal),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // ulimit reports the address space limit in KiB
+        let status = run_with_timeout(
+            r#"test "$(ulimit -v)" = 102400 && test "$(nice)" = 10"#,
+            options,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(status.is_some_and(|status| status.success()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_shell_none() {
+        let options = RunOptions {
+            working_dir: Some(String::from("/")),
+            disable_logs: true,
+            shell: Shell::None,
+            ..Default::default()
+        };
+        // the arguments reach the executable without going through a shell
+        let status = run_with_timeout(
+            r#"/bin/sh -c "exit $0" 3"#,
+            options.clone(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(status.and_then(|status| status.code()), Some(3));
+
+        assert!(run_command("   ", options).is_err());
+    }
+
     #[test]
     fn test_extract_executable_with_quoted_path() {
         let command = r#""C:\Program Files\SomeApp\app.exe" --arg1 --arg2"#;
@@ -208,6 +906,21 @@ mod tests {
         assert_eq!(result, Some(String::from(r#"C:\Program Files\app.exe"#)));
     }
 
+    #[test]
+    fn test_extract_executable_stops_at_the_first_exe() {
+        let cmdline = r#"C:\SomeApppp.exe --tool C:\Tools	sc.exe"#;
+        assert_eq!(
+            extract_executable(cmdline),
+            Some(String::from(r#"C:\SomeApppp.exe"#))
+        );
+        // as CreateProcess does, the shortest candidate wins
+        let cmdline = r#"C:	ools.exe dirpp.exe --arg1"#;
+        assert_eq!(
+            extract_executable(cmdline),
+            Some(String::from(r#"C:	ools.exe"#))
+        );
+    }
+
     #[test]
     fn test_extract_executable_with_empty_string() {
         let command = r#""#;
@@ -224,12 +937,20 @@ mod tests {
     }
 
     #[test]
+    #[cfg(windows)]
     fn test_find_working_dir_with_executable_path() {
         let cmdline = r#"C:\SomeApp\app.exe --arg1"#;
         let result = find_working_dir(cmdline, None);
         assert_eq!(result, PathBuf::from(r#"C:\SomeApp"#));
     }
 
+    #[test]
+    fn test_find_working_dir_ignores_later_executables() {
+        let cmdline = "/opt/some app/app.exe --tool /usr/local/bin/tsc.exe";
+        let result = find_working_dir(cmdline, None);
+        assert_eq!(result, PathBuf::from("/opt/some app"));
+    }
+
     #[test]
     fn test_find_working_dir_with_empty_command() {
         let cmdline = r#""#;
@@ -0,0 +1,344 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+};
+
+/// How far in the future a matching time is searched before giving up.
+/// Schedules like "0 0 30 2 *" never match
+const MAX_SEARCH_DAYS: i64 = 366 * 5;
+
+/// A cron style schedule: "minute hour day-of-month month day-of-week".
+///
+/// Each field accepts `*`, single values, ranges (`1-5`), steps (`*/15`, `0-30/10`)
+/// and comma separated lists of them. Day of week goes from 0 (Sunday) to 6, 7 is
+/// accepted as Sunday too. Like in cron, when both day of month and day of week
+/// are restricted a day matches if either of them matches.
+/// The `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shortcuts are supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let expanded = match source {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ => source,
+        };
+
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Invalid schedule: {} (expected 5 fields: minute hour day-of-month month day-of-week)",
+                s
+            ));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        // 7 is Sunday too
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(CronSchedule {
+            source: source.to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+}
+
+/// Parses a single field into a bit set of the allowed values
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid schedule field: {}", field);
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start.parse().map_err(|_| invalid())?,
+                end.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let value = range.parse().map_err(|_| invalid())?;
+            // "5/10" means from 5 to the end, every 10
+            if part.contains('/') {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if !has(self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = has(self.days_of_month, date.day());
+        let day_of_week = has(self.days_of_week, date.weekday().num_days_from_sunday());
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (false, true) => day_of_month,
+            (true, false) => day_of_week,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+
+    /// Returns the first time strictly after `after` matching the schedule.
+    /// The schedule is evaluated in the wall clock time of the `after` time zone:
+    /// * times skipped when the clock moves forward fire at the first valid
+    ///   time after the transition
+    /// * times repeated when the clock moves backward fire only once,
+    ///   at their first occurrence
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)?;
+        let first_day = start.date();
+
+        for day in 0..MAX_SEARCH_DAYS {
+            let date = first_day + Duration::days(day);
+            if !self.matches_day(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|h| has(self.hours, *h)) {
+                for minute in (0..60).filter(|m| has(self.minutes, *m)) {
+                    let naive = date.and_hms_opt(hour, minute, 0)?;
+                    if naive < start {
+                        continue;
+                    }
+                    if let Some(time) = resolve_local(&tz, naive)
+                        && time > *after
+                    {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Maps a wall clock time to an instant in the given time zone
+fn resolve_local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> Option<DateTime<Tz>> {
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(time) => Some(time),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => {
+            // The time falls in a gap: move to the end of it
+            (1..=24 * 60).find_map(|minutes| {
+                tz.from_local_datetime(&(naive + Duration::minutes(minutes)))
+                    .earliest()
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use chrono_tz::Europe::Rome;
+
+    fn schedule(s: &str) -> CronSchedule {
+        s.parse().unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn rome(s: &str) -> DateTime<chrono_tz::Tz> {
+        Rome.from_local_datetime(&s.parse().unwrap())
+            .earliest()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!("0 3 * * *".parse::<CronSchedule>().is_ok());
+        assert!("*/15 8-18 * 1,6-8 1-5".parse::<CronSchedule>().is_ok());
+        assert!("@daily".parse::<CronSchedule>().is_ok());
+        assert!("0 3 * *".parse::<CronSchedule>().is_err());
+        assert!("60 3 * * *".parse::<CronSchedule>().is_err());
+        assert!("0 3 0 * *".parse::<CronSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<CronSchedule>().is_err());
+        assert!("5-1 * * * *".parse::<CronSchedule>().is_err());
+        assert_eq!(schedule(" 0 3 * * * ").to_string(), "0 3 * * *");
+    }
+
+    #[test]
+    fn test_next_daily() {
+        let s = schedule("0 3 * * *");
+        assert_eq!(
+            s.next_after(&utc("2024-05-10T01:20:00Z")),
+            Some(utc("2024-05-10T03:00:00Z"))
+        );
+        // strictly after
+        assert_eq!(
+            s.next_after(&utc("2024-05-10T03:00:00Z")),
+            Some(utc("2024-05-11T03:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_next_steps_and_lists() {
+        let s = schedule("*/20 9,17 * * *");
+        assert_eq!(
+            s.next_after(&utc("2024-05-10T09:41:10Z")),
+            Some(utc("2024-05-10T17:00:00Z"))
+        );
+        assert_eq!(
+            schedule("10/20 * * * *").next_after(&utc("2024-05-10T09:31:00Z")),
+            Some(utc("2024-05-10T09:50:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_next_day_of_week() {
+        // 2024-05-10 is a Friday
+        let s = schedule("0 0 * * 1");
+        assert_eq!(
+            s.next_after(&utc("2024-05-10T12:00:00Z")),
+            Some(utc("2024-05-13T00:00:00Z"))
+        );
+        assert_eq!(
+            schedule("0 0 * * 7").next_after(&utc("2024-05-10T12:00:00Z")),
+            Some(utc("2024-05-12T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_day_of_month_or_day_of_week() {
+        // the 15th or any Monday, whichever comes first
+        let s = schedule("0 0 15 * 1");
+        assert_eq!(
+            s.next_after(&utc("2024-05-10T12:00:00Z")),
+            Some(utc("2024-05-13T00:00:00Z"))
+        );
+        assert_eq!(
+            s.next_after(&utc("2024-05-13T12:00:00Z")),
+            Some(utc("2024-05-15T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_next_month_end_and_leap_day() {
+        assert_eq!(
+            schedule("0 0 31 * *").next_after(&utc("2024-04-01T00:00:00Z")),
+            Some(utc("2024-05-31T00:00:00Z"))
+        );
+        assert_eq!(
+            schedule("0 0 29 2 *").next_after(&utc("2025-01-01T00:00:00Z")),
+            Some(utc("2028-02-29T00:00:00Z"))
+        );
+        assert_eq!(
+            schedule("0 0 30 2 *").next_after(&utc("2025-01-01T00:00:00Z")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dst_spring_forward_gap() {
+        // In Rome on 2024-03-31 the clock jumps from 02:00 to 03:00
+        let s = schedule("30 2 * * *");
+        let next = s.next_after(&rome("2024-03-31T01:00:00")).unwrap();
+        assert_eq!(next.naive_local(), "2024-03-31T03:00:00".parse().unwrap());
+        assert_eq!(next, utc("2024-03-31T01:00:00Z"));
+
+        // and the day after the usual time is used again
+        let next = s.next_after(&next).unwrap();
+        assert_eq!(next.naive_local(), "2024-04-01T02:30:00".parse().unwrap());
+    }
+
+    #[test]
+    fn test_dst_spring_forward_every_minute_of_the_gap_fires_once() {
+        let s = schedule("* 2 * * *");
+        let next = s.next_after(&rome("2024-03-31T01:59:00")).unwrap();
+        assert_eq!(next, utc("2024-03-31T01:00:00Z"));
+        let next = s.next_after(&next).unwrap();
+        assert_eq!(next.naive_local(), "2024-04-01T02:00:00".parse().unwrap());
+    }
+
+    #[test]
+    fn test_dst_fall_back_repeated_hour_fires_once() {
+        // In Rome on 2024-10-27 the clock goes back from 03:00 to 02:00
+        let s = schedule("30 2 * * *");
+        let next = s.next_after(&rome("2024-10-27T01:00:00")).unwrap();
+        assert_eq!(next, utc("2024-10-27T00:30:00Z"));
+
+        // the second 02:30 of the day is skipped
+        let next = s.next_after(&next).unwrap();
+        assert_eq!(next.naive_local(), "2024-10-28T02:30:00".parse().unwrap());
+    }
+
+    #[test]
+    fn test_dst_hourly_across_fall_back() {
+        // hourly schedules keep the wall clock: the repeated 02:00 fires only once
+        let s = schedule("0 * * * *");
+        let mut time = rome("2024-10-27T01:30:00");
+        let mut fired = vec![];
+        for _ in 0..3 {
+            time = s.next_after(&time).unwrap();
+            fired.push(time.with_timezone(&Utc));
+        }
+        assert_eq!(
+            fired,
+            vec![
+                utc("2024-10-27T00:00:00Z"),
+                utc("2024-10-27T02:00:00Z"),
+                utc("2024-10-27T03:00:00Z"),
+            ]
+        );
+    }
+}
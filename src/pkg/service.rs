@@ -5,33 +5,95 @@ use std::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
-    thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::{error, info};
 use windows::{
-    Win32::{Foundation::CloseHandle, System::Services::*},
+    Win32::System::Services::*,
     core::{PCWSTR, PWSTR},
 };
 use windows_service::{
     service::{
-        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
-        ServiceType,
+        ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept, ServiceDependency,
+        ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod, ServiceState,
+        ServiceStatus, ServiceType, UserEventCode,
     },
-    service_control_handler::{self, ServiceControlHandlerResult},
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
 };
 
+use windows_sys::Win32::Foundation::CloseHandle;
+use windows_sys::Win32::Security::{
+    AdjustTokenPrivileges, LookupPrivilegeValueW, SE_PRIVILEGE_ENABLED, SE_SHUTDOWN_NAME,
+    TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES,
+};
+use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
 use windows_service::service::{ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType};
 use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
 
 use std::ffi::OsString;
 
-use crate::cli::{Cli, Commands, LogRotation};
+use crate::cli::{
+    Cli, Commands, InstallArgs, SERVICE_DESCRIPTION_PREFIX, StartType, SupervisorArgs,
+};
 
-use super::runner::run_command;
+use super::dependencies::DependencyGraph;
+use super::events::{Controls, Event, EventQueue};
+use super::logs::flush_logs;
+use super::recovery::{FailureAction, FailureActionKind, Recovery};
+use super::state::StateFile;
+use super::status::{State, Status, StatusReporter, StatusTracker};
+use super::supervisor::{CommandLauncher, Job, supervise};
 
 const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
-pub const SERVICE_DESCRIPTION_PREFIX: &str = "wsw";
+
+/// Reports the service status to the SCM. `pausable` tells the control
+/// handler whether the last reported status accepts pause and continue
+struct ScmStatusReporter {
+    handle: ServiceStatusHandle,
+    pausable: Arc<AtomicBool>,
+}
+
+impl StatusReporter for ScmStatusReporter {
+    fn report(&mut self, status: &Status) {
+        let (current_state, mut controls_accepted) = match status.state {
+            State::StartPending => (
+                ServiceState::StartPending,
+                ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN,
+            ),
+            State::Running => (
+                ServiceState::Running,
+                ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN,
+            ),
+            State::Paused => (
+                ServiceState::Paused,
+                ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN,
+            ),
+            State::StopPending => (ServiceState::StopPending, ServiceControlAccept::empty()),
+            State::Stopped => (ServiceState::Stopped, ServiceControlAccept::empty()),
+        };
+        if status.pausable {
+            controls_accepted |= ServiceControlAccept::PAUSE_CONTINUE;
+        }
+        self.pausable.store(status.pausable, Ordering::SeqCst);
+        let exit_code = match status.exit_code {
+            Some(code) => ServiceExitCode::ServiceSpecific(code),
+            None => ServiceExitCode::Win32(0),
+        };
+
+        if let Err(e) = self.handle.set_service_status(ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state,
+            controls_accepted,
+            exit_code,
+            checkpoint: status.checkpoint,
+            wait_hint: status.wait_hint,
+            process_id: None,
+        }) {
+            error!("Failed to set service status {:?}: {}", current_state, e);
+        }
+    }
+}
 
 pub fn get_service_desc(name: &str) -> String {
     if name == SERVICE_DESCRIPTION_PREFIX {
@@ -47,6 +109,7 @@ pub fn service_main(_args: Vec<OsString>) {
     let svc_name_arg;
     let working_dir_arg: Option<String>;
     let no_logs: bool;
+    let supervisor_args: SupervisorArgs;
 
     match cli.command {
         Some(Commands::Run {
@@ -56,103 +119,76 @@ pub fn service_main(_args: Vec<OsString>) {
             disable_logs,
             log_rotation: _,
             max_log_files: _,
+            supervisor,
         }) => {
-            cmd_arg = cmd.clone();
+            cmd_arg = cmd.unwrap_or_default();
             svc_name_arg = name;
             working_dir_arg = working_dir;
             no_logs = disable_logs;
+            supervisor_args = supervisor;
         }
         _ => {
             panic!("Service main called without --cmd argument");
         }
     }
 
-    let running = Arc::new(AtomicBool::new(true));
-    let stop_flag = running.clone();
-
-    let event_handler =
-        service_control_handler::register(svc_name_arg, move |control_event| match control_event {
-            ServiceControl::Stop => {
-                stop_flag.store(false, Ordering::SeqCst);
-                ServiceControlHandlerResult::NoError
-            }
-            _ => ServiceControlHandlerResult::NotImplemented,
-        })
-        .unwrap();
+    let events = EventQueue::new();
+    let handler_tx = events.sender();
+    let control_table = supervisor_args.control_table();
+    let pausable = Arc::new(AtomicBool::new(false));
+    let handler_pausable = pausable.clone();
+
+    let job = Job {
+        cmd: cmd_arg,
+        working_dir: working_dir_arg,
+        no_logs,
+        args: supervisor_args,
+        state: StateFile::new(&svc_name_arg),
+        events: events.sender(),
+    };
+    job.state.clear();
 
-    event_handler
-        .set_service_status(ServiceStatus {
-            service_type: SERVICE_TYPE,
-            current_state: ServiceState::Running,
-            controls_accepted: ServiceControlAccept::STOP,
-            exit_code: ServiceExitCode::Win32(0),
-            checkpoint: 0,
-            wait_hint: Duration::default(),
-            process_id: None,
-        })
-        .unwrap();
-
-    let running_bg = Arc::clone(&running);
-
-    while running_bg.load(Ordering::SeqCst) {
-        if let Ok(mut process) = run_command(&cmd_arg, working_dir_arg.clone(), no_logs) {
-            info!("Child process started with PID: {}", process.1.id());
-
-            // Poll for shutdown
-            while running_bg.load(Ordering::SeqCst) {
-                thread::sleep(Duration::from_secs(1));
-                let exited = {
-                    match process.1.try_wait() {
-                        Ok(Some(status)) => {
-                            error!("Child exited with status: {}", status);
-                            true
-                        }
-                        Ok(None) => false,
-                        Err(e) => {
-                            info!("Failed to check child status: {}", e);
-                            true
-                        }
-                    }
-                };
-                if exited {
-                    break;
-                }
+    let event_handler = service_control_handler::register(svc_name_arg, move |control_event| {
+        let event = match control_event {
+            ServiceControl::Stop => Event::Stop { shutdown: false },
+            ServiceControl::Preshutdown | ServiceControl::Shutdown => {
+                Event::Stop { shutdown: true }
             }
-
-            let _ = process.1.kill();
-            unsafe {
-                if let Err(e) = CloseHandle(std::mem::transmute(process.0)) {
-                    error!("Failed to close handle: {:?}", e);
-                }
+            // there may be no child to pause, e.g. while waiting to restart it
+            ServiceControl::Pause | ServiceControl::Continue
+                if !handler_pausable.load(Ordering::SeqCst) =>
+            {
+                return ServiceControlHandlerResult::NotImplemented;
             }
-            thread::sleep(Duration::from_secs(1));
-        }
-    }
-
+            ServiceControl::Pause => Event::Pause,
+            ServiceControl::Continue => Event::Resume,
+            ServiceControl::UserEvent(code) => match control_table.dispatch(code.to_raw()) {
+                Some(action) => Event::Control(action.clone()),
+                None => return ServiceControlHandlerResult::NotImplemented,
+            },
+            _ => return ServiceControlHandlerResult::NotImplemented,
+        };
+        let _ = handler_tx.send(event);
+        ServiceControlHandlerResult::NoError
+    })
+    .unwrap();
+    let mut controls = Controls::new(events);
+
+    let mut status = StatusTracker::new(ScmStatusReporter {
+        handle: event_handler,
+        pausable,
+    });
+    let exit_code = supervise(&job, &mut CommandLauncher, &mut controls, &mut status);
+
+    info!("Service stopped");
+    // Windows may kill the process as soon as it reports itself stopped
+    flush_logs();
     // Update status before exiting
-    event_handler
-        .set_service_status(ServiceStatus {
-            service_type: SERVICE_TYPE,
-            current_state: ServiceState::Stopped,
-            controls_accepted: ServiceControlAccept::empty(),
-            exit_code: ServiceExitCode::Win32(0),
-            checkpoint: 0,
-            wait_hint: Duration::default(),
-            process_id: None,
-        })
-        .expect("set service stopped");
+    status.stopped(exit_code);
 }
 
-pub fn install_service(
-    name: &str,
-    working_dir: Option<String>,
-    service_cmd: &str,
-    disable_logs: bool,
-    log_rotation: LogRotation,
-    max_log_files: usize,
-    account_name: Option<String>,
-    account_password: Option<String>
-) -> windows_service::Result<()> {
+pub fn install_service(args: &InstallArgs) -> windows_service::Result<()> {
+    let name = args.name.as_str();
     let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
     let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
 
@@ -160,53 +196,215 @@ pub fn install_service(
 
     let mut launch_arguments = vec![
         OsString::from("run"),
-        OsString::from("--cmd"),
-        OsString::from(service_cmd),
         OsString::from("--name"),
         OsString::from(name),
         OsString::from("--log-rotation"),
-        OsString::from(log_rotation.to_string()),
+        OsString::from(args.log_rotation.to_string()),
         OsString::from("--max-log-files"),
-        OsString::from(max_log_files.to_string()),
+        OsString::from(args.max_log_files.to_string()),
     ];
 
-    if let Some(dir) = working_dir {
+    if let Some(cmd) = &args.cmd {
+        launch_arguments.push(OsString::from("--cmd"));
+        launch_arguments.push(OsString::from(cmd));
+    }
+    if let Some(dir) = &args.working_dir {
         launch_arguments.push(OsString::from("--working-dir"));
         launch_arguments.push(OsString::from(dir));
     }
-    if disable_logs {
+    if args.disable_logs {
         launch_arguments.push(OsString::from("--disable-logs"));
     }
+    launch_arguments.extend(args.supervisor.to_launch_arguments());
 
-    let an = match account_name {
+    let an = match &args.account_name {
         Some(name) => Some(OsString::from(name)),
-        None => None
+        None => None,
     };
 
-    let ap = match account_password {
+    let ap = match &args.account_password {
         Some(password) => Some(OsString::from(password)),
-        None => None
+        None => None,
     };
 
     let service_info = ServiceInfo {
         name: OsString::from(name),
         display_name: OsString::from(get_service_desc(name)),
         service_type: SERVICE_TYPE,
-        start_type: ServiceStartType::AutoStart,
+        start_type: scm_start_type(args.start_type),
         error_control: ServiceErrorControl::Normal,
         executable_path: executable_path.into(),
         launch_arguments,
-        dependencies: vec![],
+        dependencies: args
+            .depends_on
+            .iter()
+            .map(|name| ServiceDependency::Service(OsString::from(name)))
+            .collect(),
         account_name: an,
         account_password: ap,
     };
 
-    let service = service_manager.create_service(&service_info, ServiceAccess::START)?;
+    let service = service_manager.create_service(
+        &service_info,
+        ServiceAccess::START | ServiceAccess::CHANGE_CONFIG,
+    )?;
+    service.set_preshutdown_timeout(args.supervisor.preshutdown_timeout)?;
+    if args.start_type == StartType::Delayed {
+        service.set_delayed_auto_start(true)?;
+    }
 
-    service.start::<std::ffi::OsString>(&[])?;
+    if args.start_type != StartType::Disabled {
+        service.start::<std::ffi::OsString>(&[])?;
+    }
     Ok(())
 }
 
+fn scm_start_type(start_type: StartType) -> ServiceStartType {
+    match start_type {
+        StartType::Auto | StartType::Delayed => ServiceStartType::AutoStart,
+        StartType::Manual => ServiceStartType::OnDemand,
+        StartType::Disabled => ServiceStartType::Disabled,
+    }
+}
+
+pub fn set_start_type(name: &str, start_type: StartType) -> windows_service::Result<()> {
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // Open the existing service
+    let service = manager.open_service(name, ServiceAccess::CHANGE_CONFIG)?;
+
+    // Change only the start type, the rest of the config is left untouched
+    unsafe {
+        ChangeServiceConfigW(
+            SC_HANDLE(service.raw_handle()),
+            ENUM_SERVICE_TYPE(SERVICE_NO_CHANGE),
+            SERVICE_START_TYPE(scm_start_type(start_type).to_raw()),
+            SERVICE_ERROR(SERVICE_NO_CHANGE),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            None,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            PCWSTR::null(),
+        )
+        .map_err(|e| windows_service::Error::Winapi(io::Error::from_raw_os_error(e.code().0)))?;
+    }
+    // the flag is only honored for auto start services
+    service.set_delayed_auto_start(start_type == StartType::Delayed)?;
+    Ok(())
+}
+
+/// Enables the shutdown privilege of the current process,
+/// the SCM refuses to configure the "reboot" failure action without it
+fn enable_shutdown_privilege() -> io::Result<()> {
+    unsafe {
+        let mut token = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut privileges: TOKEN_PRIVILEGES = std::mem::zeroed();
+        privileges.PrivilegeCount = 1;
+        privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+        let result = if LookupPrivilegeValueW(
+            std::ptr::null(),
+            SE_SHUTDOWN_NAME,
+            &mut privileges.Privileges[0].Luid,
+        ) == 0
+            || AdjustTokenPrivileges(
+                token,
+                0,
+                &privileges,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            ) == 0
+        {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        CloseHandle(token);
+        result
+    }
+}
+
+pub fn set_recovery(name: &str, recovery: &Recovery) -> windows_service::Result<()> {
+    if recovery.reboots() {
+        enable_shutdown_privilege().map_err(windows_service::Error::Winapi)?;
+    }
+
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // The "restart" action needs the start access right on top of the config one
+    let service =
+        manager.open_service(name, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+
+    let actions = recovery
+        .actions
+        .iter()
+        .map(|action| ServiceAction {
+            action_type: match action.kind {
+                FailureActionKind::None => ServiceActionType::None,
+                FailureActionKind::Restart => ServiceActionType::Restart,
+                FailureActionKind::RunCommand => ServiceActionType::RunCommand,
+                FailureActionKind::Reboot => ServiceActionType::Reboot,
+            },
+            delay: action.delay,
+        })
+        .collect();
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: match recovery.reset_period {
+            Some(period) => ServiceFailureResetPeriod::After(period),
+            None => ServiceFailureResetPeriod::Never,
+        },
+        reboot_msg: recovery.reboot_message.as_ref().map(OsString::from),
+        command: recovery.command.as_ref().map(OsString::from),
+        actions: Some(actions),
+    })?;
+    service.set_failure_actions_on_non_crash_failures(recovery.on_non_crash_failures)?;
+    Ok(())
+}
+
+pub fn get_recovery(name: &str) -> windows_service::Result<Recovery> {
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // Open the existing service
+    let service = manager.open_service(name, ServiceAccess::QUERY_CONFIG)?;
+
+    let failure_actions = service.get_failure_actions()?;
+    Ok(Recovery {
+        actions: failure_actions
+            .actions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|action| FailureAction {
+                kind: match action.action_type {
+                    ServiceActionType::None => FailureActionKind::None,
+                    ServiceActionType::Restart => FailureActionKind::Restart,
+                    ServiceActionType::RunCommand => FailureActionKind::RunCommand,
+                    ServiceActionType::Reboot => FailureActionKind::Reboot,
+                },
+                delay: action.delay,
+            })
+            .collect(),
+        reset_period: match failure_actions.reset_period {
+            ServiceFailureResetPeriod::Never => None,
+            ServiceFailureResetPeriod::After(period) => Some(period),
+        },
+        command: failure_actions
+            .command
+            .map(|command| command.to_string_lossy().to_string()),
+        reboot_message: failure_actions
+            .reboot_msg
+            .map(|message| message.to_string_lossy().to_string()),
+        on_non_crash_failures: service.get_failure_actions_on_non_crash_failures()?,
+    })
+}
+
 pub fn uninstall_service(name: &str) -> windows_service::Result<()> {
     // Connect to the SCM
     let manager = ServiceManager::local_computer(
@@ -250,6 +448,51 @@ pub fn start_service(name: &str) -> windows_service::Result<()> {
     Ok(())
 }
 
+pub fn pause_service(name: &str) -> windows_service::Result<()> {
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // Open the existing service
+    let service = manager.open_service(
+        name,
+        ServiceAccess::PAUSE_CONTINUE | ServiceAccess::QUERY_STATUS,
+    )?;
+
+    // Pause the service
+    service.pause()?;
+    Ok(())
+}
+
+pub fn resume_service(name: &str) -> windows_service::Result<()> {
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // Open the existing service
+    let service = manager.open_service(
+        name,
+        ServiceAccess::PAUSE_CONTINUE | ServiceAccess::QUERY_STATUS,
+    )?;
+
+    // Resume the service
+    service.resume()?;
+    Ok(())
+}
+
+pub fn send_control_code(name: &str, code: UserEventCode) -> windows_service::Result<()> {
+    // Connect to the SCM
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    // Open the existing service
+    let service = manager.open_service(
+        name,
+        ServiceAccess::USER_DEFINED_CONTROL | ServiceAccess::QUERY_STATUS,
+    )?;
+
+    // Send the user defined control code
+    service.notify(code)?;
+    Ok(())
+}
+
 pub fn get_service_status(name: &str) -> windows_service::Result<ServiceStatus> {
     // Connect to the SCM
     let manager = ServiceManager::local_computer(
@@ -319,20 +562,33 @@ pub fn wait_for_service_status(
     // Open the existing service
     let service = manager.open_service(name, ServiceAccess::QUERY_STATUS)?;
 
-    // Wait for the service to reach the target state
+    // Wait for the service to reach the target state.
+    // While a start or a stop is pending, the timeout is restarted every time
+    // the service reports progress by increasing its checkpoint
+    let mut start = Instant::now();
+    let mut last_checkpoint = 0;
     loop {
         let status = service.query_status()?;
-        let start = std::time::Instant::now();
-        if start.elapsed() > timeout {
+        if status.current_state == target_state {
+            break;
+        }
+        if status.current_state == ServiceState::Stopped {
+            return Err(windows_service::Error::Winapi(io::Error::other(format!(
+                "service stopped with exit code {:?}",
+                status.exit_code
+            ))));
+        }
+        if status.checkpoint > last_checkpoint {
+            last_checkpoint = status.checkpoint;
+            start = Instant::now();
+        }
+        if start.elapsed() > timeout.max(status.wait_hint) {
             tracing::error!("Timeout waiting for service status to change");
             return Err(windows_service::Error::Winapi(io::Error::new(
                 io::ErrorKind::TimedOut,
                 "operation timed out",
             )));
         }
-        if status.current_state == target_state {
-            break;
-        }
         std::thread::sleep(std::time::Duration::from_secs(1));
     }
     Ok(())
@@ -434,6 +690,27 @@ pub fn list_services_with_status() -> windows_service::Result<Vec<(String, Strin
     return Ok(service_list);
 }
 
+/// Builds the dependency graph of the services managed from wsw
+pub fn dependency_graph() -> windows_service::Result<DependencyGraph> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+
+    let mut graph = DependencyGraph::new();
+    for (name, _) in list_services_with_status()? {
+        let service = manager.open_service(&name, ServiceAccess::QUERY_CONFIG)?;
+        let dependencies: Vec<String> = service
+            .query_config()?
+            .dependencies
+            .into_iter()
+            .filter_map(|dependency| match dependency {
+                ServiceDependency::Service(name) => Some(name.to_string_lossy().to_string()),
+                ServiceDependency::Group(_) => None,
+            })
+            .collect();
+        graph.add(&name, &dependencies);
+    }
+    Ok(graph)
+}
+
 fn widestring_to_string(ptr: PWSTR) -> String {
     unsafe {
         if ptr.0.is_null() {
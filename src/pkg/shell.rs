@@ -0,0 +1,321 @@
+use std::fmt;
+use std::io;
+use std::iter;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use regex::Regex;
+
+/// How the command line of the wrapped process is launched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    /// Spawn the executable directly, with the arguments parsed from the command line
+    None,
+    /// cmd.exe /C
+    Cmd,
+    /// Windows PowerShell -Command
+    Powershell,
+    /// PowerShell 7+ -Command
+    Pwsh,
+    /// sh -c
+    Sh,
+}
+
+impl Default for Shell {
+    /// cmd on Windows and sh on Unix, which is how the command line was always launched
+    fn default() -> Self {
+        if cfg!(windows) { Shell::Cmd } else { Shell::Sh }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shell::None => write!(f, "none"),
+            Shell::Cmd => write!(f, "cmd"),
+            Shell::Powershell => write!(f, "powershell"),
+            Shell::Pwsh => write!(f, "pwsh"),
+            Shell::Sh => write!(f, "sh"),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Shell::None),
+            "cmd" => Ok(Shell::Cmd),
+            "powershell" => Ok(Shell::Powershell),
+            "pwsh" => Ok(Shell::Pwsh),
+            "sh" => Ok(Shell::Sh),
+            _ => Err(format!("Invalid shell: {}", s)),
+        }
+    }
+}
+
+impl Shell {
+    /// Builds the command running the command line from the working directory
+    pub fn command(&self, cmdline: &str, working_dir: &Path) -> io::Result<Command> {
+        let (program, args) = match self {
+            Shell::None => {
+                let mut args = parse_command_line(cmdline, working_dir).into_iter();
+                let program = args.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "empty command line")
+                })?;
+                let mut command = Command::new(program);
+                command.args(args);
+                return Ok(command);
+            }
+            Shell::Cmd => ("cmd.exe", vec!["/C"]),
+            Shell::Powershell => (
+                "powershell",
+                vec!["-NoProfile", "-NonInteractive", "-Command"],
+            ),
+            Shell::Pwsh => ("pwsh", vec!["-NoProfile", "-NonInteractive", "-Command"]),
+            Shell::Sh => ("sh", vec!["-c"]),
+        };
+        let mut command = Command::new(program);
+        command.args(args).arg(cmdline);
+        Ok(command)
+    }
+}
+
+/// Splits the executable off the start of a command line, when it can be told apart:
+/// quoted, or unquoted and ending with `.exe`, in which case it may contain spaces.
+/// An unquoted path stops at the first `.exe` followed by a space, the first candidate
+/// `CreateProcess` tries too, so that a later argument is never taken for it.
+/// Returns it together with the rest of the line
+pub fn split_executable(cmdline: &str) -> Option<(String, &str)> {
+    let re = Regex::new(r#"^(?:"([^"]+)"|([^"]*?\.exe))(?:\s|$)"#).unwrap();
+
+    re.captures(cmdline).map(|caps| {
+        // Choose the matching capture group: either quoted (1) or unquoted (2)
+        let exe = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        (exe.to_string(), &cmdline[caps.get(0).unwrap().end()..])
+    })
+}
+
+/// Splits arguments following the rules of the Microsoft C runtime:
+/// arguments are separated by spaces or tabs, double quotes group spaces,
+/// `""` within quotes is a literal quote and backslashes are literal
+/// unless they precede a quote
+pub fn split_arguments(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // an argument was started, even an empty one like ""
+    let mut started = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            backslashes += 1;
+            started = true;
+            continue;
+        }
+        if c == '"' {
+            // 2n backslashes escape each other, 2n+1 escape the quote too
+            current.extend(iter::repeat_n('\\', backslashes / 2));
+            if backslashes % 2 == 1 {
+                current.push('"');
+            } else if quoted && chars.peek() == Some(&'"') {
+                chars.next();
+                current.push('"');
+            } else {
+                quoted = !quoted;
+            }
+            backslashes = 0;
+            started = true;
+            continue;
+        }
+        current.extend(iter::repeat_n('\\', backslashes));
+        backslashes = 0;
+        if (c == ' ' || c == '\t') && !quoted {
+            if started {
+                args.push(std::mem::take(&mut current));
+                started = false;
+            }
+        } else {
+            current.push(c);
+            started = true;
+        }
+    }
+    current.extend(iter::repeat_n('\\', backslashes));
+    if started {
+        args.push(current);
+    }
+    args
+}
+
+/// Parses a command line into the executable and its arguments, as `split_arguments` does.
+/// An unquoted executable path with spaces, as found by `split_executable`, is kept whole
+/// only when it exists, relative to the working directory: otherwise a later argument
+/// ending with `.exe` would be taken for it
+pub fn parse_command_line(cmdline: &str, working_dir: &Path) -> Vec<String> {
+    let cmdline = cmdline.trim_start();
+    match split_executable(cmdline) {
+        Some((exe, rest))
+            if cmdline.starts_with('"')
+                || !exe.contains(char::is_whitespace)
+                || working_dir.join(&exe).is_file() =>
+        {
+            iter::once(exe).chain(split_arguments(rest)).collect()
+        }
+        _ => split_arguments(cmdline),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_shell_round_trip() {
+        for shell in ["none", "cmd", "powershell", "pwsh", "sh"] {
+            assert_eq!(shell.parse::<Shell>().unwrap().to_string(), shell);
+        }
+        assert!("bash".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(
+            split_arguments("--name  my app\t-v"),
+            args(&["--name", "my", "app", "-v"])
+        );
+        assert_eq!(
+            split_arguments(r#"--title "hello world" "" x"#),
+            args(&["--title", "hello world", "", "x"])
+        );
+        // quotes can appear in the middle of an argument
+        assert_eq!(
+            split_arguments(r#"--path="C:\Program Files\app""#),
+            args(&[r#"--path=C:\Program Files\app"#])
+        );
+        assert_eq!(
+            split_arguments(r#""say ""hi""" 100%"#),
+            args(&[r#"say "hi""#, "100%"])
+        );
+        assert_eq!(split_arguments("   "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_split_arguments_backslashes() {
+        assert_eq!(split_arguments(r#"a\\b c\"#), args(&[r#"a\\b"#, r#"c\"#]));
+        assert_eq!(split_arguments(r#"a\"b"#), args(&[r#"a"b"#]));
+        assert_eq!(split_arguments(r#"a\\"b c""#), args(&[r#"a\b c"#]));
+        assert_eq!(
+            split_arguments(r#""C:\dir\\" x"#),
+            args(&[r#"C:\dir\"#, "x"])
+        );
+    }
+
+    #[test]
+    fn test_parse_command_line() {
+        assert_eq!(
+            parse_command_line(
+                r#""C:\Program Files\app.exe" --name "my app""#,
+                Path::new(".")
+            ),
+            args(&[r#"C:\Program Files\app.exe"#, "--name", "my app"])
+        );
+        // an unquoted path with spaces is only recognized when it exists
+        assert_eq!(
+            parse_command_line(
+                r#"C:\Program Files\app.exe --port 80 & echo %PATH%"#,
+                Path::new(".")
+            ),
+            args(&[
+                r#"C:\Program"#,
+                r#"Files\app.exe"#,
+                "--port",
+                "80",
+                "&",
+                "echo",
+                "%PATH%"
+            ])
+        );
+        // a later argument ending with ".exe" is not taken for the executable
+        assert_eq!(
+            parse_command_line(r#"node server.js --tool C:\bin\tsc.exe"#, Path::new(".")),
+            args(&["node", "server.js", "--tool", r#"C:\bin\tsc.exe"#])
+        );
+        // the executable ends at the first ".exe"
+        assert_eq!(
+            parse_command_line(
+                r#"C:\app\setup.exe --install C:\app\agent.exe"#,
+                Path::new(".")
+            ),
+            args(&[r#"C:\app\setup.exe"#, "--install", r#"C:\app\agent.exe"#])
+        );
+        assert_eq!(
+            parse_command_line("  node server.js", Path::new(".")),
+            args(&["node", "server.js"])
+        );
+        assert_eq!(parse_command_line("", Path::new(".")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_command_line_existing_path_with_spaces() {
+        let dir = std::env::temp_dir().join(format!("wsw shell {}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tools dir")).unwrap();
+        let exe = dir.join("tools dir").join("app.exe");
+        std::fs::write(&exe, "").unwrap();
+        let exe = exe.to_string_lossy().to_string();
+        assert_eq!(
+            parse_command_line(&format!("{} --port 80", exe), Path::new(".")),
+            args(&[&exe, "--port", "80"])
+        );
+        // a relative path is looked up in the working directory of the child
+        let relative = Path::new("tools dir").join("app.exe");
+        let relative = relative.to_string_lossy().to_string();
+        assert_eq!(
+            parse_command_line(&format!("{} --port 80", relative), &dir),
+            args(&[&relative, "--port", "80"])
+        );
+        assert_eq!(
+            parse_command_line(&format!("{} --port 80", relative), Path::new(".")),
+            args(&[
+                "tools",
+                &format!("dir{}app.exe", std::path::MAIN_SEPARATOR),
+                "--port",
+                "80"
+            ])
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_command() {
+        let command = Shell::None
+            .command(r#""C:\My App\app.exe" --name "my app""#, Path::new("."))
+            .unwrap();
+        assert_eq!(command.get_program(), r#"C:\My App\app.exe"#);
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["--name", "my app"]);
+
+        let command = Shell::Cmd
+            .command("app.exe & echo done", Path::new("."))
+            .unwrap();
+        assert_eq!(command.get_program(), "cmd.exe");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["/C", "app.exe & echo done"]
+        );
+
+        let command = Shell::Pwsh.command("Get-Date", Path::new(".")).unwrap();
+        assert_eq!(command.get_program(), "pwsh");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-NoProfile", "-NonInteractive", "-Command", "Get-Date"]
+        );
+
+        assert!(Shell::None.command("  ", Path::new(".")).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+#[cfg(any(windows, test))]
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::pkg::logs::get_log_dir;
+
+/// Directory holding the runtime state files. It is a sibling of the log directory
+pub fn get_state_dir() -> PathBuf {
+    let log_dir = get_log_dir();
+    match log_dir.parent() {
+        Some(parent) => parent.join("state"),
+        None => PathBuf::from("state"),
+    }
+}
+
+#[cfg(windows)]
+pub fn get_state_filename(name: &str) -> String {
+    format!("{}.state", name)
+}
+
+/// Runtime information published by the service wrapper, so that "wsw status",
+/// which runs in a different process, can show it.
+/// The information is stored as "key=value" lines and the file is rewritten
+/// on every change.
+#[derive(Clone)]
+pub struct StateFile {
+    path: PathBuf,
+    entries: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl StateFile {
+    #[cfg(windows)]
+    pub fn new(name: &str) -> Self {
+        Self::at(get_state_dir().join(get_state_filename(name)))
+    }
+
+    #[cfg(any(windows, test))]
+    pub fn at(path: PathBuf) -> Self {
+        StateFile {
+            path,
+            entries: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Sets the value for `key`, keeping the position of already existing keys
+    pub fn set(&self, key: &str, value: impl Into<String>) {
+        let value = value.into().replace(['\r', '\n'], " ");
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => entries.push((key.to_string(), value)),
+        }
+        self.write(&entries);
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let len = entries.len();
+        entries.retain(|(k, _)| k != key);
+        if entries.len() != len {
+            self.write(&entries);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Removes all the entries, e.g. the ones left over by a previous run
+    #[cfg(any(windows, test))]
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        self.write(&entries);
+    }
+
+    fn write(&self, entries: &[(String, String)]) {
+        let content: String = entries
+            .iter()
+            .map(|(k, v)| format!("{}={}\n", k, v))
+            .collect();
+
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| {
+                // write to a temporary file first, so that readers never see a partial file
+                let tmp = self.path.with_extension("tmp");
+                fs::write(&tmp, content)?;
+                fs::rename(&tmp, &self.path)
+            });
+        if let Err(e) = result {
+            tracing::error!("Failed to write state file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Reads the state published by the service wrapper
+#[cfg(windows)]
+pub fn read_state(name: &str) -> io::Result<Vec<(String, String)>> {
+    read_state_file(&get_state_dir().join(get_state_filename(name)))
+}
+
+#[cfg(any(windows, test))]
+fn read_state_file(path: &PathBuf) -> io::Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("wsw-state-test-{}", std::process::id()));
+        let path = dir.join("svc.state");
+        let state = StateFile::at(path.clone());
+
+        state.set("Liveness", "healthy");
+        state.set("Last Failure", "connection refused\nfrom 127.0.0.1");
+        state.set("Liveness", "unhealthy");
+        assert_eq!(
+            read_state_file(&path).unwrap(),
+            vec![
+                (String::from("Liveness"), String::from("unhealthy")),
+                (
+                    String::from("Last Failure"),
+                    String::from("connection refused from 127.0.0.1")
+                ),
+            ]
+        );
+
+        state.remove("Liveness");
+        state.remove("Unknown");
+        assert_eq!(
+            read_state_file(&path).unwrap(),
+            vec![(
+                String::from("Last Failure"),
+                String::from("connection refused from 127.0.0.1")
+            )]
+        );
+
+        state.clear();
+        assert!(read_state_file(&path).unwrap().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
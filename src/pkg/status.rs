@@ -0,0 +1,316 @@
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// Service states published by the supervisor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    StartPending,
+    Running,
+    Paused,
+    StopPending,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub state: State,
+    /// Increases on every report while a start or a stop is pending,
+    /// so that the SCM can tell a slow operation apart from a hang
+    pub checkpoint: u32,
+    /// Estimated time before the next report
+    pub wait_hint: Duration,
+    /// Service specific exit code, only meaningful in the Stopped state
+    pub exit_code: Option<u32>,
+    /// Pause and continue requests are accepted
+    pub pausable: bool,
+}
+
+/// Service specific exit codes reported when the service stops for a reason of its own.
+/// Bit 29 marks application defined codes, which Windows errors never use, and
+/// the exit codes of the wrapped process are kept out of the range
+pub const RESERVED_EXIT_CODES: RangeInclusive<u32> = 0x2057_5700..=0x2057_57FF;
+
+/// Reported instead of an exit code of the wrapped process that falls in the reserved range
+pub const RESERVED_CHILD_EXIT_CODE: u32 = *RESERVED_EXIT_CODES.start();
+
+/// The wrapped process is crash looping
+pub const CRASH_LOOP_EXIT_CODE: u32 = RESERVED_CHILD_EXIT_CODE + 1;
+
+/// The wrapped process does not become ready within the startup timeout
+pub const NOT_READY_EXIT_CODE: u32 = RESERVED_CHILD_EXIT_CODE + 2;
+
+/// A pre-start or post-start hook fails and "hook-failure-aborts-start" is set
+pub const HOOK_FAILED_EXIT_CODE: u32 = RESERVED_CHILD_EXIT_CODE + 3;
+
+/// The process group can't be built, e.g. because the Procfile is gone
+pub const INVALID_GROUP_EXIT_CODE: u32 = RESERVED_CHILD_EXIT_CODE + 4;
+
+/// Converts the exit code of the wrapped process to the service specific
+/// exit code reported when the service stops because of it.
+/// Clean exits are not reported
+pub fn service_exit_code(child_exit_code: Option<i32>) -> Option<u32> {
+    child_exit_code
+        .filter(|code| *code != 0)
+        // Windows exit codes are unsigned, e.g. 0xC0000005 for an access violation
+        .map(|code| code as u32)
+        .map(|code| {
+            if RESERVED_EXIT_CODES.contains(&code) {
+                RESERVED_CHILD_EXIT_CODE
+            } else {
+                code
+            }
+        })
+}
+
+/// Tells what a service specific exit code of the reserved range means
+#[cfg(any(windows, test))]
+pub fn describe_exit_code(code: u32) -> Option<&'static str> {
+    match code {
+        RESERVED_CHILD_EXIT_CODE => Some("the app exit code is in the reserved range"),
+        CRASH_LOOP_EXIT_CODE => Some("the app is crash looping"),
+        NOT_READY_EXIT_CODE => Some("the app did not become ready"),
+        HOOK_FAILED_EXIT_CODE => Some("a start hook failed"),
+        INVALID_GROUP_EXIT_CODE => Some("invalid process group"),
+        _ => None,
+    }
+}
+
+/// Publishes the service status. When running as a Windows service
+/// the status is sent to the SCM
+pub trait StatusReporter {
+    fn report(&mut self, status: &Status);
+}
+
+/// Keeps track of the current state and of the pending operations checkpoint
+pub struct StatusTracker<R: StatusReporter> {
+    reporter: R,
+    state: Option<State>,
+    checkpoint: u32,
+    /// There is something to pause while running, e.g. the child is up
+    pausable: bool,
+}
+
+impl<R: StatusReporter> StatusTracker<R> {
+    #[cfg(any(windows, test))]
+    pub fn new(reporter: R) -> Self {
+        StatusTracker {
+            reporter,
+            state: None,
+            checkpoint: 0,
+            pausable: false,
+        }
+    }
+
+    pub fn state(&self) -> Option<State> {
+        self.state
+    }
+
+    pub fn start_pending(&mut self, wait_hint: Duration) {
+        self.pending(State::StartPending, wait_hint);
+    }
+
+    pub fn running(&mut self) {
+        self.report(State::Running, 0, Duration::ZERO, None);
+    }
+
+    pub fn paused(&mut self) {
+        self.report(State::Paused, 0, Duration::ZERO, None);
+    }
+
+    /// Tells if pause requests can be accepted while running.
+    /// A paused service always accepts to continue
+    pub fn set_pausable(&mut self, pausable: bool) {
+        if self.pausable != pausable {
+            self.pausable = pausable;
+            if self.state == Some(State::Running) {
+                self.running();
+            }
+        }
+    }
+
+    pub fn stop_pending(&mut self, wait_hint: Duration) {
+        self.pending(State::StopPending, wait_hint);
+    }
+
+    #[cfg(any(windows, test))]
+    pub fn stopped(&mut self, exit_code: Option<u32>) {
+        self.report(State::Stopped, 0, Duration::ZERO, exit_code);
+    }
+
+    fn pending(&mut self, state: State, wait_hint: Duration) {
+        let checkpoint = if self.state == Some(state) {
+            self.checkpoint + 1
+        } else {
+            1
+        };
+        self.report(state, checkpoint, wait_hint, None);
+    }
+
+    fn report(
+        &mut self,
+        state: State,
+        checkpoint: u32,
+        wait_hint: Duration,
+        exit_code: Option<u32>,
+    ) {
+        self.state = Some(state);
+        self.checkpoint = checkpoint;
+        self.reporter.report(&Status {
+            state,
+            checkpoint,
+            wait_hint,
+            exit_code,
+            pausable: match state {
+                State::Running => self.pausable,
+                State::Paused => true,
+                _ => false,
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct RecordingReporter {
+        reports: Rc<RefCell<Vec<Status>>>,
+    }
+
+    impl StatusReporter for RecordingReporter {
+        fn report(&mut self, status: &Status) {
+            self.reports.borrow_mut().push(status.clone());
+        }
+    }
+
+    impl RecordingReporter {
+        fn states(&self) -> Vec<(State, u32)> {
+            self.reports
+                .borrow()
+                .iter()
+                .map(|s| (s.state, s.checkpoint))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_start_and_stop_sequence() {
+        let recorder = RecordingReporter::default();
+        let mut tracker = StatusTracker::new(recorder.clone());
+        let hint = Duration::from_secs(5);
+
+        tracker.start_pending(hint);
+        tracker.start_pending(hint);
+        tracker.running();
+        tracker.stop_pending(hint);
+        tracker.stop_pending(hint);
+        tracker.stop_pending(hint);
+        tracker.stopped(None);
+
+        assert_eq!(
+            recorder.states(),
+            vec![
+                (State::StartPending, 1),
+                (State::StartPending, 2),
+                (State::Running, 0),
+                (State::StopPending, 1),
+                (State::StopPending, 2),
+                (State::StopPending, 3),
+                (State::Stopped, 0),
+            ]
+        );
+        assert_eq!(tracker.state(), Some(State::Stopped));
+    }
+
+    #[test]
+    fn test_pause_and_continue() {
+        let recorder = RecordingReporter::default();
+        let mut tracker = StatusTracker::new(recorder.clone());
+        let hint = Duration::from_secs(5);
+
+        tracker.running();
+        tracker.paused();
+        tracker.running();
+        tracker.paused();
+        tracker.stop_pending(hint);
+
+        assert_eq!(
+            recorder.states(),
+            vec![
+                (State::Running, 0),
+                (State::Paused, 0),
+                (State::Running, 0),
+                (State::Paused, 0),
+                (State::StopPending, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pausable() {
+        let recorder = RecordingReporter::default();
+        let mut tracker = StatusTracker::new(recorder.clone());
+
+        tracker.start_pending(Duration::from_secs(5));
+        tracker.set_pausable(true);
+        tracker.running();
+        tracker.paused();
+        tracker.running();
+        // the child is gone: the running state is reported again, without pause
+        tracker.set_pausable(false);
+        tracker.set_pausable(false);
+
+        let reports = recorder.reports.borrow();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|s| (s.state, s.pausable))
+                .collect::<Vec<_>>(),
+            vec![
+                (State::StartPending, false),
+                (State::Running, true),
+                (State::Paused, true),
+                (State::Running, true),
+                (State::Running, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_service_exit_code() {
+        assert_eq!(service_exit_code(None), None);
+        assert_eq!(service_exit_code(Some(0)), None);
+        assert_eq!(service_exit_code(Some(3)), Some(3));
+        assert_eq!(service_exit_code(Some(-1073741819)), Some(0xC0000005));
+        // the exit codes of the app never look like a reason of the service
+        for code in [CRASH_LOOP_EXIT_CODE, HOOK_FAILED_EXIT_CODE] {
+            assert_eq!(
+                service_exit_code(Some(code as i32)),
+                Some(RESERVED_CHILD_EXIT_CODE)
+            );
+        }
+        assert_eq!(
+            service_exit_code(Some(3)).and_then(describe_exit_code),
+            None
+        );
+        assert!(describe_exit_code(CRASH_LOOP_EXIT_CODE).is_some());
+    }
+
+    #[test]
+    fn test_pending_reports_wait_hint() {
+        let recorder = RecordingReporter::default();
+        let mut tracker = StatusTracker::new(recorder.clone());
+        tracker.stop_pending(Duration::from_secs(15));
+        tracker.stopped(Some(7));
+
+        let reports = recorder.reports.borrow();
+        assert_eq!(reports[0].wait_hint, Duration::from_secs(15));
+        assert_eq!(reports[0].exit_code, None);
+        assert_eq!(reports[1].wait_hint, Duration::ZERO);
+        assert_eq!(reports[1].exit_code, Some(7));
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+use crate::pkg::runner::{ChildProcess, ConsoleEvent};
+
+/// How the wrapped process is asked to exit when the service stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMethod {
+    /// Send CTRL_BREAK to the child process group (SIGTERM on Unix)
+    CtrlBreak,
+    /// Send CTRL_C to the child console (SIGINT on Unix)
+    CtrlC,
+    /// Write the stop line to the child stdin
+    Stdin,
+    /// Kill the child straight away
+    Kill,
+}
+
+impl fmt::Display for StopMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopMethod::CtrlBreak => write!(f, "ctrl-break"),
+            StopMethod::CtrlC => write!(f, "ctrl-c"),
+            StopMethod::Stdin => write!(f, "stdin"),
+            StopMethod::Kill => write!(f, "kill"),
+        }
+    }
+}
+
+impl FromStr for StopMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ctrl-break" => Ok(StopMethod::CtrlBreak),
+            "ctrl-c" => Ok(StopMethod::CtrlC),
+            "stdin" => Ok(StopMethod::Stdin),
+            "kill" => Ok(StopMethod::Kill),
+            _ => Err(format!("Invalid stop method: {}", s)),
+        }
+    }
+}
+
+/// A way to ask the child to exit gracefully
+pub trait StopStrategy: Send {
+    fn request_stop(&self, process: &mut ChildProcess) -> io::Result<()>;
+}
+
+/// Delivers a console control event to the child process group
+pub struct ConsoleEventStop(pub ConsoleEvent);
+
+impl StopStrategy for ConsoleEventStop {
+    fn request_stop(&self, process: &mut ChildProcess) -> io::Result<()> {
+        process.send_console_event(self.0)
+    }
+}
+
+/// Writes a line to the child stdin
+pub struct StdinLineStop(pub String);
+
+impl StopStrategy for StdinLineStop {
+    fn request_stop(&self, process: &mut ChildProcess) -> io::Result<()> {
+        process.write_stdin_line(&self.0)
+    }
+}
+
+/// Builds the strategy for the given method.
+/// Returns None if the child has to be killed without asking
+pub fn build_strategy(
+    method: StopMethod,
+    stop_line: Option<&str>,
+) -> Option<Box<dyn StopStrategy>> {
+    match method {
+        StopMethod::CtrlBreak => Some(Box::new(ConsoleEventStop(ConsoleEvent::CtrlBreak))),
+        StopMethod::CtrlC => Some(Box::new(ConsoleEventStop(ConsoleEvent::CtrlC))),
+        StopMethod::Stdin => Some(Box::new(StdinLineStop(
+            stop_line.unwrap_or_default().to_string(),
+        ))),
+        StopMethod::Kill => None,
+    }
+}
+
+/// Stops the child: it is first asked to exit using the strategy, then after
+/// `timeout` the whole process tree is killed.
+/// `progress` is called every second while waiting for the child to exit.
+/// Returns the child exit status, if available
+pub fn stop_child(
+    process: &mut ChildProcess,
+    strategy: Option<&dyn StopStrategy>,
+    timeout: Duration,
+    progress: &mut dyn FnMut(),
+) -> Option<ExitStatus> {
+    if let Some(strategy) = strategy {
+        match strategy.request_stop(process) {
+            Ok(_) => {
+                info!("Waiting up to {:?} for the child process to exit", timeout);
+                let deadline = Instant::now() + timeout;
+                let mut last_progress = Instant::now();
+                loop {
+                    match process.try_wait() {
+                        Ok(Some(status)) => {
+                            info!("Child process exited gracefully with status: {}", status);
+                            return Some(status);
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            error!("Failed to check child status: {}", e);
+                            break;
+                        }
+                    }
+                    let now = Instant::now();
+                    if now >= deadline {
+                        warn!("Child process did not exit within {:?}", timeout);
+                        break;
+                    }
+                    if now.duration_since(last_progress) >= Duration::from_secs(1) {
+                        last_progress = now;
+                        progress();
+                    }
+                    thread::sleep((deadline - now).min(Duration::from_millis(100)));
+                }
+            }
+            Err(e) => error!("Failed to ask the child process to stop: {}", e),
+        }
+    }
+
+    info!("Killing the child process tree");
+    match process.terminate() {
+        Ok(status) => Some(status),
+        Err(e) => {
+            error!("Failed to kill the child process tree: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::pkg::runner::{RunOptions, run_command};
+    use std::os::unix::process::ExitStatusExt;
+
+    fn spawn(cmdline: &str, pipe_stdin: bool) -> ChildProcess {
+        let process = run_command(
+            cmdline,
+            RunOptions {
+                working_dir: Some(String::from("/")),
+                disable_logs: true,
+                pipe_stdin,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // give the shell the time to install its traps
+        thread::sleep(Duration::from_millis(200));
+        process
+    }
+
+    #[test]
+    fn test_stop_method_from_str() {
+        assert_eq!("ctrl-break".parse(), Ok(StopMethod::CtrlBreak));
+        assert_eq!("CTRL-C".parse(), Ok(StopMethod::CtrlC));
+        assert_eq!("stdin".parse(), Ok(StopMethod::Stdin));
+        assert_eq!("kill".parse(), Ok(StopMethod::Kill));
+        assert!("sigterm".parse::<StopMethod>().is_err());
+    }
+
+    #[test]
+    fn test_stop_child_gracefully_with_signal() {
+        let mut process = spawn("trap 'exit 3' TERM; while true; do sleep 0.1; done", false);
+        let strategy = build_strategy(StopMethod::CtrlBreak, None);
+        let status = stop_child(
+            &mut process,
+            strategy.as_deref(),
+            Duration::from_secs(5),
+            &mut || {},
+        );
+        assert_eq!(status.and_then(|s| s.code()), Some(3));
+    }
+
+    #[test]
+    fn test_stop_child_gracefully_with_stdin_line() {
+        let mut process = spawn("read line; [ \"$line\" = quit ] && exit 4", true);
+        let strategy = build_strategy(StopMethod::Stdin, Some("quit"));
+        let status = stop_child(
+            &mut process,
+            strategy.as_deref(),
+            Duration::from_secs(5),
+            &mut || {},
+        );
+        assert_eq!(status.and_then(|s| s.code()), Some(4));
+    }
+
+    #[test]
+    fn test_stop_child_is_killed_after_timeout() {
+        let mut process = spawn("trap '' TERM; while true; do sleep 0.1; done", false);
+        let strategy = build_strategy(StopMethod::CtrlBreak, None);
+        let started = Instant::now();
+        let mut progress = 0;
+        let status = stop_child(
+            &mut process,
+            strategy.as_deref(),
+            Duration::from_millis(1500),
+            &mut || progress += 1,
+        );
+        assert!(started.elapsed() >= Duration::from_millis(1500));
+        assert_eq!(progress, 1);
+        assert_eq!(status.and_then(|s| s.signal()), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn test_stop_child_kill_right_away() {
+        let mut process = spawn("sleep 30", false);
+        let strategy = build_strategy(StopMethod::Kill, None);
+        assert!(strategy.is_none());
+        let status = stop_child(&mut process, None, Duration::from_secs(30), &mut || {});
+        assert_eq!(status.and_then(|s| s.signal()), Some(libc::SIGKILL));
+    }
+}
@@ -0,0 +1,2117 @@
+use chrono::{DateTime, Local};
+use std::{
+    io,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::cli::SupervisorArgs;
+
+use super::control::ControlAction;
+use super::duration::with_jitter;
+use super::events::{Controls, Event, EventSource, Waker, earliest, waker};
+use super::exit_action::{ExitAction, ExitActionTable};
+use super::group::{GroupAction, ProcessSpec, on_process_exit};
+use super::hooks::{ENV_EXIT_CODE, ENV_RESTART_COUNT, HookPoint, Hooks};
+use super::limits::LimitViolation;
+use super::liveness::{LivenessMonitor, ProbeConfig};
+use super::log_writer::chain_observers;
+use super::logs::{get_log_dir, reopen_logs};
+use super::metrics::{Usage, UsagePublisher, UsageSource};
+use super::notify::NotifyListener;
+use super::output_trigger::OutputTrigger;
+use super::readiness::Readiness;
+use super::restart::{
+    CrashLoopDetector, RestartDecision, RestartPolicy, RestartTracker, random_fraction,
+};
+#[cfg(windows)]
+use super::runner::run_command;
+use super::runner::{ChildProcess, ConsoleEvent, RunOptions, find_working_dir};
+use super::state::{StateFile, get_state_dir};
+use super::status::{
+    CRASH_LOOP_EXIT_CODE, HOOK_FAILED_EXIT_CODE, INVALID_GROUP_EXIT_CODE, NOT_READY_EXIT_CODE,
+    State, StatusReporter, StatusTracker, service_exit_code,
+};
+use super::stop::{StopStrategy, build_strategy, stop_child};
+use super::thresholds::{ThresholdMonitor, Thresholds};
+use super::watch::{FileWatcher, WatchMonitor, watch_targets};
+
+/// Wait hint reported while the child process is being launched
+const START_WAIT_HINT: Duration = Duration::from_secs(10);
+
+/// Extra time, on top of the stop timeout, needed to kill the child process tree
+const KILL_WAIT_HINT: Duration = Duration::from_secs(5);
+
+/// How often the SCM checkpoint moves and the readiness is checked while starting
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the usage is sampled when thresholds are configured
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Minimum delay before starting again a child that could not be started
+const START_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// What the service supervises
+pub struct Job {
+    /// Command line of the child process, empty for a process group
+    pub cmd: String,
+    pub working_dir: Option<String>,
+    pub no_logs: bool,
+    pub args: SupervisorArgs,
+    pub state: StateFile,
+    /// Where the wakers send their events, e.g. the exit of a child process
+    pub events: Sender<Event>,
+}
+
+/// Starts the supervised processes
+pub trait Launcher {
+    type Process: Supervised;
+
+    fn launch(&mut self, cmdline: &str, options: RunOptions) -> io::Result<Self::Process>;
+}
+
+/// Launches real processes
+#[cfg(windows)]
+pub struct CommandLauncher;
+
+#[cfg(windows)]
+impl Launcher for CommandLauncher {
+    type Process = ChildProcess;
+
+    fn launch(&mut self, cmdline: &str, options: RunOptions) -> io::Result<ChildProcess> {
+        run_command(cmdline, options)
+    }
+}
+
+/// What the supervisor does with a running process
+pub trait Supervised: UsageSource {
+    fn id(&self) -> u32;
+    fn on_exit(&self, wake: Waker) -> io::Result<()>;
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>>;
+    fn suspend(&self) -> io::Result<()>;
+    fn resume(&self) -> io::Result<()>;
+    fn limit_violations(&self) -> Vec<LimitViolation>;
+    fn send_console_event(&self, event: ConsoleEvent) -> io::Result<()>;
+    fn write_stdin_line(&mut self, line: &str) -> io::Result<()>;
+    /// Asks the process to stop, and kills its tree after the timeout
+    fn stop(
+        &mut self,
+        strategy: Option<&dyn StopStrategy>,
+        timeout: Duration,
+        progress: &mut dyn FnMut(),
+    ) -> Option<ExitStatus>;
+}
+
+impl Supervised for ChildProcess {
+    fn id(&self) -> u32 {
+        ChildProcess::id(self)
+    }
+
+    fn on_exit(&self, wake: Waker) -> io::Result<()> {
+        ChildProcess::on_exit(self, wake)
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        ChildProcess::try_wait(self)
+    }
+
+    fn suspend(&self) -> io::Result<()> {
+        ChildProcess::suspend(self)
+    }
+
+    fn resume(&self) -> io::Result<()> {
+        ChildProcess::resume(self)
+    }
+
+    fn limit_violations(&self) -> Vec<LimitViolation> {
+        ChildProcess::limit_violations(self)
+    }
+
+    fn send_console_event(&self, event: ConsoleEvent) -> io::Result<()> {
+        ChildProcess::send_console_event(self, event)
+    }
+
+    fn write_stdin_line(&mut self, line: &str) -> io::Result<()> {
+        ChildProcess::write_stdin_line(self, line)
+    }
+
+    fn stop(
+        &mut self,
+        strategy: Option<&dyn StopStrategy>,
+        timeout: Duration,
+        progress: &mut dyn FnMut(),
+    ) -> Option<ExitStatus> {
+        stop_child(self, strategy, timeout, progress)
+    }
+}
+
+/// How a run of the child process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunOutcome {
+    /// The child could not be started
+    StartFailed,
+    /// The child exited on its own. There is no status when its exit
+    /// could not be checked
+    Exited(Option<ExitStatus>),
+    /// The child was stopped because it was unhealthy
+    Restarted(Option<ExitStatus>),
+    /// The child was stopped to be started afresh, which is not a failure
+    Recycled(Option<ExitStatus>),
+    /// The service stops, with the given exit code
+    Stopped {
+        exit: Option<ExitStatus>,
+        exit_code: Option<u32>,
+    },
+}
+
+impl RunOutcome {
+    fn exit(&self) -> Option<ExitStatus> {
+        match self {
+            RunOutcome::StartFailed => None,
+            RunOutcome::Exited(exit) | RunOutcome::Restarted(exit) | RunOutcome::Recycled(exit) => {
+                *exit
+            }
+            RunOutcome::Stopped { exit, .. } => *exit,
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        match self {
+            RunOutcome::Exited(exit) => exit.is_some_and(|exit| exit.success()),
+            RunOutcome::Recycled(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// What to do once the child process is gone
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AfterExit {
+    /// Start the child again after the delay
+    Restart(Duration),
+    /// Leave the child down until a restart-child control code
+    WaitForRestart,
+    /// Stop the service with the exit code
+    Stop(Option<u32>),
+}
+
+/// Decides what happens once the child is gone: the exit action mapped to its
+/// exit code comes first, then the restart policy, then the crash loop detection
+struct RestartPlanner {
+    exit_actions: ExitActionTable,
+    restarts: RestartTracker,
+    crash_loop: CrashLoopDetector,
+    policy: RestartPolicy,
+    window: Duration,
+}
+
+impl RestartPlanner {
+    fn new(args: &SupervisorArgs) -> Self {
+        RestartPlanner {
+            exit_actions: args.exit_action_table(),
+            restarts: RestartTracker::new(args.restart, args.backoff(), args.restart_reset_after),
+            crash_loop: CrashLoopDetector::new(args.max_restarts, args.restart_window),
+            policy: args.restart,
+            window: args.restart_window,
+        }
+    }
+
+    /// Decides for a child that is gone for good, i.e. neither recycled
+    /// nor stopped with the service
+    fn decide(&mut self, outcome: RunOutcome) -> AfterExit {
+        let exit_code = outcome.exit().and_then(|exit| exit.code());
+        // the exit code mapping only applies when the child exits on its own
+        let exit_action = match outcome {
+            RunOutcome::Exited(_) => self.exit_actions.action(exit_code),
+            _ => None,
+        };
+        let decision = match exit_action {
+            None => self.restarts.child_exited(outcome.is_success()),
+            Some(action) => {
+                info!("Exit action: {}", action);
+                match action {
+                    ExitAction::Restart => RestartDecision::Restart(self.restarts.restart()),
+                    ExitAction::RestartAfter(delay) => RestartDecision::Restart(delay),
+                    ExitAction::StopService => RestartDecision::Stop,
+                    ExitAction::Ignore => return AfterExit::WaitForRestart,
+                }
+            }
+        };
+
+        match decision {
+            RestartDecision::Restart(_) if !self.crash_loop.record_restart() => {
+                error!(
+                    "Child process is crash looping: giving up after {} restarts in {:?}",
+                    self.crash_loop.restarts(),
+                    self.window
+                );
+                AfterExit::Stop(Some(CRASH_LOOP_EXIT_CODE))
+            }
+            // without a floor, a child that can't even be started would
+            // be retried in a tight loop with a zero restart delay
+            RestartDecision::Restart(delay) if outcome == RunOutcome::StartFailed => {
+                AfterExit::Restart(delay.max(START_RETRY_DELAY))
+            }
+            RestartDecision::Restart(delay) => AfterExit::Restart(delay),
+            RestartDecision::Stop if exit_action.is_some() => {
+                info!("Child process will not be restarted");
+                AfterExit::Stop(service_exit_code(exit_code))
+            }
+            RestartDecision::Stop => {
+                info!(
+                    "Child process will not be restarted (restart policy: {})",
+                    self.policy
+                );
+                AfterExit::Stop(service_exit_code(exit_code))
+            }
+        }
+    }
+}
+
+/// Why the watch over a running child ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WatchEnd {
+    /// The child exited on its own
+    Exited(Option<ExitStatus>),
+    /// The child is unhealthy and must be restarted, for the given reason
+    Restart(String),
+    /// The child must be started afresh, for the given reason
+    Recycle(&'static str),
+    /// The service stops, with the given exit code
+    Stop(Option<u32>),
+}
+
+/// A running child process and what is tracked about it
+struct ChildRun<P> {
+    process: P,
+    started_at: Instant,
+    /// When the restart schedule recycles the child
+    recycle_at: Option<DateTime<Local>>,
+    /// False when the exit of the child must be polled
+    exit_watched: bool,
+    monitor: ThresholdMonitor,
+    liveness: Option<LivenessMonitor>,
+    suspended: bool,
+    restart_requested: bool,
+    /// Last limit the child ran into
+    limit_hit: Option<LimitViolation>,
+    sampled_at: Option<Instant>,
+}
+
+/// Runs the single child process of the service, one run after the other
+struct ChildSupervisor<'a> {
+    job: &'a Job,
+    hooks: Hooks,
+    stop_wait_hint: Duration,
+    stop_strategy: Option<Box<dyn StopStrategy>>,
+    readiness: Option<Readiness>,
+    notify: Option<NotifyListener>,
+    liveness_config: Option<ProbeConfig>,
+    output_trigger: Option<OutputTrigger>,
+    thresholds: Thresholds,
+    /// Resolved once, so that the hooks run where the child does
+    working_dir: PathBuf,
+    watch: Option<WatchMonitor>,
+    /// When the service started, the readiness timeout counts from there
+    started_at: Instant,
+    publisher: UsagePublisher,
+}
+
+impl<'a> ChildSupervisor<'a> {
+    /// Fails with the service exit code if the notification socket can't be opened
+    fn new(job: &'a Job) -> Result<Self, u32> {
+        let supervisor_args = &job.args;
+        let hooks = supervisor_args.hooks();
+        let stop_hooks = [HookPoint::PreStop, HookPoint::PostStop]
+            .into_iter()
+            .filter(|point| hooks.command(*point).is_some())
+            .count() as u32;
+        let stop_wait_hint =
+            supervisor_args.stop_timeout + KILL_WAIT_HINT + hooks.timeout * stop_hooks;
+        let stop_strategy = build_strategy(
+            supervisor_args.stop_method,
+            supervisor_args.stop_line.as_deref(),
+        );
+        let notify = if supervisor_args.notify {
+            match NotifyListener::start(job.state.clone(), waker(&job.events, Event::Wake)) {
+                Ok(listener) => {
+                    info!("Listening for notifications on {}", listener.address());
+                    Some(listener)
+                }
+                Err(e) => {
+                    error!("Failed to open the notification socket: {}", e);
+                    return Err(NOT_READY_EXIT_CODE);
+                }
+            }
+        } else {
+            None
+        };
+        let output_trigger = supervisor_args.output_trigger();
+        if job.no_logs && output_trigger.is_some() {
+            warn!("The output restart rules are ignored: the output is not captured");
+        }
+        let mut thresholds = supervisor_args.thresholds();
+        if job.no_logs && thresholds.silence.take().is_some() {
+            warn!("The silence timeout is ignored: the output is not captured");
+        }
+        let working_dir = find_working_dir(&job.cmd, job.working_dir.clone());
+        let watch = if supervisor_args.watch.is_empty() {
+            None
+        } else {
+            let targets = watch_targets(&supervisor_args.watch, &working_dir);
+            // never react to our own files
+            let ignored = vec![get_log_dir(), get_state_dir()];
+            Some(WatchMonitor::start(
+                FileWatcher::new(targets, ignored, supervisor_args.watch_debounce),
+                waker(&job.events, Event::Wake),
+            ))
+        };
+        Ok(ChildSupervisor {
+            job,
+            hooks,
+            stop_wait_hint,
+            stop_strategy,
+            readiness: supervisor_args.ready.clone().map(Readiness::new),
+            notify,
+            liveness_config: supervisor_args.liveness_config(),
+            output_trigger,
+            thresholds,
+            working_dir,
+            watch,
+            started_at: Instant::now(),
+            publisher: UsagePublisher::default(),
+        })
+    }
+
+    /// Runs the child once: starts it, watches it until it exits, has to be
+    /// restarted or the service stops, then stops it
+    fn run<E: EventSource, R: StatusReporter, L: Launcher>(
+        &mut self,
+        planner: &mut RestartPlanner,
+        launcher: &mut L,
+        controls: &mut Controls<E>,
+        status: &mut StatusTracker<R>,
+    ) -> RunOutcome {
+        let job = self.job;
+        let supervisor_args = &job.args;
+        let mut run_options = supervisor_args.run_options(
+            Some(self.working_dir.to_string_lossy().into_owned()),
+            job.no_logs,
+        );
+        if let Some(readiness) = &mut self.readiness {
+            run_options.output_observer = readiness.child_started(waker(&job.events, Event::Wake));
+        }
+        let monitor = ThresholdMonitor::new(self.thresholds.clone(), Instant::now());
+        run_options.output_observer =
+            chain_observers(run_options.output_observer, monitor.output_observer());
+        if let Some(output_trigger) = &self.output_trigger {
+            run_options.output_observer = chain_observers(
+                run_options.output_observer,
+                Some(output_trigger.child_started(waker(&job.events, Event::Output))),
+            );
+        }
+        if let Some(notify) = &self.notify {
+            run_options
+                .env
+                .extend(notify.env(supervisor_args.watchdog_timeout));
+        }
+
+        if status.state() == Some(State::StartPending) {
+            status.start_pending(START_WAIT_HINT + self.hooks.timeout);
+        }
+        if self
+            .hooks
+            .run(HookPoint::PreStart, &run_options, &[])
+            .is_err()
+            && supervisor_args.hook_failure_aborts_start
+        {
+            return RunOutcome::Stopped {
+                exit: None,
+                exit_code: Some(HOOK_FAILED_EXIT_CODE),
+            };
+        }
+
+        if let Some(notify) = &self.notify {
+            notify.child_started();
+        }
+        let outcome = match launcher.launch(&job.cmd, run_options.clone()) {
+            Ok(process) => {
+                let mut run = self.started(process, monitor, planner, controls, status);
+                if status.state() == Some(State::StartPending) {
+                    status.start_pending(START_WAIT_HINT + self.hooks.timeout);
+                }
+                let end = if self
+                    .hooks
+                    .run(HookPoint::PostStart, &run_options, &[])
+                    .is_err()
+                    && supervisor_args.hook_failure_aborts_start
+                {
+                    WatchEnd::Stop(Some(HOOK_FAILED_EXIT_CODE))
+                } else {
+                    if status.state() == Some(State::StartPending)
+                        && self.readiness.is_none()
+                        && self.notify.is_none()
+                    {
+                        status.running();
+                    }
+                    self.watch(&mut run, planner, controls, status)
+                };
+                run.liveness = None;
+                let outcome =
+                    self.stop(&mut run, end, &run_options, controls.shutting_down, status);
+
+                // the pause ends with the child, and so does a request in flight
+                status.set_pausable(false);
+                controls.pause_requested = false;
+                if status.state() == Some(State::Paused) {
+                    status.running();
+                }
+                let _ = self.hooks.run(HookPoint::PostStop, &run_options, &[]);
+                outcome
+            }
+            Err(e) => {
+                error!("Failed to run cmd: {:?}", e);
+                if status.state() == Some(State::StartPending) {
+                    status.start_pending(START_WAIT_HINT);
+                }
+                RunOutcome::StartFailed
+            }
+        };
+        self.publisher.clear(&job.state);
+
+        let exit = outcome.exit();
+        if let Some(exit) = exit {
+            job.state.set(
+                "Last Child Exit",
+                format!("{} at {}", exit, Local::now().format("%Y-%m-%d %H:%M:%S")),
+            );
+        }
+        if !outcome.is_success() && !matches!(outcome, RunOutcome::Stopped { .. }) {
+            let env = [
+                (
+                    ENV_EXIT_CODE,
+                    exit.and_then(|exit| exit.code())
+                        .map_or(String::new(), |code| code.to_string()),
+                ),
+                (ENV_RESTART_COUNT, planner.restarts.attempt().to_string()),
+            ];
+            let _ = self.hooks.run(HookPoint::OnFailure, &run_options, &env);
+        }
+        outcome
+    }
+
+    /// Sets up the watch over a child that was just launched
+    fn started<P: Supervised, E: EventSource, R: StatusReporter>(
+        &self,
+        process: P,
+        mut monitor: ThresholdMonitor,
+        planner: &mut RestartPlanner,
+        controls: &mut Controls<E>,
+        status: &mut StatusTracker<R>,
+    ) -> ChildRun<P> {
+        let pid = process.id();
+        info!("Child process started with PID: {}", pid);
+        let exit_watched = match process.on_exit(waker(&self.job.events, Event::ChildExited(pid))) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to watch the child process exit, polling it: {}", e);
+                false
+            }
+        };
+        planner.restarts.child_started();
+        monitor.reset(Instant::now());
+        // a pause requested while no child was running came too late
+        if controls.pause_requested {
+            warn!("Pause request ignored: the child process was not running");
+            controls.pause_requested = false;
+            if status.state() == Some(State::Running) {
+                status.running();
+            }
+        }
+        status.set_pausable(true);
+        if let Some(watch) = &self.watch {
+            // changes made while the child was down are already picked up
+            watch.take_changed();
+        }
+        let recycle_at = self
+            .job
+            .args
+            .restart_schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_after(&Local::now()));
+        if let Some(at) = recycle_at {
+            info!("Child process will be recycled at {}", at);
+            self.job
+                .state
+                .set("Next Recycle", at.format("%Y-%m-%d %H:%M:%S").to_string());
+        }
+
+        ChildRun {
+            process,
+            started_at: Instant::now(),
+            recycle_at,
+            exit_watched,
+            monitor,
+            liveness: None,
+            suspended: false,
+            restart_requested: false,
+            limit_hit: None,
+            sampled_at: None,
+        }
+    }
+
+    /// Watches the running child until it exits, has to be stopped,
+    /// or the service stops
+    fn watch<P: Supervised, E: EventSource, R: StatusReporter>(
+        &mut self,
+        run: &mut ChildRun<P>,
+        planner: &RestartPlanner,
+        controls: &mut Controls<E>,
+        status: &mut StatusTracker<R>,
+    ) -> WatchEnd {
+        let supervisor_args = &self.job.args;
+        let state = &self.job.state;
+        while controls.running {
+            // wait for an event, or for the next timer to fire
+            controls.wait(self.next_deadline(run, status));
+            if !controls.running {
+                break;
+            }
+
+            if let Some(violation) = check_limits(&run.process, "Child process", state) {
+                run.limit_hit = Some(violation);
+            }
+            match run.process.try_wait() {
+                Ok(Some(exit)) => {
+                    log_exit("Child process", exit, run.limit_hit);
+                    return WatchEnd::Exited(Some(exit));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    info!("Failed to check child status: {}", e);
+                    return WatchEnd::Exited(None);
+                }
+            }
+
+            self.handle_actions(run, planner, controls, status);
+            self.update_pause(run, controls, status);
+            if run.suspended {
+                continue;
+            }
+
+            if status.state() == Some(State::StartPending)
+                && (self.readiness.is_some() || self.notify.is_some())
+            {
+                let ready = match &self.readiness {
+                    Some(readiness) => readiness.is_ready(),
+                    None => self.notify.as_ref().is_some_and(|n| n.is_ready()),
+                };
+                if ready {
+                    info!("Child process is ready");
+                    status.running();
+                } else if self.started_at.elapsed() > supervisor_args.ready_timeout {
+                    error!(
+                        "Child process did not become ready within {:?}",
+                        supervisor_args.ready_timeout
+                    );
+                    return WatchEnd::Stop(Some(NOT_READY_EXIT_CODE));
+                } else {
+                    status.start_pending(START_WAIT_HINT);
+                }
+            }
+
+            // Probing starts once the child is up, so that a slow start
+            // is covered by the readiness timeout instead
+            if status.state() == Some(State::Running)
+                && run.liveness.is_none()
+                && let Some(config) = &self.liveness_config
+            {
+                run.liveness = Some(LivenessMonitor::start(
+                    config.clone(),
+                    self.job.working_dir.clone(),
+                    state.clone(),
+                    waker(&self.job.events, Event::Wake),
+                ));
+            }
+
+            let now = Instant::now();
+            let usage = if now >= self.next_sample(run, now) {
+                run.sampled_at = Some(now);
+                run.process.usage().ok()
+            } else {
+                None
+            };
+            if let Some(usage) = &usage
+                && self.publisher.is_due(now)
+            {
+                let uptime = now.duration_since(run.started_at);
+                self.publisher.publish(state, usage, Some(uptime), now);
+            }
+            if let Some(reason) = self.restart_reason(run, status, usage, now) {
+                return WatchEnd::Restart(reason);
+            }
+            if let Some(reason) = self.recycle_reason(run) {
+                return WatchEnd::Recycle(reason);
+            }
+        }
+        WatchEnd::Stop(None)
+    }
+
+    /// When the watch has to wake up on its own: to sample the usage, move
+    /// the checkpoint, poll the exit or because a timer fires
+    fn next_deadline<P, R: StatusReporter>(
+        &self,
+        run: &ChildRun<P>,
+        status: &StatusTracker<R>,
+    ) -> Option<Instant> {
+        if run.suspended {
+            return None;
+        }
+        let supervisor_args = &self.job.args;
+        let now = Instant::now();
+        let watchdog = match (&self.notify, supervisor_args.watchdog_timeout) {
+            (Some(notify), Some(timeout)) if status.state() == Some(State::Running) => {
+                notify.watchdog_deadline(timeout)
+            }
+            _ => None,
+        };
+        earliest([
+            Some(self.next_sample(run, now)),
+            (status.state() == Some(State::StartPending) || !run.exit_watched)
+                .then(|| now + CHECKPOINT_INTERVAL),
+            run.monitor.silence_deadline(),
+            watchdog,
+            supervisor_args
+                .max_runtime
+                .map(|max_runtime| run.started_at + max_runtime),
+            run.recycle_at
+                .as_ref()
+                .map(|at| now + (*at - Local::now()).to_std().unwrap_or_default()),
+        ])
+    }
+
+    fn next_sample<P>(&self, run: &ChildRun<P>, now: Instant) -> Instant {
+        if self.thresholds.needs_usage() {
+            run.sampled_at.map_or(now, |at| at + SAMPLE_INTERVAL)
+        } else {
+            self.publisher.due_at(now)
+        }
+    }
+
+    fn handle_actions<P: Supervised, E: EventSource, R: StatusReporter>(
+        &self,
+        run: &mut ChildRun<P>,
+        planner: &RestartPlanner,
+        controls: &mut Controls<E>,
+        status: &StatusTracker<R>,
+    ) {
+        while let Some(action) = controls.actions.pop_front() {
+            info!("Control action: {}", action);
+            let result = match &action {
+                ControlAction::RestartChild => {
+                    run.restart_requested = true;
+                    Ok(())
+                }
+                ControlAction::ReopenLogs => reopen_logs(),
+                ControlAction::DumpStatus => {
+                    info!("Service state: {:?}", status.state());
+                    info!(
+                        "Child process PID: {}, uptime: {:?}, paused: {}",
+                        run.process.id(),
+                        run.started_at.elapsed(),
+                        run.suspended
+                    );
+                    info!(
+                        "Consecutive restarts: {}, restarts in the last {:?}: {}",
+                        planner.restarts.attempt(),
+                        planner.window,
+                        planner.crash_loop.restarts()
+                    );
+                    for (key, value) in self.job.state.entries() {
+                        info!("{}: {}", key, value);
+                    }
+                    Ok(())
+                }
+                ControlAction::ConsoleEvent(event) => run.process.send_console_event(*event),
+                ControlAction::StdinLine(line) => run.process.write_stdin_line(line),
+            };
+            if let Err(e) = result {
+                error!("Control action {} failed: {}", action, e);
+            }
+        }
+    }
+
+    /// Suspends or resumes the child to follow the pause requests
+    fn update_pause<P: Supervised, E: EventSource, R: StatusReporter>(
+        &self,
+        run: &mut ChildRun<P>,
+        controls: &mut Controls<E>,
+        status: &mut StatusTracker<R>,
+    ) {
+        let pause = controls.pause_requested;
+        if pause == run.suspended {
+            return;
+        }
+        let result = if pause {
+            run.process.suspend()
+        } else {
+            run.process.resume()
+        };
+        match result {
+            Ok(_) if pause => {
+                info!("Child process paused");
+                // a frozen child can't answer the probes
+                run.liveness = None;
+                run.suspended = true;
+                status.paused();
+            }
+            Ok(_) => {
+                info!("Child process resumed");
+                if let Some(notify) = &self.notify {
+                    notify.reset_watchdog();
+                }
+                run.monitor.reset(Instant::now());
+                run.suspended = false;
+                status.running();
+            }
+            Err(e) => {
+                error!("Failed to pause or resume child process: {}", e);
+                controls.pause_requested = run.suspended;
+                // the SCM still waits for an answer
+                if run.suspended {
+                    status.paused();
+                } else {
+                    status.running();
+                }
+            }
+        }
+    }
+
+    /// Tells why the child is unhealthy and must be restarted, if it is
+    fn restart_reason<P, R: StatusReporter>(
+        &self,
+        run: &mut ChildRun<P>,
+        status: &StatusTracker<R>,
+        usage: Option<Usage>,
+        now: Instant,
+    ) -> Option<String> {
+        if run.liveness.as_ref().is_some_and(|l| l.is_unhealthy()) {
+            Some(String::from("liveness probe failed"))
+        } else if status.state() == Some(State::Running)
+            && let (Some(notify), Some(timeout)) = (&self.notify, self.job.args.watchdog_timeout)
+            && notify.watchdog_expired(timeout)
+        {
+            Some(String::from("watchdog timeout"))
+        } else if let Some(line) = self
+            .output_trigger
+            .as_ref()
+            .and_then(|trigger| trigger.take_match())
+        {
+            Some(format!("output matched: {}", line))
+        } else {
+            run.monitor
+                .check(usage, now)
+                .map(|trigger| trigger.to_string())
+        }
+    }
+
+    /// Tells why the child must be started afresh, if it must
+    fn recycle_reason<P>(&self, run: &ChildRun<P>) -> Option<&'static str> {
+        if run.restart_requested {
+            Some("restart requested")
+        } else if self
+            .job
+            .args
+            .max_runtime
+            .is_some_and(|max_runtime| run.started_at.elapsed() >= max_runtime)
+        {
+            Some("maximum runtime reached")
+        } else if run
+            .recycle_at
+            .as_ref()
+            .is_some_and(|at| Local::now() >= *at)
+        {
+            Some("scheduled restart")
+        } else if self
+            .watch
+            .as_ref()
+            .is_some_and(|watch| watch.take_changed())
+        {
+            Some("watched files changed")
+        } else {
+            None
+        }
+    }
+
+    /// Stops the child, unless it already exited
+    fn stop<P: Supervised, R: StatusReporter>(
+        &self,
+        run: &mut ChildRun<P>,
+        end: WatchEnd,
+        run_options: &RunOptions,
+        shutting_down: bool,
+        status: &mut StatusTracker<R>,
+    ) -> RunOutcome {
+        let supervisor_args = &self.job.args;
+        let strategy = self.stop_strategy.as_deref();
+        match end {
+            WatchEnd::Exited(exit) => RunOutcome::Exited(exit),
+            WatchEnd::Restart(reason) => {
+                error!("Restarting child process: {}", reason);
+                self.job.state.set(
+                    "Last Restart Reason",
+                    format!("{} at {}", reason, Local::now().format("%Y-%m-%d %H:%M:%S")),
+                );
+                let _ = self.hooks.run(HookPoint::PreStop, run_options, &[]);
+                RunOutcome::Restarted(run.process.stop(
+                    strategy,
+                    supervisor_args.stop_timeout,
+                    &mut || {},
+                ))
+            }
+            WatchEnd::Recycle(reason) => {
+                info!("Recycling child process: {}", reason);
+                let _ = self.hooks.run(HookPoint::PreStop, run_options, &[]);
+                RunOutcome::Recycled(run.process.stop(
+                    strategy,
+                    supervisor_args.stop_timeout,
+                    &mut || {},
+                ))
+            }
+            WatchEnd::Stop(exit_code) => {
+                let mut stop_timeout = supervisor_args.stop_timeout;
+                if shutting_down {
+                    info!("The system is shutting down");
+                    // the child must be gone before Windows stops waiting for us
+                    let budget = supervisor_args
+                        .preshutdown_timeout
+                        .saturating_sub(KILL_WAIT_HINT);
+                    if stop_timeout > budget {
+                        warn!(
+                            "Stop timeout reduced to {:?} to fit the preshutdown timeout",
+                            budget
+                        );
+                        stop_timeout = budget;
+                    }
+                }
+                info!(
+                    "Stopping child process (stop method: {})",
+                    supervisor_args.stop_method
+                );
+                let stop_wait_hint = self.stop_wait_hint;
+                status.stop_pending(stop_wait_hint);
+                // a frozen child could not react to the stop request
+                if run.suspended
+                    && let Err(e) = run.process.resume()
+                {
+                    error!("Failed to resume child process: {}", e);
+                }
+                let _ = self.hooks.run(HookPoint::PreStop, run_options, &[]);
+                status.stop_pending(stop_wait_hint);
+                let exit = run.process.stop(strategy, stop_timeout, &mut || {
+                    status.stop_pending(stop_wait_hint)
+                });
+                status.stop_pending(stop_wait_hint);
+                RunOutcome::Stopped { exit, exit_code }
+            }
+        }
+    }
+}
+
+/// Supervises the child process, or the process group, until the service is
+/// asked to stop or gives up. Returns the service exit code
+#[cfg_attr(not(windows), allow(dead_code))] // only the Windows service calls it
+pub fn supervise<E: EventSource, R: StatusReporter, L: Launcher>(
+    job: &Job,
+    launcher: &mut L,
+    controls: &mut Controls<E>,
+    status: &mut StatusTracker<R>,
+) -> Option<u32> {
+    let supervisor_args = &job.args;
+    status.start_pending(START_WAIT_HINT);
+
+    let start_delay = with_jitter(
+        supervisor_args.start_delay,
+        supervisor_args.start_jitter,
+        random_fraction(std::process::id()),
+    );
+    if !start_delay.is_zero() {
+        info!("Waiting {:?} before starting", start_delay);
+        sleep_reporting(controls, status, start_delay);
+    }
+
+    let limits = supervisor_args.resource_limits();
+    if !limits.is_empty() {
+        info!("Resource limits: {:?}", limits);
+    }
+
+    let group = supervisor_args.process_group().unwrap_or_else(|e| {
+        error!("Invalid process group: {}", e);
+        Vec::new()
+    });
+    if !group.is_empty() || job.cmd.is_empty() {
+        if group.is_empty() {
+            return Some(INVALID_GROUP_EXIT_CODE);
+        }
+        return run_group(group, job, launcher, controls, status);
+    }
+
+    let mut planner = RestartPlanner::new(supervisor_args);
+    let mut child = match ChildSupervisor::new(job) {
+        Ok(child) => child,
+        Err(exit_code) => return Some(exit_code),
+    };
+    while controls.running {
+        let outcome = child.run(&mut planner, launcher, controls, status);
+        match outcome {
+            RunOutcome::Stopped { exit_code, .. } => return exit_code,
+            // a recycle is not a failure: restart right away, bypassing
+            // the restart policy and the crash loop detection
+            RunOutcome::Recycled(_) => continue,
+            _ => {}
+        }
+
+        if status.state() == Some(State::StartPending)
+            && child.started_at.elapsed() > supervisor_args.ready_timeout
+        {
+            error!(
+                "Child process did not start within {:?}",
+                supervisor_args.ready_timeout
+            );
+            return Some(NOT_READY_EXIT_CODE);
+        }
+
+        match planner.decide(outcome) {
+            AfterExit::Restart(delay) => {
+                info!(
+                    "Restarting child process in {:?} (attempt {})",
+                    delay,
+                    planner.restarts.attempt()
+                );
+                sleep_reporting(controls, status, delay);
+            }
+            AfterExit::WaitForRestart => {
+                if status.state() == Some(State::StartPending) {
+                    status.running();
+                }
+                info!("Child process stopped, waiting for a restart-child control code");
+                controls.wait_for_restart(|action| {
+                    info!("Control action: {}", action);
+                    match &action {
+                        ControlAction::ReopenLogs => {
+                            if let Err(e) = reopen_logs() {
+                                error!("Control action {} failed: {}", action, e);
+                            }
+                        }
+                        _ => warn!(
+                            "Control action {} ignored: the child process is not running",
+                            action
+                        ),
+                    }
+                });
+            }
+            AfterExit::Stop(exit_code) => return exit_code,
+        }
+    }
+    None
+}
+
+/// Logs the limits the process tree ran into. Returns the last one
+fn check_limits(
+    process: &impl Supervised,
+    label: &str,
+    state: &StateFile,
+) -> Option<LimitViolation> {
+    let mut last = None;
+    for violation in process.limit_violations() {
+        warn!("{} hit the {}", label, violation);
+        state.set(
+            "Last Limit Hit",
+            format!(
+                "{} ({}) at {}",
+                violation,
+                label,
+                Local::now().format("%Y-%m-%d %H:%M:%S")
+            ),
+        );
+        last = Some(violation);
+    }
+    last
+}
+
+/// A process capped by a memory limit usually dies of a failed allocation,
+/// which must not be mistaken for a crash
+fn log_exit(label: &str, exit: ExitStatus, limit_hit: Option<LimitViolation>) {
+    match limit_hit.filter(|violation| violation.is_memory()) {
+        Some(violation) => error!(
+            "{} exited with status: {} after hitting the {} (resource capped)",
+            label, exit, violation
+        ),
+        None => error!("{} exited with status: {}", label, exit),
+    }
+}
+
+/// Sleeps like `Controls::sleep`. While the service is starting, the
+/// checkpoint keeps moving so that the SCM keeps waiting
+fn sleep_reporting<E: EventSource, R: StatusReporter>(
+    controls: &mut Controls<E>,
+    status: &mut StatusTracker<R>,
+    duration: Duration,
+) {
+    if status.state() != Some(State::StartPending) {
+        controls.sleep(duration);
+        return;
+    }
+    let deadline = Instant::now() + duration;
+    while controls.running && Instant::now() < deadline {
+        status.start_pending(START_WAIT_HINT);
+        controls.wait(Some(deadline.min(Instant::now() + CHECKPOINT_INTERVAL)));
+    }
+}
+
+/// A process of the group, restarted according to its own policy
+struct Member<P> {
+    spec: ProcessSpec,
+    process: Option<P>,
+    restarts: RestartTracker,
+    /// When the process is due to be (re)started
+    start_at: Option<Instant>,
+    /// Last limit the running process ran into
+    limit_hit: Option<LimitViolation>,
+    /// False when the exit of the running process must be polled
+    exit_watched: bool,
+}
+
+impl<P: Supervised> Member<P> {
+    fn is_alive(&self) -> bool {
+        self.process.is_some() || self.start_at.is_some()
+    }
+
+    fn start(&mut self, job: &Job, launcher: &mut impl Launcher<Process = P>) -> io::Result<u32> {
+        let working_dir = self.spec.working_dir.clone().or(job.working_dir.clone());
+        let mut run_options = job.args.run_options(working_dir, job.no_logs);
+        run_options.log_tag = Some(self.spec.name.clone());
+        let process = launcher.launch(&self.spec.cmd, run_options)?;
+        let pid = process.id();
+        info!("Process {} started with PID: {}", self.spec.name, pid);
+        self.exit_watched = match process.on_exit(waker(&job.events, Event::ChildExited(pid))) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!(
+                    "Failed to watch the process {} exit, polling it: {}",
+                    self.spec.name, e
+                );
+                false
+            }
+        };
+        self.restarts.child_started();
+        self.process = Some(process);
+        self.limit_hit = None;
+        Ok(pid)
+    }
+}
+
+/// Stops the running processes of the group, in the reverse start order
+fn stop_group<P: Supervised>(
+    members: &mut [Member<P>],
+    strategy: Option<&dyn StopStrategy>,
+    timeout: Duration,
+    progress: &mut dyn FnMut(),
+) {
+    for member in members.iter_mut().rev() {
+        if let Some(mut process) = member.process.take() {
+            info!("Stopping process {}", member.spec.name);
+            process.stop(strategy, timeout, progress);
+        }
+    }
+}
+
+/// Supervises the processes of a group until the service is asked to stop
+/// or the group gives up. Returns the service exit code
+fn run_group<E: EventSource, R: StatusReporter, L: Launcher>(
+    specs: Vec<ProcessSpec>,
+    job: &Job,
+    launcher: &mut L,
+    controls: &mut Controls<E>,
+    status: &mut StatusTracker<R>,
+) -> Option<u32> {
+    let supervisor_args = &job.args;
+    let state = &job.state;
+    let stop_strategy = build_strategy(
+        supervisor_args.stop_method,
+        supervisor_args.stop_line.as_deref(),
+    );
+    let stop_wait_hint = supervisor_args.stop_timeout + KILL_WAIT_HINT;
+    let mut crash_loop =
+        CrashLoopDetector::new(supervisor_args.max_restarts, supervisor_args.restart_window);
+    let now = Instant::now();
+    let mut members: Vec<Member<L::Process>> = specs
+        .into_iter()
+        .map(|spec| Member {
+            restarts: RestartTracker::new(
+                spec.restart.unwrap_or(supervisor_args.restart),
+                supervisor_args.backoff(),
+                supervisor_args.restart_reset_after,
+            ),
+            spec,
+            process: None,
+            start_at: Some(now),
+            limit_hit: None,
+            exit_watched: true,
+        })
+        .collect();
+    info!(
+        "Supervising {} processes (group mode: {})",
+        members.len(),
+        supervisor_args.group_mode
+    );
+
+    let mut exit_code = None;
+    let mut suspended = false;
+    let mut restart_requested = false;
+    let mut give_up = false;
+    let mut publisher = UsagePublisher::default();
+    while controls.running && !give_up {
+        // processes that exited, or that could not be started
+        let mut exits = Vec::new();
+        if !suspended {
+            // due processes start in the declaration order
+            let now = Instant::now();
+            for (index, member) in members.iter_mut().enumerate() {
+                if member.start_at.is_none_or(|at| at > now) {
+                    continue;
+                }
+                member.start_at = None;
+                let key = format!("Process {}", member.spec.name);
+                match member.start(job, launcher) {
+                    Ok(pid) => state.set(&key, format!("running with PID {}", pid)),
+                    Err(e) => {
+                        error!("Failed to run process {}: {:?}", member.spec.name, e);
+                        state.set(&key, format!("failed to start: {}", e));
+                        exits.push((index, None));
+                    }
+                }
+            }
+            if status.state() == Some(State::StartPending) {
+                // pausing the group also holds back the members due to start
+                status.set_pausable(true);
+                status.running();
+            }
+        }
+
+        // wait for an event, or for the next timer to fire. The processes
+        // that failed to start are handled right away
+        let now = Instant::now();
+        let deadline = if !exits.is_empty() {
+            Some(now)
+        } else if suspended {
+            None
+        } else {
+            let alive = members.iter().any(|m| m.process.is_some());
+            let polled = members
+                .iter()
+                .any(|m| m.process.is_some() && !m.exit_watched);
+            earliest(members.iter().map(|m| m.start_at).chain([
+                alive.then(|| publisher.due_at(now)),
+                polled.then(|| now + CHECKPOINT_INTERVAL),
+            ]))
+        };
+        controls.wait(deadline);
+        if !controls.running {
+            break;
+        }
+
+        while let Some(action) = controls.actions.pop_front() {
+            info!("Control action: {}", action);
+            let mut processes = members.iter_mut().filter_map(|m| m.process.as_mut());
+            let result = match &action {
+                ControlAction::RestartChild => {
+                    restart_requested = true;
+                    Ok(())
+                }
+                ControlAction::ReopenLogs => reopen_logs(),
+                ControlAction::DumpStatus => {
+                    info!("Service state: {:?}, paused: {}", status.state(), suspended);
+                    info!(
+                        "Restarts in the last {:?}: {}",
+                        supervisor_args.restart_window,
+                        crash_loop.restarts()
+                    );
+                    for (key, value) in state.entries() {
+                        info!("{}: {}", key, value);
+                    }
+                    Ok(())
+                }
+                ControlAction::ConsoleEvent(event) => {
+                    processes.try_for_each(|process| process.send_console_event(*event))
+                }
+                ControlAction::StdinLine(line) => {
+                    processes.try_for_each(|process| process.write_stdin_line(line))
+                }
+            };
+            if let Err(e) = result {
+                error!("Control action {} failed: {}", action, e);
+            }
+        }
+
+        let pause = controls.pause_requested;
+        if pause != suspended {
+            let result = members
+                .iter()
+                .filter_map(|m| m.process.as_ref())
+                .try_for_each(|process| {
+                    if pause {
+                        process.suspend()
+                    } else {
+                        process.resume()
+                    }
+                });
+            match result {
+                Ok(_) if pause => {
+                    info!("Process group paused");
+                    suspended = true;
+                    status.paused();
+                }
+                Ok(_) => {
+                    info!("Process group resumed");
+                    suspended = false;
+                    status.running();
+                }
+                Err(e) => {
+                    error!("Failed to pause or resume the process group: {}", e);
+                    controls.pause_requested = suspended;
+                    // the SCM still waits for an answer
+                    if suspended {
+                        status.paused();
+                    } else {
+                        status.running();
+                    }
+                }
+            }
+        }
+        if suspended {
+            continue;
+        }
+
+        if restart_requested {
+            // like a recycle, this bypasses the restart policies
+            restart_requested = false;
+            info!("Restarting the process group");
+            stop_group(
+                &mut members,
+                stop_strategy.as_deref(),
+                supervisor_args.stop_timeout,
+                &mut || {},
+            );
+            let now = Instant::now();
+            for member in &mut members {
+                member.start_at = Some(now);
+            }
+            continue;
+        }
+
+        for (index, member) in members.iter_mut().enumerate() {
+            let Some(process) = &mut member.process else {
+                continue;
+            };
+            let label = format!("Process {}", member.spec.name);
+            if let Some(violation) = check_limits(process, &label, state) {
+                member.limit_hit = Some(violation);
+            }
+            match process.try_wait() {
+                Ok(Some(exit)) => {
+                    log_exit(&label, exit, member.limit_hit);
+                    member.process = None;
+                    exits.push((index, Some(exit)));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to check process {} status: {}", member.spec.name, e);
+                    member.process = None;
+                    exits.push((index, None));
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if publisher.is_due(now) {
+            // the members start at different times, so there is no overall uptime
+            let mut usage = Usage::default();
+            for process in members.iter().filter_map(|m| m.process.as_ref()) {
+                if let Ok(member_usage) = process.usage() {
+                    usage += member_usage;
+                }
+            }
+            publisher.publish(state, &usage, None, now);
+        }
+
+        for (index, exit) in exits {
+            let name = members[index].spec.name.clone();
+            if let Some(exit) = exit {
+                state.set(
+                    &format!("Process {}", name),
+                    format!(
+                        "exited with {} at {}",
+                        exit,
+                        Local::now().format("%Y-%m-%d %H:%M:%S")
+                    ),
+                );
+            }
+            let exit_code_of = || service_exit_code(exit.and_then(|exit| exit.code()));
+            let success = exit.is_some_and(|exit| exit.success());
+            let others_alive = members
+                .iter()
+                .enumerate()
+                .any(|(other, member)| other != index && member.is_alive());
+            let action = match members[index].restarts.child_exited(success) {
+                RestartDecision::Restart(_) if !crash_loop.record_restart() => {
+                    error!(
+                        "Process {} is crash looping: giving up after {} restarts in {:?}",
+                        name,
+                        crash_loop.restarts(),
+                        supervisor_args.restart_window
+                    );
+                    exit_code = Some(CRASH_LOOP_EXIT_CODE);
+                    GroupAction::StopAll
+                }
+                decision => on_process_exit(supervisor_args.group_mode, decision, others_alive),
+            };
+            match action {
+                GroupAction::Restart(delay) => {
+                    // a process that could not be started must not be retried in a tight loop
+                    let delay = if exit.is_none() {
+                        delay.max(START_RETRY_DELAY)
+                    } else {
+                        delay
+                    };
+                    info!(
+                        "Restarting process {} in {:?} (attempt {})",
+                        name,
+                        delay,
+                        members[index].restarts.attempt()
+                    );
+                    members[index].start_at = Some(Instant::now() + delay);
+                }
+                GroupAction::LeaveStopped => {
+                    info!("Process {} will not be restarted", name);
+                }
+                GroupAction::RestartAll(delay) => {
+                    info!("Restarting the process group in {:?}", delay);
+                    stop_group(
+                        &mut members,
+                        stop_strategy.as_deref(),
+                        supervisor_args.stop_timeout,
+                        &mut || {},
+                    );
+                    let start_at = Instant::now() + delay;
+                    for member in &mut members {
+                        member.start_at = Some(start_at);
+                    }
+                    // the other exits are part of the same restart
+                    break;
+                }
+                GroupAction::StopAll => {
+                    info!(
+                        "Process {} will not be restarted: stopping the process group",
+                        name
+                    );
+                    if exit_code.is_none() {
+                        exit_code = exit_code_of();
+                    }
+                    give_up = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    let running = members.iter().filter(|m| m.process.is_some()).count() as u32;
+    let mut stop_timeout = supervisor_args.stop_timeout;
+    if controls.shutting_down && running > 0 {
+        info!("The system is shutting down");
+        // every process must be gone before Windows stops waiting for us
+        let budget = supervisor_args
+            .preshutdown_timeout
+            .saturating_sub(KILL_WAIT_HINT * running)
+            / running;
+        if stop_timeout > budget {
+            warn!(
+                "Stop timeout reduced to {:?} to fit the preshutdown timeout",
+                budget
+            );
+            stop_timeout = budget;
+        }
+    }
+    status.stop_pending(stop_wait_hint);
+    // frozen processes could not react to the stop request
+    if suspended {
+        for process in members.iter().filter_map(|m| m.process.as_ref()) {
+            if let Err(e) = process.resume() {
+                error!("Failed to resume process: {}", e);
+            }
+        }
+    }
+    stop_group(
+        &mut members,
+        stop_strategy.as_deref(),
+        stop_timeout,
+        &mut || status.stop_pending(stop_wait_hint),
+    );
+    publisher.clear(state);
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Commands};
+    use crate::pkg::status::Status;
+    use clap::Parser;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    /// Replays scripted events, None being a deadline reached without
+    /// sleeping. Asks to stop once the script is over
+    #[derive(Clone)]
+    struct FakeEvents {
+        script: Rc<RefCell<VecDeque<Option<Event>>>>,
+        /// How far the deadline of every wait was
+        waits: Rc<RefCell<Vec<Option<Duration>>>>,
+    }
+
+    impl FakeEvents {
+        fn new(script: impl IntoIterator<Item = Option<Event>>) -> Self {
+            FakeEvents {
+                script: Rc::new(RefCell::new(script.into_iter().collect())),
+                waits: Rc::default(),
+            }
+        }
+    }
+
+    impl EventSource for FakeEvents {
+        fn wait(&mut self, deadline: Option<Instant>) -> Option<Event> {
+            self.waits
+                .borrow_mut()
+                .push(deadline.map(|at| at.saturating_duration_since(Instant::now())));
+            self.script
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Some(Event::Stop { shutdown: false }))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingReporter {
+        reports: Rc<RefCell<Vec<Status>>>,
+    }
+
+    impl StatusReporter for RecordingReporter {
+        fn report(&mut self, status: &Status) {
+            self.reports.borrow_mut().push(status.clone());
+        }
+    }
+
+    impl RecordingReporter {
+        fn states(&self) -> Vec<State> {
+            self.reports.borrow().iter().map(|s| s.state).collect()
+        }
+    }
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+
+    /// Exits with its code when first checked, or runs until stopped without one
+    struct FakeProcess {
+        pid: u32,
+        exit_code: Option<i32>,
+        memory: u64,
+        stopped: Rc<RefCell<Vec<u32>>>,
+        stop_timeouts: Rc<RefCell<Vec<Duration>>>,
+        /// true for a suspend, false for a resume
+        paused: Rc<RefCell<Vec<bool>>>,
+    }
+
+    impl UsageSource for FakeProcess {
+        fn usage(&self) -> io::Result<Usage> {
+            Ok(Usage {
+                memory: self.memory,
+                ..Default::default()
+            })
+        }
+    }
+
+    impl Supervised for FakeProcess {
+        fn id(&self) -> u32 {
+            self.pid
+        }
+
+        fn on_exit(&self, _wake: Waker) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+            Ok(self.exit_code.map(exit_status))
+        }
+
+        fn suspend(&self) -> io::Result<()> {
+            self.paused.borrow_mut().push(true);
+            Ok(())
+        }
+
+        fn resume(&self) -> io::Result<()> {
+            self.paused.borrow_mut().push(false);
+            Ok(())
+        }
+
+        fn limit_violations(&self) -> Vec<LimitViolation> {
+            Vec::new()
+        }
+
+        fn send_console_event(&self, _event: ConsoleEvent) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_stdin_line(&mut self, _line: &str) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn stop(
+            &mut self,
+            _strategy: Option<&dyn StopStrategy>,
+            timeout: Duration,
+            progress: &mut dyn FnMut(),
+        ) -> Option<ExitStatus> {
+            progress();
+            self.stopped.borrow_mut().push(self.pid);
+            self.stop_timeouts.borrow_mut().push(timeout);
+            Some(exit_status(0))
+        }
+    }
+
+    /// Launches processes exiting with the scripted codes, then processes
+    /// running until stopped
+    #[derive(Default)]
+    struct FakeLauncher {
+        exit_codes: VecDeque<i32>,
+        /// Memory used by every process
+        memory: u64,
+        launched: u32,
+        stopped: Rc<RefCell<Vec<u32>>>,
+        stop_timeouts: Rc<RefCell<Vec<Duration>>>,
+        paused: Rc<RefCell<Vec<bool>>>,
+    }
+
+    impl FakeLauncher {
+        fn new(exit_codes: impl IntoIterator<Item = i32>) -> Self {
+            FakeLauncher {
+                exit_codes: exit_codes.into_iter().collect(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Launcher for FakeLauncher {
+        type Process = FakeProcess;
+
+        fn launch(&mut self, cmdline: &str, _options: RunOptions) -> io::Result<FakeProcess> {
+            assert_eq!(cmdline, "app");
+            self.launched += 1;
+            Ok(FakeProcess {
+                pid: self.launched,
+                exit_code: self.exit_codes.pop_front(),
+                memory: self.memory,
+                stopped: self.stopped.clone(),
+                stop_timeouts: self.stop_timeouts.clone(),
+                paused: self.paused.clone(),
+            })
+        }
+    }
+
+    fn state_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "wsw-supervisor-{}-{}.state",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn supervisor_args(args: &[&str]) -> SupervisorArgs {
+        // a process group replaces the command
+        let cmd: &[&str] = if args.contains(&"--process") {
+            &[]
+        } else {
+            &["--cmd", "app"]
+        };
+        let cli = Cli::try_parse_from(["wsw", "run"].iter().chain(cmd).chain(args)).unwrap();
+        let Some(Commands::Run { supervisor, .. }) = cli.command else {
+            panic!("not a run command");
+        };
+        supervisor
+    }
+
+    fn job(name: &str, args: &[&str]) -> Job {
+        let args = supervisor_args(args);
+        Job {
+            cmd: if args.processes.is_empty() {
+                String::from("app")
+            } else {
+                String::new()
+            },
+            working_dir: None,
+            no_logs: true,
+            args,
+            state: StateFile::at(state_path(name)),
+            events: std::sync::mpsc::channel().0,
+        }
+    }
+
+    fn run(
+        job: &Job,
+        launcher: &mut FakeLauncher,
+        events: &FakeEvents,
+    ) -> (Option<u32>, RecordingReporter) {
+        let mut controls = Controls::new(events.clone());
+        let reporter = RecordingReporter::default();
+        let mut status = StatusTracker::new(reporter.clone());
+        let exit_code = supervise(job, launcher, &mut controls, &mut status);
+        (exit_code, reporter)
+    }
+
+    #[test]
+    fn test_restart_after_backoff() {
+        let job = job(
+            "restart",
+            &["--restart-delay", "2s", "--restart-jitter", "0"],
+        );
+        let mut launcher = FakeLauncher::new([1]);
+        let events = FakeEvents::new([
+            Some(Event::ChildExited(1)),
+            // the backoff delay elapses
+            None,
+            Some(Event::Stop { shutdown: false }),
+        ]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("restart"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 2);
+        assert_eq!(*launcher.stopped.borrow(), [2]);
+        let backoff = events.waits.borrow()[1].unwrap();
+        assert!(backoff > Duration::from_secs(1) && backoff <= Duration::from_secs(2));
+        let running: Vec<bool> = reporter
+            .reports
+            .borrow()
+            .iter()
+            .filter(|s| s.state == State::Running)
+            .map(|s| s.pausable)
+            .collect();
+        // not pausable while waiting to restart
+        assert_eq!(running, [true, false, true]);
+        assert_eq!(reporter.states().last(), Some(&State::StopPending));
+    }
+
+    #[test]
+    fn test_stop_during_backoff() {
+        // the child never becomes ready, so the service is still starting
+        let job = job(
+            "backoff",
+            &[
+                "--ready",
+                "output:ready",
+                "--restart-delay",
+                "1h",
+                "--restart-jitter",
+                "0",
+            ],
+        );
+        let mut launcher = FakeLauncher::new([1]);
+        let events = FakeEvents::new([
+            Some(Event::ChildExited(1)),
+            Some(Event::Wake),
+            None,
+            Some(Event::Stop { shutdown: false }),
+            Some(Event::Wake),
+        ]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("backoff"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 1);
+        assert!(launcher.stopped.borrow().is_empty());
+        // the stop request ends the backoff right away
+        assert_eq!(*events.script.borrow(), [Some(Event::Wake)]);
+        // the checkpoint keeps moving while waiting to restart
+        let reports = reporter.reports.borrow();
+        assert!(reports.iter().all(|s| s.state == State::StartPending));
+        let checkpoints: Vec<u32> = reports.iter().rev().take(3).map(|s| s.checkpoint).collect();
+        assert!(checkpoints.windows(2).all(|w| w[0] == w[1] + 1));
+        assert!(
+            events.waits.borrow()[1..]
+                .iter()
+                .all(|wait| wait.is_some_and(|wait| wait <= CHECKPOINT_INTERVAL))
+        );
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let job = job(
+            "crash-loop",
+            &[
+                "--max-restarts",
+                "2",
+                "--restart-delay",
+                "10ms",
+                "--restart-jitter",
+                "0",
+            ],
+        );
+        let mut launcher = FakeLauncher::new([1, 1, 1, 1]);
+        let events = FakeEvents::new([
+            Some(Event::ChildExited(1)),
+            None,
+            Some(Event::ChildExited(2)),
+            None,
+            Some(Event::ChildExited(3)),
+        ]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("crash-loop"));
+
+        assert_eq!(exit_code, Some(CRASH_LOOP_EXIT_CODE));
+        // the first run and two restarts
+        assert_eq!(launcher.launched, 3);
+        assert!(launcher.stopped.borrow().is_empty());
+        assert_eq!(reporter.states().last(), Some(&State::Running));
+    }
+
+    #[test]
+    fn test_stop_while_running() {
+        let job = job(
+            "stop",
+            &["--stop-timeout", "5m", "--preshutdown-timeout", "1m"],
+        );
+        let mut launcher = FakeLauncher::new([]);
+        let events = FakeEvents::new([Some(Event::Wake), Some(Event::Stop { shutdown: true })]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("stop"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 1);
+        assert_eq!(*launcher.stopped.borrow(), [1]);
+        // the stop fits in the preshutdown timeout
+        assert_eq!(
+            *launcher.stop_timeouts.borrow(),
+            [Duration::from_secs(60) - KILL_WAIT_HINT]
+        );
+        // the checkpoint moves while the child is stopped
+        let reports = reporter.reports.borrow();
+        let stopping: Vec<u32> = reports
+            .iter()
+            .filter(|s| s.state == State::StopPending)
+            .map(|s| s.checkpoint)
+            .collect();
+        assert!(stopping.len() > 1);
+        assert!(stopping.windows(2).all(|w| w[1] == w[0] + 1));
+        assert!(!reports.last().unwrap().pausable);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let job = job("pause", &[]);
+        let mut launcher = FakeLauncher::new([]);
+        let events = FakeEvents::new([
+            Some(Event::Pause),
+            Some(Event::Resume),
+            Some(Event::Pause),
+            Some(Event::Stop { shutdown: false }),
+        ]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("pause"));
+
+        assert_eq!(exit_code, None);
+        // a paused child is resumed so that it can react to the stop
+        assert_eq!(*launcher.paused.borrow(), [true, false, true, false]);
+        assert_eq!(*launcher.stopped.borrow(), [1]);
+        let states: Vec<State> = reporter
+            .states()
+            .into_iter()
+            .filter(|state| matches!(state, State::Running | State::Paused))
+            .collect();
+        assert_eq!(
+            states,
+            [State::Running, State::Paused, State::Running, State::Paused]
+        );
+        // no deadline while the child is frozen
+        assert_eq!(events.waits.borrow()[1], None);
+    }
+
+    #[test]
+    fn test_readiness_timeout() {
+        let job = job(
+            "not-ready",
+            &["--ready", "output:ready", "--ready-timeout", "0s"],
+        );
+        let mut launcher = FakeLauncher::new([]);
+        let events = FakeEvents::new([None]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("not-ready"));
+
+        assert_eq!(exit_code, Some(NOT_READY_EXIT_CODE));
+        assert_eq!(launcher.launched, 1);
+        assert_eq!(*launcher.stopped.borrow(), [1]);
+        assert!(!reporter.states().contains(&State::Running));
+        assert_eq!(reporter.states().last(), Some(&State::StopPending));
+    }
+
+    #[test]
+    fn test_recycle_on_restart_request() {
+        let job = job("recycle", &["--restart-delay", "1h"]);
+        let mut launcher = FakeLauncher::new([]);
+        let events = FakeEvents::new([
+            Some(Event::Control(ControlAction::RestartChild)),
+            Some(Event::Stop { shutdown: false }),
+        ]);
+        let (exit_code, _) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("recycle"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 2);
+        assert_eq!(*launcher.stopped.borrow(), [1, 2]);
+        // started again right away, without a backoff delay
+        assert_eq!(events.waits.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_recycle_after_max_runtime() {
+        let job = job("max-runtime", &["--max-runtime", "0s"]);
+        let mut launcher = FakeLauncher::new([]);
+        let events = FakeEvents::new([None, Some(Event::Stop { shutdown: false })]);
+        let (exit_code, _) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("max-runtime"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 2);
+        assert_eq!(*launcher.stopped.borrow(), [1, 2]);
+        // the first wait ends when the maximum runtime is reached
+        assert_eq!(events.waits.borrow()[0], Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_restart_on_memory_threshold() {
+        let job = job(
+            "threshold",
+            &[
+                "--memory-threshold",
+                "1M",
+                "--memory-threshold-duration",
+                "0s",
+                "--restart-delay",
+                "30s",
+                "--restart-jitter",
+                "0",
+            ],
+        );
+        let mut launcher = FakeLauncher {
+            memory: 2 << 20,
+            ..Default::default()
+        };
+        let events = FakeEvents::new([None, Some(Event::Stop { shutdown: false })]);
+        let (exit_code, _) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("threshold"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 1);
+        assert_eq!(*launcher.stopped.borrow(), [1]);
+        // unlike a recycle, the restart goes through the backoff
+        let backoff = events.waits.borrow()[1].unwrap();
+        assert!(backoff > Duration::from_secs(29));
+    }
+
+    #[test]
+    fn test_exit_action_stop_service() {
+        let job = job("stop-service", &["--on-exit", "3=stop-service"]);
+        let mut launcher = FakeLauncher::new([3]);
+        let events = FakeEvents::new([Some(Event::ChildExited(1))]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("stop-service"));
+
+        assert_eq!(exit_code, Some(3));
+        assert_eq!(launcher.launched, 1);
+        assert!(launcher.stopped.borrow().is_empty());
+        assert_eq!(reporter.states().last(), Some(&State::Running));
+    }
+
+    #[test]
+    fn test_exit_action_ignore() {
+        let job = job("ignore", &["--on-exit", "3=ignore"]);
+        let mut launcher = FakeLauncher::new([3]);
+        let events = FakeEvents::new([
+            Some(Event::ChildExited(1)),
+            Some(Event::Control(ControlAction::DumpStatus)),
+            Some(Event::Control(ControlAction::RestartChild)),
+            Some(Event::Stop { shutdown: false }),
+        ]);
+        let (exit_code, _) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("ignore"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 2);
+        assert_eq!(*launcher.stopped.borrow(), [2]);
+        // the child stays down until the restart request
+        assert_eq!(events.waits.borrow()[1..3], [None, None]);
+    }
+
+    #[test]
+    fn test_group_restarts_a_process() {
+        let job = job(
+            "group",
+            &[
+                "--process",
+                "web=app",
+                "--process",
+                "worker=app",
+                "--restart-delay",
+                "30s",
+                "--restart-jitter",
+                "0",
+            ],
+        );
+        let mut launcher = FakeLauncher::new([1]);
+        let events = FakeEvents::new([Some(Event::ChildExited(1))]);
+        let (exit_code, reporter) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("group"));
+
+        assert_eq!(exit_code, None);
+        // web waits for its restart, worker keeps running
+        assert_eq!(launcher.launched, 2);
+        assert_eq!(*launcher.stopped.borrow(), [2]);
+        assert_eq!(reporter.states().last(), Some(&State::StopPending));
+    }
+
+    #[test]
+    fn test_group_restart_all() {
+        let job = job(
+            "group-all",
+            &[
+                "--process",
+                "web=app",
+                "--process",
+                "worker=app",
+                "--group-mode",
+                "restart-all",
+                "--restart-delay",
+                "0s",
+            ],
+        );
+        let mut launcher = FakeLauncher::new([1]);
+        let events = FakeEvents::new([Some(Event::ChildExited(1)), Some(Event::Wake)]);
+        let (exit_code, _) = run(&job, &mut launcher, &events);
+        let _ = std::fs::remove_file(state_path("group-all"));
+
+        assert_eq!(exit_code, None);
+        assert_eq!(launcher.launched, 4);
+        // the whole group is restarted, then stopped in the reverse order
+        assert_eq!(*launcher.stopped.borrow(), [2, 4, 3]);
+    }
+
+    fn exited(code: i32) -> RunOutcome {
+        RunOutcome::Exited(Some(exit_status(code)))
+    }
+
+    #[test]
+    fn test_decide_exit_actions() {
+        let mut planner = RestartPlanner::new(&supervisor_args(&[
+            "--restart",
+            "on-failure",
+            "--restart-delay",
+            "1s",
+            "--restart-jitter",
+            "0",
+            "--on-exit",
+            "3=stop-service",
+            "--on-exit",
+            "4=ignore",
+            "--on-exit",
+            "5=restart-after:30s",
+        ]));
+        assert_eq!(planner.decide(exited(3)), AfterExit::Stop(Some(3)));
+        assert_eq!(planner.decide(exited(4)), AfterExit::WaitForRestart);
+        assert_eq!(
+            planner.decide(exited(5)),
+            AfterExit::Restart(Duration::from_secs(30))
+        );
+        // the codes without a mapping follow the restart policy
+        assert_eq!(planner.decide(exited(0)), AfterExit::Stop(None));
+        assert_eq!(
+            planner.decide(exited(6)),
+            AfterExit::Restart(Duration::from_secs(1))
+        );
+        // the mapping only applies when the child exits on its own
+        assert_eq!(
+            planner.decide(RunOutcome::Restarted(Some(exit_status(3)))),
+            AfterExit::Restart(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn test_decide_restart_policy() {
+        let backoff = ["--restart-delay", "1s", "--restart-jitter", "0"];
+        let mut always = RestartPlanner::new(&supervisor_args(&backoff));
+        assert_eq!(
+            always.decide(exited(0)),
+            AfterExit::Restart(Duration::from_secs(1))
+        );
+        assert_eq!(
+            always.decide(exited(1)),
+            AfterExit::Restart(Duration::from_secs(2))
+        );
+
+        let mut never = RestartPlanner::new(&supervisor_args(&["--restart", "never"]));
+        assert_eq!(never.decide(exited(1)), AfterExit::Stop(Some(1)));
+        assert_eq!(
+            never.decide(RunOutcome::Exited(None)),
+            AfterExit::Stop(None)
+        );
+    }
+
+    #[test]
+    fn test_decide_crash_loop() {
+        let mut planner = RestartPlanner::new(&supervisor_args(&[
+            "--max-restarts",
+            "1",
+            "--on-exit",
+            "3=restart-after:1s",
+        ]));
+        assert!(matches!(planner.decide(exited(1)), AfterExit::Restart(_)));
+        // the exit actions count as restarts too
+        assert_eq!(
+            planner.decide(exited(3)),
+            AfterExit::Stop(Some(CRASH_LOOP_EXIT_CODE))
+        );
+    }
+
+    #[test]
+    fn test_decide_start_failed() {
+        let mut planner = RestartPlanner::new(&supervisor_args(&["--restart-delay", "0s"]));
+        assert_eq!(
+            planner.decide(RunOutcome::StartFailed),
+            AfterExit::Restart(START_RETRY_DELAY)
+        );
+        assert_eq!(
+            planner.decide(exited(1)),
+            AfterExit::Restart(Duration::ZERO)
+        );
+    }
+}
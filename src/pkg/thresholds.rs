@@ -0,0 +1,270 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::pkg::duration::format_duration;
+use crate::pkg::log_writer::LineObserver;
+use crate::pkg::metrics::Usage;
+
+/// Restart triggers based on the resource usage and the output of the child
+#[derive(Debug, Clone, Default)]
+pub struct Thresholds {
+    /// Memory in bytes, and how long the usage must stay above it
+    pub memory: Option<(u64, Duration)>,
+    /// CPU usage in percent of one core, and how long the usage must stay above it
+    pub cpu: Option<(u32, Duration)>,
+    /// How long the child can go without printing anything
+    pub silence: Option<Duration>,
+}
+
+impl Thresholds {
+    /// Tells if the resource usage of the child must be sampled
+    pub fn needs_usage(&self) -> bool {
+        self.memory.is_some() || self.cpu.is_some()
+    }
+}
+
+/// Threshold that fired
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    Memory {
+        usage: u64,
+        limit: u64,
+        duration: Duration,
+    },
+    Cpu {
+        percent: f64,
+        limit: u32,
+        duration: Duration,
+    },
+    Silence(Duration),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Memory {
+                usage,
+                limit,
+                duration,
+            } => write!(
+                f,
+                "memory threshold exceeded: {} MiB used, above {} MiB for {}",
+                usage >> 20,
+                limit >> 20,
+                format_duration(*duration)
+            ),
+            Trigger::Cpu {
+                percent,
+                limit,
+                duration,
+            } => write!(
+                f,
+                "CPU threshold exceeded: {:.0}% used, above {}% for {}",
+                percent,
+                limit,
+                format_duration(*duration)
+            ),
+            Trigger::Silence(timeout) => {
+                write!(f, "no output for {}", format_duration(*timeout))
+            }
+        }
+    }
+}
+
+/// Checks the thresholds of the current child against the usage samples
+pub struct ThresholdMonitor {
+    thresholds: Thresholds,
+    memory_above_since: Option<Instant>,
+    cpu_above_since: Option<Instant>,
+    /// Time and CPU time of the previous sample
+    last_sample: Option<(Instant, Duration)>,
+    last_output: Arc<Mutex<Instant>>,
+}
+
+impl ThresholdMonitor {
+    pub fn new(thresholds: Thresholds, now: Instant) -> Self {
+        ThresholdMonitor {
+            thresholds,
+            memory_above_since: None,
+            cpu_above_since: None,
+            last_sample: None,
+            last_output: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Returns the observer to attach to the child output, if the silence is watched
+    pub fn output_observer(&self) -> Option<LineObserver> {
+        self.thresholds.silence?;
+        let last_output = self.last_output.clone();
+        Some(Arc::new(move |_: &str| {
+            *last_output.lock().unwrap() = Instant::now();
+        }))
+    }
+
+    /// Starts over, e.g. when the child is started or resumed
+    pub fn reset(&mut self, now: Instant) {
+        self.memory_above_since = None;
+        self.cpu_above_since = None;
+        self.last_sample = None;
+        *self.last_output.lock().unwrap() = now;
+    }
+
+    /// When the silence timeout fires if the child stays silent
+    pub fn silence_deadline(&self) -> Option<Instant> {
+        let timeout = self.thresholds.silence?;
+        Some(*self.last_output.lock().unwrap() + timeout)
+    }
+
+    /// Records a usage sample, if any, and tells which threshold fired
+    pub fn check(&mut self, usage: Option<Usage>, now: Instant) -> Option<Trigger> {
+        if let Some(usage) = usage {
+            if let Some((limit, duration)) = self.thresholds.memory {
+                if usage.memory > limit {
+                    let since = *self.memory_above_since.get_or_insert(now);
+                    if now.duration_since(since) >= duration {
+                        return Some(Trigger::Memory {
+                            usage: usage.memory,
+                            limit,
+                            duration,
+                        });
+                    }
+                } else {
+                    self.memory_above_since = None;
+                }
+            }
+
+            if let Some((limit, duration)) = self.thresholds.cpu {
+                if let Some((at, cpu_time)) = self.last_sample
+                    && now > at
+                {
+                    let percent = usage.cpu_time.saturating_sub(cpu_time).as_secs_f64()
+                        / now.duration_since(at).as_secs_f64()
+                        * 100.0;
+                    if percent > limit as f64 {
+                        let since = *self.cpu_above_since.get_or_insert(at);
+                        if now.duration_since(since) >= duration {
+                            return Some(Trigger::Cpu {
+                                percent,
+                                limit,
+                                duration,
+                            });
+                        }
+                    } else {
+                        self.cpu_above_since = None;
+                    }
+                }
+                self.last_sample = Some((now, usage.cpu_time));
+            }
+        }
+
+        if let Some(timeout) = self.thresholds.silence
+            && now.duration_since(*self.last_output.lock().unwrap()) >= timeout
+        {
+            return Some(Trigger::Silence(timeout));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(memory_mib: u64, cpu_secs: u64) -> Option<Usage> {
+        Some(Usage {
+            memory: memory_mib << 20,
+            cpu_time: Duration::from_secs(cpu_secs),
+            ..Default::default()
+        })
+    }
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_memory_threshold() {
+        let start = Instant::now();
+        let mut monitor = ThresholdMonitor::new(
+            Thresholds {
+                memory: Some((100 << 20, secs(30))),
+                ..Default::default()
+            },
+            start,
+        );
+        assert_eq!(monitor.check(usage(150, 0), start), None);
+        assert_eq!(monitor.check(usage(150, 0), start + secs(20)), None);
+        // a sample below the threshold starts the count over
+        assert_eq!(monitor.check(usage(80, 0), start + secs(25)), None);
+        assert_eq!(monitor.check(usage(150, 0), start + secs(40)), None);
+        assert_eq!(
+            monitor.check(usage(160, 0), start + secs(70)),
+            Some(Trigger::Memory {
+                usage: 160 << 20,
+                limit: 100 << 20,
+                duration: secs(30),
+            })
+        );
+    }
+
+    #[test]
+    fn test_cpu_threshold() {
+        let start = Instant::now();
+        let mut monitor = ThresholdMonitor::new(
+            Thresholds {
+                cpu: Some((90, secs(60))),
+                ..Default::default()
+            },
+            start,
+        );
+        // a spinning child burns one second of CPU every second
+        assert_eq!(monitor.check(usage(10, 5), start), None);
+        assert_eq!(monitor.check(usage(10, 35), start + secs(30)), None);
+        let trigger = monitor.check(usage(10, 65), start + secs(60));
+        assert!(matches!(trigger, Some(Trigger::Cpu { limit: 90, .. })));
+        assert_eq!(
+            trigger.unwrap().to_string(),
+            "CPU threshold exceeded: 100% used, above 90% for 60s"
+        );
+
+        // an idle period starts the count over
+        monitor.reset(start);
+        assert_eq!(monitor.check(usage(10, 0), start), None);
+        assert_eq!(monitor.check(usage(10, 30), start + secs(30)), None);
+        assert_eq!(monitor.check(usage(10, 31), start + secs(40)), None);
+        assert_eq!(monitor.check(usage(10, 81), start + secs(90)), None);
+    }
+
+    #[test]
+    fn test_silence_timeout() {
+        let start = Instant::now();
+        let mut monitor = ThresholdMonitor::new(
+            Thresholds {
+                silence: Some(secs(600)),
+                ..Default::default()
+            },
+            start,
+        );
+        let observer = monitor.output_observer().unwrap();
+        assert_eq!(monitor.silence_deadline(), Some(start + secs(600)));
+        assert_eq!(monitor.check(None, start + secs(599)), None);
+
+        observer("still alive");
+        let now = Instant::now();
+        assert_eq!(monitor.check(None, now + secs(599)), None);
+        assert_eq!(
+            monitor.check(None, now + secs(600)),
+            Some(Trigger::Silence(secs(600)))
+        );
+    }
+
+    #[test]
+    fn test_no_thresholds() {
+        let start = Instant::now();
+        let mut monitor = ThresholdMonitor::new(Thresholds::default(), start);
+        assert!(monitor.output_observer().is_none());
+        assert_eq!(monitor.silence_deadline(), None);
+        assert_eq!(monitor.check(usage(4096, 100), start + secs(3600)), None);
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::pkg::events::Waker;
+
+/// How often the watched paths are scanned
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A file, a directory (watched recursively) or a glob pattern to watch.
+/// Globs support `*` and `?` within a path component and `**` for any
+/// number of directories, e.g. `conf/**/*.toml`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchTarget {
+    /// Directory or file to scan
+    base: PathBuf,
+    /// Pattern matched against the paths relative to `base`
+    pattern: Option<Vec<String>>,
+}
+
+impl WatchTarget {
+    /// Builds a target from a path or a glob. Relative paths are resolved
+    /// against `working_dir`
+    pub fn new(path: &str, working_dir: &Path) -> Self {
+        let path = working_dir.join(path);
+        let mut base = PathBuf::new();
+        let mut pattern = Vec::new();
+        for component in path.components() {
+            let part = component.as_os_str().to_string_lossy();
+            if pattern.is_empty() && !is_glob(&part) {
+                base.push(component);
+            } else {
+                pattern.push(part.to_string());
+            }
+        }
+        WatchTarget {
+            base,
+            pattern: if pattern.is_empty() {
+                None
+            } else {
+                Some(pattern)
+            },
+        }
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let Some(pattern) = &self.pattern else {
+            return true;
+        };
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let parts: Vec<String> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        match_components(pattern, &parts)
+    }
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?'])
+}
+
+/// Matches path components against pattern components, `**` matching any
+/// number of components
+fn match_components(pattern: &[String], parts: &[String]) -> bool {
+    match pattern.first() {
+        None => parts.is_empty(),
+        Some(p) if p == "**" => {
+            (0..=parts.len()).any(|skip| match_components(&pattern[1..], &parts[skip..]))
+        }
+        Some(p) => {
+            !parts.is_empty()
+                && match_wildcard(p.as_bytes(), parts[0].as_bytes())
+                && match_components(&pattern[1..], &parts[1..])
+        }
+    }
+}
+
+/// Matches a single name against a pattern with `*` and `?`
+fn match_wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            match_wildcard(&pattern[1..], name)
+                || (!name.is_empty() && match_wildcard(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => match_wildcard(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p.eq_ignore_ascii_case(n) => {
+            match_wildcard(&pattern[1..], &name[1..])
+        }
+        _ => false,
+    }
+}
+
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Detects changes of the watched files by comparing their modification
+/// time and size between scans
+pub struct FileWatcher {
+    targets: Vec<WatchTarget>,
+    ignored: Vec<PathBuf>,
+    debounce: Duration,
+    snapshot: Snapshot,
+    changed_at: Option<Instant>,
+}
+
+impl FileWatcher {
+    /// Files under the `ignored` directories never trigger a change
+    pub fn new(targets: Vec<WatchTarget>, ignored: Vec<PathBuf>, debounce: Duration) -> Self {
+        let mut watcher = FileWatcher {
+            targets,
+            ignored,
+            debounce,
+            snapshot: Snapshot::new(),
+            changed_at: None,
+        };
+        watcher.snapshot = watcher.scan();
+        watcher
+    }
+
+    /// Scans the watched paths. Returns true when a change has been detected
+    /// and no further changes happened for the debounce interval
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let snapshot = self.scan();
+        if snapshot != self.snapshot {
+            self.snapshot = snapshot;
+            self.changed_at = Some(now);
+            return false;
+        }
+        match self.changed_at {
+            Some(changed_at) if now.duration_since(changed_at) >= self.debounce => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn scan(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for target in &self.targets {
+            self.scan_path(target, &target.base, &mut snapshot);
+        }
+        snapshot
+    }
+
+    fn scan_path(&self, target: &WatchTarget, path: &Path, snapshot: &mut Snapshot) {
+        if self.ignored.iter().any(|ignored| path.starts_with(ignored)) {
+            return;
+        }
+        let Ok(metadata) = fs::metadata(path) else {
+            return;
+        };
+        if metadata.is_dir() {
+            let Ok(entries) = fs::read_dir(path) else {
+                return;
+            };
+            for entry in entries.flatten() {
+                self.scan_path(target, &entry.path(), snapshot);
+            }
+        } else if target.matches(path) {
+            snapshot.insert(
+                path.to_path_buf(),
+                (metadata.modified().ok(), metadata.len()),
+            );
+        }
+    }
+}
+
+/// Runs a `FileWatcher` on a background thread, calling `wake` on changes
+pub struct WatchMonitor {
+    changed: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl WatchMonitor {
+    pub fn start(mut watcher: FileWatcher, wake: Waker) -> Self {
+        let changed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let changed_bg = changed.clone();
+        let stop_bg = stop.clone();
+        thread::spawn(move || {
+            while !stop_bg.load(Ordering::SeqCst) {
+                thread::sleep(SCAN_INTERVAL);
+                if watcher.poll(Instant::now()) {
+                    info!("Watched files changed");
+                    changed_bg.store(true, Ordering::SeqCst);
+                    wake();
+                }
+            }
+        });
+
+        WatchMonitor { changed, stop }
+    }
+
+    /// Returns true if the watched files changed since the last call
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for WatchMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Builds the watch targets, warning about the paths that do not exist yet
+pub fn watch_targets(paths: &[String], working_dir: &Path) -> Vec<WatchTarget> {
+    paths
+        .iter()
+        .map(|path| {
+            let target = WatchTarget::new(path, working_dir);
+            if !target.base.exists() {
+                warn!("Watched path {:?} does not exist", target.base);
+            }
+            target
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("wsw-watch-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn s(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_match_wildcard() {
+        assert!(match_wildcard(b"*.toml", b"app.toml"));
+        assert!(match_wildcard(b"*.TOML", b"app.toml"));
+        assert!(match_wildcard(b"app?.exe", b"app2.exe"));
+        assert!(!match_wildcard(b"*.toml", b"app.yaml"));
+        assert!(!match_wildcard(b"app?.exe", b"app.exe"));
+    }
+
+    #[test]
+    fn test_match_components() {
+        assert!(match_components(&s(&["**", "*.toml"]), &s(&["app.toml"])));
+        assert!(match_components(
+            &s(&["**", "*.toml"]),
+            &s(&["a", "b", "app.toml"])
+        ));
+        assert!(match_components(
+            &s(&["conf", "*.toml"]),
+            &s(&["conf", "app.toml"])
+        ));
+        assert!(!match_components(
+            &s(&["conf", "*.toml"]),
+            &s(&["conf", "x", "app.toml"])
+        ));
+    }
+
+    #[test]
+    fn test_target_split() {
+        let target = WatchTarget::new("conf/**/*.toml", Path::new("/srv/app"));
+        assert_eq!(target.base, PathBuf::from("/srv/app/conf"));
+        assert_eq!(target.pattern, Some(s(&["**", "*.toml"])));
+
+        let target = WatchTarget::new("/srv/app/app.exe", Path::new("/other"));
+        assert_eq!(target.base, PathBuf::from("/srv/app/app.exe"));
+        assert_eq!(target.pattern, None);
+    }
+
+    #[test]
+    fn test_change_is_debounced() {
+        let dir = TempDir::new("debounce");
+        let file = dir.0.join("app.toml");
+        fs::write(&file, "a").unwrap();
+
+        let mut watcher = FileWatcher::new(
+            vec![WatchTarget::new("*.toml", &dir.0)],
+            vec![],
+            Duration::from_secs(2),
+        );
+        let now = Instant::now();
+        assert!(!watcher.poll(now));
+
+        fs::write(&file, "bb").unwrap();
+        assert!(!watcher.poll(now));
+        // still settling
+        assert!(!watcher.poll(now + Duration::from_secs(1)));
+        assert!(watcher.poll(now + Duration::from_secs(2)));
+        // reported only once
+        assert!(!watcher.poll(now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_new_and_removed_files() {
+        let dir = TempDir::new("create");
+        let mut watcher =
+            FileWatcher::new(vec![WatchTarget::new(".", &dir.0)], vec![], Duration::ZERO);
+        let now = Instant::now();
+
+        fs::create_dir_all(dir.0.join("sub")).unwrap();
+        fs::write(dir.0.join("sub").join("new.txt"), "x").unwrap();
+        assert!(!watcher.poll(now));
+        assert!(watcher.poll(now));
+
+        fs::remove_file(dir.0.join("sub").join("new.txt")).unwrap();
+        assert!(!watcher.poll(now));
+        assert!(watcher.poll(now));
+    }
+
+    #[test]
+    fn test_ignored_and_unmatched_files() {
+        let dir = TempDir::new("ignored");
+        let logs = dir.0.join("logs");
+        fs::create_dir_all(&logs).unwrap();
+        let now = Instant::now();
+
+        let mut watcher = FileWatcher::new(
+            vec![WatchTarget::new(".", &dir.0)],
+            vec![logs.clone()],
+            Duration::ZERO,
+        );
+        fs::write(logs.join("svc.log"), "line").unwrap();
+        assert!(!watcher.poll(now));
+        assert!(!watcher.poll(now));
+
+        let mut watcher = FileWatcher::new(
+            vec![WatchTarget::new("*.toml", &dir.0)],
+            vec![],
+            Duration::ZERO,
+        );
+        fs::write(dir.0.join("notes.txt"), "x").unwrap();
+        assert!(!watcher.poll(now));
+        assert!(!watcher.poll(now));
+    }
+}